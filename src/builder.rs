@@ -1,155 +1,5599 @@
-use std::collections::{HashMap, HashSet};
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::process::Command;
+use std::time::{Duration, Instant};
 use regex::Regex;
+use crate::config::{self, AutoIndex, Config, FileHandler, MissingContent, PassOrder, Redirect, Robots, Value, CONFIG_FILE};
+use crate::css;
+use crate::directives::{self, Directives, COMPONENT_TAG, CONTENT_TAG, LAYOUT_TAG, RAW_TAG};
+use crate::encoding;
+use crate::error::BuildError;
+use crate::frontmatter::{self, FrontMatter};
+use crate::html;
+use crate::log;
+use crate::remote;
+use crate::sourcefs::{DiskFs, SourceFs};
+use crate::sourcemap;
+use crate::variables;
 
 pub mod site_builder {
     use super::*;
 
+    const LAYOUT_MARKER: &str = "_layout.html";
+    // Reserved layout name for pages that must not be wrapped, e.g. HTMX partials.
+    const NO_LAYOUT: &str = "none";
+    const I18N_DIR: &str = "_i18n";
+    const DEFAULT_INDEX_FILE: &str = "index.html";
+    const DEFAULT_IGNORED_FILES: &[&str] = &[".DS_Store", "Thumbs.db", "desktop.ini"];
+    const DEFAULT_IGNORED_EXTENSIONS: &[&str] = &["swp", "swo"];
+    // Smaller expansions cost less than the placeholder and script that would replace them.
+    const DEDUPE_MIN_LEN: usize = 200;
+    const CATALOG_STYLE: &str = "body{font-family:sans-serif;margin:2rem}section{border-top:1px solid #ccc;padding:1rem 0}.el-catalog-example{padding:1rem;border:1px dashed #aaa}";
+    const DEDUPE_SCRIPT: &str = r#"<script>document.querySelectorAll("template[data-el-use]").forEach(function(t){t.replaceWith(document.getElementById(t.dataset.elUse).content.cloneNode(true));});</script>"#;
+
+    #[derive(Debug, Default, Clone)]
+    pub struct BuildReport {
+        pub written: Vec<PathBuf>,
+        pub timing: Timing,
+        // Everything written, page and asset bytes alike.
+        pub bytes_written: u64,
+        // How much larger the output would have been without comment stripping, component
+        // deduplication and asset pruning. Negative when they cost more than they saved.
+        pub bytes_saved: i64,
+        // Outputs an incremental build kept from the previous build instead of rebuilding.
+        pub reused: Vec<PathBuf>,
+    }
+
+    // A page output as of the last incremental build, fingerprinted by the content of
+    // everything it was built from.
+    #[derive(Debug, Clone)]
+    struct IndexedOutput {
+        output: PathBuf,
+        fingerprint: String,
+        dependencies: Vec<PathBuf>,
+    }
+
+    // Keyed by page and the output it was asked for, before permalinks and path mapping.
+    type HashIndex = HashMap<(PathBuf, PathBuf), IndexedOutput>;
+
+    // Where a build spent its time. Component and layout totals cover the top-level expansion
+    // of each page, nested components included; io covers writing the flattened output.
+    #[derive(Debug, Default, Clone)]
+    pub struct Timing {
+        pub pages: Vec<(PathBuf, Duration)>,
+        pub components: Duration,
+        pub layouts: Duration,
+        pub io: Duration,
+    }
+
+    impl Timing {
+        pub fn slowest(&self, count: usize) -> Vec<&(PathBuf, Duration)> {
+            let mut pages: Vec<_> = self.pages.iter().collect();
+            pages.sort_by_key(|page| std::cmp::Reverse(page.1));
+            pages.truncate(count);
+            pages
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct MissingReference {
+        pub file: PathBuf,
+        pub name: String,
+        pub expected: PathBuf,
+    }
+
+    // Expanded components keyed by canonical source path, shareable between builders whose
+    // sites use the same component files.
+    pub type SharedCache = Arc<Mutex<HashMap<PathBuf, String>>>;
+
+    // Library hook run on every flattened page, e.g. to add nonce attributes.
+    pub type Transform = Box<dyn FnMut(&Path, String) -> String>;
+
+    // What a custom directive handler is given besides the tag's attributes.
+    pub struct DirectiveContext<'a> {
+        // The page being built, relative to src_dir.
+        pub page: &'a Path,
+        pub variables: &'a BTreeMap<String, String>,
+        // What the tag encloses; None when it closes itself.
+        pub content: Option<&'a str>,
+    }
+
+    // Library hook rendering a custom directive tag; the result replaces the tag.
+    pub type DirectiveHandler = Box<dyn Fn(&[(&str, &str)], &DirectiveContext) -> String>;
+
+    // What build_with_progress reports as the build proceeds.
+    #[derive(Debug, Clone)]
+    pub enum BuildEvent {
+        Started,
+        // A source file was flattened or copied; the path is relative to src_dir.
+        FileProcessed { path: PathBuf },
+        Warning { message: String },
+        Finished { report: BuildReport },
+    }
+
+    // Library hook receiving build events, e.g. to show progress in a host application.
+    pub type ProgressHandler = Box<dyn FnMut(BuildEvent)>;
+
+    // Library hook choosing where an output goes, given the path it would otherwise get
+    // relative to dest_dir.
+    pub type PathMapper = Box<dyn Fn(&Path) -> PathBuf>;
+
+    // Which directives a site uses and which component and layout names it references.
+    #[derive(Debug, Default, Clone, PartialEq)]
+    pub struct DirectiveUsage {
+        pub tags: BTreeMap<String, usize>,
+        pub components: BTreeSet<String>,
+        pub layouts: BTreeSet<String>,
+    }
+
+    // A TODO or FIXME comment left in a source file.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct TodoMarker {
+        pub file: PathBuf,
+        pub line: usize,
+        pub marker: String,
+        pub note: String,
+    }
+
+    // An asset copy queued for the worker pool.
+    struct PendingCopy {
+        asset: PathBuf,
+        src_path: PathBuf,
+        write_path: PathBuf,
+        previous: PathBuf,
+    }
+
     pub struct SiteBuilder {
+        base_dir: PathBuf,
         dest_dir: PathBuf,
+        staging_dir: Option<PathBuf>,
         src_dir: PathBuf,
         components_dir: String,
         layout_dir: String,
-        cache: HashMap<PathBuf, String>,
-        component_regex: Regex,
-        layout_regex: Regex,
-        layout_content_regex: Regex,
+        cache: SharedCache,
+        front_matter: HashMap<PathBuf, FrontMatter>,
+        layout_variables: HashMap<PathBuf, BTreeMap<String, String>>,
+        reading_times: HashMap<PathBuf, usize>,
+        layout_chain: Vec<String>,
+        trim_directives: bool,
+        robots: Option<Robots>,
+        redirects: Vec<Redirect>,
+        page_redirects: Vec<Redirect>,
+        auto_index: Option<AutoIndex>,
+        page_titles: BTreeMap<PathBuf, (PathBuf, String)>,
+        index_variables: BTreeMap<String, String>,
+        only: Option<PathBuf>,
+        path_mapper: Option<PathMapper>,
+        source_fs: Box<dyn SourceFs>,
+        locales: Vec<String>,
+        default_locale: Option<String>,
+        locale: Option<String>,
+        keep_going: bool,
+        errors_reported: Cell<usize>,
+        outputs: HashMap<PathBuf, PathBuf>,
+        use_cache: bool,
+        aliases: HashMap<String, String>,
+        // Directories relative to src_dir, by the name that selects them, e.g. "ui" for @ui/.
+        component_roots: HashMap<String, String>,
+        handlers: HashMap<String, FileHandler>,
+        global_components: HashMap<String, String>,
+        component_index: OnceCell<HashMap<String, Vec<String>>>,
+        error_pages: Vec<PathBuf>,
+        verbatim_dirs: Vec<PathBuf>,
+        static_dir: Option<PathBuf>,
+        ignored_files: HashSet<String>,
+        ignored_extensions: HashSet<String>,
+        allowed_output_extensions: Vec<String>,
+        directives: Directives,
+        content_tag: String,
+        comment_regex: Regex,
+        strip_comments: bool,
+        strict: bool,
+        enable_ssi: bool,
+        post_build: Option<String>,
+        progress: bool,
+        dry_run: bool,
+        reject_unknown_directives: bool,
+        validate_references: bool,
+        warn_duplicate_content: bool,
+        warn_fragments: bool,
+        fragment_pages: Vec<PathBuf>,
+        included_files: HashSet<PathBuf>,
+        critical_css: Option<PathBuf>,
+        critical_css_content: Option<String>,
+        pages: Vec<PathBuf>,
+        missing_content: MissingContent,
+        missing_component: Option<String>,
+        version_meta: bool,
+        build_id: Option<String>,
+        pass_order: PassOrder,
+        legacy_layout: bool,
+        transforms: Vec<Transform>,
+        postprocessors: HashMap<String, Vec<Transform>>,
+        custom_directives: Vec<(Regex, DirectiveHandler)>,
+        progress_handler: Option<RefCell<ProgressHandler>>,
+        skip_unchanged_assets: bool,
+        fsync: bool,
+        copy_jobs: usize,
+        pending_copies: Vec<PendingCopy>,
+        mount: Option<String>,
+        dedupe_components: bool,
+        shared_components: Option<Vec<String>>,
+        warn_empty: bool,
+        build_time_format: String,
+        variables: BTreeMap<String, String>,
+        defines: BTreeMap<String, String>,
+        validate_html: bool,
+        a11y_lint: bool,
+        encoding_lint: bool,
+        check_links: bool,
+        drafts: bool,
+        nojekyll: bool,
+        relative_links: bool,
+        env: Option<String>,
+        trace: Option<Vec<String>>,
+        // Each <el-link> target: the page, the link as written and the outputs that satisfy it.
+        links: Vec<(PathBuf, String, Vec<PathBuf>)>,
+        memory_output: Option<HashMap<PathBuf, Vec<u8>>>,
+        scoped_styles: bool,
+        read_buffer: String,
+        // Expansions of pure components, kept for one build regardless of use_cache.
+        pure_components: HashMap<PathBuf, String>,
+        dependencies: HashSet<PathBuf>,
+        component_dependencies: HashMap<PathBuf, HashSet<PathBuf>>,
+        page_dependencies: HashMap<PathBuf, HashSet<PathBuf>>,
+        // Like dependencies, but every expansion of a component or layout, repeats included.
+        invocations: Vec<PathBuf>,
+        component_invocations: HashMap<PathBuf, Vec<PathBuf>>,
+        // How often each component and layout was expanded and by which pages.
+        usage: Option<BTreeMap<PathBuf, (usize, BTreeSet<PathBuf>)>>,
+        incremental: bool,
+        previous_hash_index: HashIndex,
+        hash_index: Option<HashIndex>,
+        file_hashes: HashMap<PathBuf, [u8; 48]>,
+        config_fingerprint: String,
+        reused: Vec<PathBuf>,
+        index_file: String,
+        lint: bool,
+        max_file_size: Option<u64>,
+        source_map: bool,
+        source_map_sources: Vec<String>,
+        bundle: bool,
+        integrity: bool,
+        csp_nonce: bool,
+        prune_unused_assets: bool,
+        deferred_assets: Vec<PathBuf>,
+        referenced_assets: HashSet<PathBuf>,
+        allow_remote: bool,
+        remote_timeout: Duration,
+        no_clean: bool,
+        wait_for_lock: bool,
+        bytes_written: u64,
+        bytes_saved: i64,
+        timing: Timing,
     }
 
     impl SiteBuilder {
         pub fn new(base_dir: PathBuf) -> Self {
             SiteBuilder {
                 dest_dir: base_dir.join("build"),
+                staging_dir: None,
                 src_dir: base_dir.join("src"),
                 components_dir: "el-components".to_string(),
                 layout_dir: "el-layouts".to_string(),
-                cache: HashMap::new(),
-                component_regex: Regex::new(r#"<el-component\s+name="([^"]*)"\s*>(.*?)</el-component>"#).unwrap(),
-                layout_regex: Regex::new(r#"<el-layout\s+name="([^"]*)"\s*>(.*?)</el-layout>"#).unwrap(),
-                layout_content_regex: Regex::new(r#"<el-content\s*/>"#).unwrap(),
+                cache: SharedCache::default(),
+                front_matter: HashMap::new(),
+                layout_variables: HashMap::new(),
+                reading_times: HashMap::new(),
+                layout_chain: Vec::new(),
+                trim_directives: false,
+                robots: None,
+                redirects: Vec::new(),
+                page_redirects: Vec::new(),
+                auto_index: None,
+                page_titles: BTreeMap::new(),
+                index_variables: BTreeMap::new(),
+                only: None,
+                path_mapper: None,
+                source_fs: Box::new(DiskFs),
+                locales: Vec::new(),
+                default_locale: None,
+                locale: None,
+                keep_going: false,
+                errors_reported: Cell::new(0),
+                outputs: HashMap::new(),
+                use_cache: true,
+                aliases: HashMap::new(),
+                component_roots: HashMap::new(),
+                handlers: HashMap::from([("html".to_string(), FileHandler::Flatten)]),
+                global_components: HashMap::new(),
+                component_index: OnceCell::new(),
+                error_pages: Vec::new(),
+                verbatim_dirs: Vec::new(),
+                static_dir: None,
+                ignored_files: DEFAULT_IGNORED_FILES.iter().map(|s| s.to_string()).collect(),
+                ignored_extensions: DEFAULT_IGNORED_EXTENSIONS.iter().map(|s| s.to_string()).collect(),
+                allowed_output_extensions: Vec::new(),
+                directives: Directives::default(),
+                content_tag: CONTENT_TAG.to_string(),
+                comment_regex: Regex::new(r#"(?s)<pre\b.*?</pre>|<el-raw\b.*?</el-raw>|<!--.*?-->"#).unwrap(),
+                strip_comments: false,
+                strict: false,
+                enable_ssi: false,
+                post_build: None,
+                progress: true,
+                dry_run: false,
+                reject_unknown_directives: false,
+                validate_references: false,
+                warn_duplicate_content: false,
+                warn_fragments: false,
+                fragment_pages: Vec::new(),
+                included_files: HashSet::new(),
+                critical_css: None,
+                critical_css_content: None,
+                pages: Vec::new(),
+                missing_content: MissingContent::default(),
+                missing_component: None,
+                version_meta: false,
+                build_id: None,
+                pass_order: PassOrder::default(),
+                legacy_layout: false,
+                transforms: Vec::new(),
+                postprocessors: HashMap::new(),
+                custom_directives: Vec::new(),
+                progress_handler: None,
+                skip_unchanged_assets: false,
+                fsync: false,
+                copy_jobs: 1,
+                pending_copies: Vec::new(),
+                mount: None,
+                dedupe_components: false,
+                shared_components: None,
+                warn_empty: false,
+                build_time_format: variables::DEFAULT_TIME_FORMAT.to_string(),
+                variables: BTreeMap::new(),
+                defines: BTreeMap::new(),
+                validate_html: false,
+                a11y_lint: false,
+                encoding_lint: false,
+                check_links: false,
+                drafts: false,
+                nojekyll: false,
+                relative_links: false,
+                env: None,
+                trace: None,
+                links: Vec::new(),
+                memory_output: None,
+                scoped_styles: false,
+                read_buffer: String::new(),
+                pure_components: HashMap::new(),
+                dependencies: HashSet::new(),
+                component_dependencies: HashMap::new(),
+                page_dependencies: HashMap::new(),
+                invocations: Vec::new(),
+                component_invocations: HashMap::new(),
+                usage: None,
+                incremental: false,
+                previous_hash_index: HashMap::new(),
+                hash_index: None,
+                file_hashes: HashMap::new(),
+                config_fingerprint: String::new(),
+                reused: Vec::new(),
+                index_file: DEFAULT_INDEX_FILE.to_string(),
+                lint: false,
+                max_file_size: None,
+                source_map: false,
+                source_map_sources: Vec::new(),
+                bundle: false,
+                integrity: false,
+                csp_nonce: false,
+                prune_unused_assets: false,
+                deferred_assets: Vec::new(),
+                referenced_assets: HashSet::new(),
+                allow_remote: false,
+                remote_timeout: remote::DEFAULT_TIMEOUT,
+                no_clean: false,
+                wait_for_lock: false,
+                bytes_written: 0,
+                bytes_saved: 0,
+                timing: Timing::default(),
+                base_dir,
             }
         }
 
-        fn flatten_file(&mut self, file: &Path) {
-            let mut processing = HashSet::new();
-            let result = self.replace_components(file, &mut processing);
-            let result = self.replace_layout(&result);
-            let dest_path = self.dest_dir.join(file);
-            if let Some(parent) = dest_path.parent() {
-                if let Err(e) = fs::create_dir_all(parent) {
-                    eprintln!("Failed to create directory: {}", e);
-                    return;
+        pub fn from_config(base_dir: PathBuf, config: &Config) -> Self {
+            let mut site_builder = SiteBuilder::new(base_dir.clone());
+            if let Some(dest_dir) = &config.dest_dir {
+                site_builder = site_builder.with_dest_dir(base_dir.join(dest_dir));
+            }
+            site_builder
+                .with_aliases(config.aliases.clone())
+                .with_component_roots(config.component_roots.clone())
+                .with_handlers(config.handlers.clone())
+                .with_global_components(&config.global_components)
+                .with_error_pages(config.error_pages.iter().map(PathBuf::from).collect())
+                .with_verbatim_dirs(config.verbatim.iter().map(PathBuf::from).collect())
+                .with_static_dir(config.static_dir.as_ref().map(PathBuf::from))
+                .with_ignored(&config.ignore_files, &config.ignore_extensions)
+                .with_allowed_output_extensions(&config.allowed_output_extensions)
+                .with_post_build(config.post_build.clone())
+                .with_critical_css(config.critical_css.as_ref().map(PathBuf::from))
+                .with_missing_content(config.missing_content)
+                .with_missing_component(config.missing_component.clone())
+                .with_content_tag(config.content_tag.clone())
+                .with_version_meta(config.version_meta)
+                .with_fsync(config.fsync)
+                .with_pass_order(config.pass_order)
+                .with_locales(config.locales.clone(), config.default_locale.clone())
+                .with_build_time_format(config.build_time_format.clone())
+                .with_index_file(config.index_file.clone())
+                .with_robots(config.robots.clone())
+                .with_redirects(config.redirects.clone())
+                .with_auto_index(config.auto_index.clone())
+        }
+
+        // Global components can be referenced by their bare file name from any page,
+        // e.g. "icons/star" is available as <el-component name="star">.
+        pub fn with_global_components(mut self, components: &[String]) -> Self {
+            for component in components {
+                let component = component.trim_end_matches(".html");
+                let name = component.rsplit('/').next().unwrap_or(component).to_string();
+                if let Some(existing) = self.global_components.get(&name) {
+                    self.warn(&format!("global component [{}] is shadowed by [{}]", component, existing));
+                    continue;
                 }
+                self.global_components.insert(name, component.to_string());
+            }
+            self
+        }
+
+        pub fn with_dest_dir(mut self, dest_dir: PathBuf) -> Self {
+            self.dest_dir = dest_dir;
+            self
+        }
+
+        // Keeps dest_dir inside base_dir but renames its last segment, e.g. "dist".
+        pub fn with_output_name(mut self, name: &str) -> Self {
+            self.dest_dir = self.base_dir.join(name);
+            self
+        }
+
+        // Decides by extension which files are flattened as pages and which are copied. These
+        // are added to, and override, the default of flattening html.
+        pub fn with_handlers(mut self, handlers: HashMap<String, FileHandler>) -> Self {
+            self.handlers.extend(handlers);
+            self
+        }
+
+        pub fn with_aliases(mut self, aliases: HashMap<String, String>) -> Self {
+            self.aliases = aliases;
+            self
+        }
+
+        // Makes <el-component name="@ui/button" /> resolve to button.html in the directory
+        // roots["ui"], given relative to base_dir, e.g. a vendored component library.
+        pub fn with_component_roots(mut self, roots: HashMap<String, String>) -> Self {
+            self.component_roots = roots.into_iter()
+                .map(|(root, dir)| (root, format!("../{}", dir.trim_matches('/'))))
+                .collect();
+            self
+        }
+
+        // Error page templates are paths relative to src_dir. Each one is built into the root
+        // of dest_dir under its own file name, even when it lives outside the walked tree.
+        pub fn with_error_pages(mut self, error_pages: Vec<PathBuf>) -> Self {
+            self.error_pages = error_pages;
+            self
+        }
+
+        // HTML files under these directories (relative to src_dir) are copied as they are,
+        // like any other asset, instead of being flattened.
+        pub fn with_verbatim_dirs(mut self, verbatim_dirs: Vec<PathBuf>) -> Self {
+            self.verbatim_dirs = verbatim_dirs;
+            self
+        }
+
+        // Everything under this directory (relative to src_dir) is copied as it is to the root
+        // of dest_dir, e.g. src/static/robots.txt to robots.txt. Generated pages win over
+        // static files that would land on the same output.
+        pub fn with_static_dir(mut self, static_dir: Option<PathBuf>) -> Self {
+            self.static_dir = static_dir;
+            self
+        }
+
+        // Adds to the default junk list; extensions are given without the leading dot.
+        pub fn with_ignored(mut self, files: &[String], extensions: &[String]) -> Self {
+            self.ignored_files.extend(files.iter().cloned());
+            self.ignored_extensions.extend(extensions.iter().map(|ext| ext.trim_start_matches('.').to_string()));
+            self
+        }
+
+        // Fails the build when a page or asset would be written with any other extension,
+        // after permalinks and path mapping. Empty allows every extension.
+        pub fn with_allowed_output_extensions(mut self, extensions: &[String]) -> Self {
+            self.allowed_output_extensions = extensions.iter().map(|ext| ext.trim_start_matches('.').to_ascii_lowercase()).collect();
+            self
+        }
+
+        pub fn with_post_build(mut self, command: Option<String>) -> Self {
+            self.post_build = command;
+            self
+        }
+
+        pub fn with_progress(mut self, progress: bool) -> Self {
+            self.progress = progress;
+            self
+        }
+
+        pub fn with_warn_fragments(mut self, warn_fragments: bool) -> Self {
+            self.warn_fragments = warn_fragments;
+            self
+        }
+
+        // The critical stylesheet is a path relative to src_dir.
+        pub fn with_critical_css(mut self, critical_css: Option<PathBuf>) -> Self {
+            self.critical_css = critical_css;
+            self
+        }
+
+        // Drops the blank lines directive tags leave behind when they sit on a line of their own.
+        pub fn with_trim_directives(mut self, trim_directives: bool) -> Self {
+            self.trim_directives = trim_directives;
+            self
+        }
+
+        // Writes a robots.txt into dest_dir from the given rules, allowing everything when
+        // there are none, plus a Sitemap line when a sitemap URL is set.
+        pub fn with_robots(mut self, robots: Option<Robots>) -> Self {
+            self.robots = robots;
+            self
+        }
+
+        // Writes a _redirects file into dest_dir, as read by Netlify and Cloudflare Pages, from
+        // these rules followed by the redirect_from front matter of every page built.
+        pub fn with_redirects(mut self, redirects: Vec<Redirect>) -> Self {
+            self.redirects = redirects;
+            self
+        }
+
+        // Builds an index page for each listed directory that has none, from a template that
+        // sees {{ index.dir }}, {{ index.count }} and {{ index.entries }}, a <ul> linking every
+        // page in the directory by its title. Only full builds generate these.
+        pub fn with_auto_index(mut self, auto_index: Option<AutoIndex>) -> Self {
+            self.auto_index = auto_index;
+            self
+        }
+
+        // Builds only the pages and assets under one directory of src_dir, still resolving
+        // components and layouts from their usual directories. Like with_no_clean, the rest of
+        // dest_dir is left alone.
+        pub fn with_only(mut self, only: Option<PathBuf>) -> Self {
+            self.only = only.map(|only| only.strip_prefix(&self.src_dir).map(Path::to_path_buf).unwrap_or(only));
+            self
+        }
+
+        // Writes straight into an existing dest_dir instead of replacing it, so files elss does
+        // not produce survive. Outputs of pages since removed from the source also survive, and
+        // a failed build leaves dest_dir partly updated.
+        pub fn with_no_clean(mut self, no_clean: bool) -> Self {
+            self.no_clean = no_clean;
+            self
+        }
+
+        // Builds lock their output directory. By default a second build fails at once while
+        // another holds the lock; with this set it waits for the lock instead.
+        pub fn with_wait_for_lock(mut self, wait_for_lock: bool) -> Self {
+            self.wait_for_lock = wait_for_lock;
+            self
+        }
+
+        // Runs the whole pipeline but writes nothing and skips the post-build command.
+        pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+            self.dry_run = dry_run;
+            self
+        }
+
+        // Treats any <el-*> tag left in a flattened page as an error.
+        pub fn with_unknown_directive_check(mut self, reject_unknown_directives: bool) -> Self {
+            self.reject_unknown_directives = reject_unknown_directives;
+            self
+        }
+
+        // Checks every component and layout reference before building anything.
+        pub fn with_reference_validation(mut self, validate_references: bool) -> Self {
+            self.validate_references = validate_references;
+            self
+        }
+
+        pub fn with_warn_duplicate_content(mut self, warn_duplicate_content: bool) -> Self {
+            self.warn_duplicate_content = warn_duplicate_content;
+            self
+        }
+
+        // Restricts the build to these pages (relative to src_dir) and whatever they reference;
+        // no other sources are walked or copied.
+        pub fn with_pages(mut self, pages: Vec<PathBuf>) -> Self {
+            self.pages = pages;
+            self
+        }
+
+        pub fn with_missing_content(mut self, missing_content: MissingContent) -> Self {
+            self.missing_content = missing_content;
+            self
+        }
+
+        // Outside strict mode a component that doesn't exist is replaced with this markup,
+        // with {{ name }} standing for the name it was asked for, instead of nothing.
+        pub fn with_missing_component(mut self, placeholder: Option<String>) -> Self {
+            self.missing_component = placeholder;
+            self
+        }
+
+        // The tag layouts mark the page body with, <el-content /> unless set.
+        pub fn with_content_tag(mut self, tag: Option<String>) -> Self {
+            self.content_tag = tag.unwrap_or_else(|| CONTENT_TAG.to_string());
+            self.directives = Directives::new(COMPONENT_TAG, LAYOUT_TAG, &self.content_tag);
+            self
+        }
+
+        // Adds <meta name="generator"> naming this elss version and <meta name="build-id"> to
+        // the head of every page. The build id defaults to the site's git revision; without
+        // one only the generator is added.
+        pub fn with_version_meta(mut self, version_meta: bool) -> Self {
+            self.version_meta = version_meta;
+            self
+        }
+
+        pub fn with_build_id(mut self, build_id: Option<String>) -> Self {
+            self.build_id = build_id;
+            self
+        }
+
+        // Flushes every written page and asset to disk before moving on, trading build speed
+        // for outputs that survive a crash right after the build.
+        pub fn with_fsync(mut self, fsync: bool) -> Self {
+            self.fsync = fsync;
+            self
+        }
+
+        // Writes usage.json to base_dir after each build, giving for every component and
+        // layout the number of times it was expanded and the pages that use it. Pages an
+        // incremental build keeps are not counted.
+        pub fn with_usage_report(mut self, usage_report: bool) -> Self {
+            self.usage = usage_report.then(BTreeMap::new);
+            self
+        }
+
+        // Builds the site once per locale into dest_dir/<locale>, with {{ t.key }} taken from
+        // _i18n/<locale>.yaml next to src. Keys missing there fall back to the default locale.
+        pub fn with_locales(mut self, locales: Vec<String>, default_locale: Option<String>) -> Self {
+            self.locales = locales;
+            self.default_locale = default_locale;
+            self
+        }
+
+        // Outside strict mode, makes a build that reported any problem fail once every file has
+        // been processed, so one run shows them all.
+        pub fn with_keep_going(mut self, keep_going: bool) -> Self {
+            self.keep_going = keep_going;
+            self
+        }
+
+        pub fn with_pass_order(mut self, pass_order: PassOrder) -> Self {
+            self.pass_order = pass_order;
+            self
+        }
+
+        // Lays pages out the way the first releases did, see replace_layout_legacy, for sites
+        // that rely on it.
+        pub fn with_legacy_layout(mut self, legacy_layout: bool) -> Self {
+            self.legacy_layout = legacy_layout;
+            self
+        }
+
+        // Remaps every page and asset output, e.g. to add a locale prefix. Mapped paths must
+        // stay inside dest_dir.
+        pub fn with_path_mapper<F>(mut self, mapper: F) -> Self
+        where
+            F: Fn(&Path) -> PathBuf + 'static,
+        {
+            self.path_mapper = Some(Box::new(mapper));
+            self
+        }
+
+        // Reads sources through fs instead of the real filesystem. Output is still written
+        // to dest_dir unless the site is built with build_to_memory.
+        pub fn with_source_fs<F: SourceFs + 'static>(mut self, fs: F) -> Self {
+            self.source_fs = Box::new(fs);
+            self
+        }
+
+        // Transforms run in registration order, after the layout has been applied.
+        pub fn with_transform<F>(mut self, transform: F) -> Self
+        where
+            F: FnMut(&Path, String) -> String + 'static,
+        {
+            self.transforms.push(Box::new(transform));
+            self
+        }
+
+        // Postprocessors run on every page or asset written with the extension, e.g. "css",
+        // just before it is written. They get the output path relative to dest_dir and run in
+        // registration order; assets they apply to are read as UTF-8 text.
+        pub fn with_postprocessor<F>(mut self, extension: &str, postprocessor: F) -> Self
+        where
+            F: FnMut(&Path, String) -> String + 'static,
+        {
+            let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+            self.postprocessors.entry(extension).or_default().push(Box::new(postprocessor));
+            self
+        }
+
+        // Renders every <tag ...> or <tag ...>...</tag> on a page with handler, once variables
+        // are substituted and before relative links are rebased. Handlers run in
+        // registration order; what they return is not expanded further.
+        pub fn with_directive<F>(mut self, tag: &str, handler: F) -> Self
+        where
+            F: Fn(&[(&str, &str)], &DirectiveContext) -> String + 'static,
+        {
+            self.custom_directives.push((directives::custom_directive(tag), Box::new(handler)));
+            self
+        }
+
+        pub fn with_skip_unchanged_assets(mut self, skip_unchanged_assets: bool) -> Self {
+            self.skip_unchanged_assets = skip_unchanged_assets;
+            self
+        }
+
+        // With more than one job, asset copies are queued while pages are flattened and run on
+        // that many threads at the end of the build, before the output is swapped in.
+        pub fn with_copy_jobs(mut self, copy_jobs: usize) -> Self {
+            self.copy_jobs = copy_jobs.max(1);
+            self
+        }
+
+        // Builds into a subdirectory of dest_dir and rewrites root-relative links to match,
+        // e.g. "/pr-123" for a self-contained preview. Call after with_dest_dir.
+        pub fn with_mount(mut self, mount: Option<String>) -> Self {
+            let mount = mount.map(|mount| mount.trim_matches('/').to_string()).filter(|mount| !mount.is_empty());
+            if let Some(mount) = &mount {
+                self.dest_dir = self.dest_dir.join(mount);
             }
-            if let Err(e) = fs::write(dest_path, result) {
-                eprintln!("Failed to write file: {}", e);
+            self.mount = mount;
+            self
+        }
+
+        // Rewrites root-relative links under base_path like with_mount, but leaves the output
+        // where it is, for a host that serves dest_dir itself from /base_path.
+        pub fn with_base_path(mut self, base_path: Option<String>) -> Self {
+            self.mount = base_path.map(|base_path| base_path.trim_matches('/').to_string()).filter(|base_path| !base_path.is_empty());
+            self
+        }
+
+        // Rewrites root-relative links on every page relative to the page itself, so the
+        // output works when opened straight from disk. A mount or base path is then moot.
+        pub fn with_relative_links(mut self, relative_links: bool) -> Self {
+            self.relative_links = relative_links;
+            self
+        }
+
+        // Writes an empty .nojekyll so GitHub Pages serves the output as it is, _-prefixed
+        // files included.
+        pub fn with_nojekyll(mut self, nojekyll: bool) -> Self {
+            self.nojekyll = nojekyll;
+            self
+        }
+
+        // Components expanded several times into the same page or layout are emitted once in a
+        // <template> and cloned into place by a small inline script.
+        pub fn with_dedupe_components(mut self, dedupe_components: bool) -> Self {
+            self.dedupe_components = dedupe_components;
+            self
+        }
+
+        pub fn with_warn_empty(mut self, warn_empty: bool) -> Self {
+            self.warn_empty = warn_empty;
+            self
+        }
+
+        // strftime-style format of {{ build.time }}, see variables::format_timestamp.
+        pub fn with_build_time_format(mut self, format: Option<String>) -> Self {
+            if let Some(format) = format {
+                self.build_time_format = format;
             }
+            self
+        }
+
+        // Lints every flattened page for mismatched or unclosed tags without changing it.
+        pub fn with_html_validation(mut self, validate_html: bool) -> Self {
+            self.validate_html = validate_html;
+            self
+        }
+
+        // Warns about images without alt, an <html> without lang and buttons without text in
+        // flattened pages.
+        pub fn with_a11y_lint(mut self, a11y_lint: bool) -> Self {
+            self.a11y_lint = a11y_lint;
+            self
+        }
+
+        // Warns about pages, components and layouts with control characters other than tab,
+        // newline and CR, or that are not valid UTF-8, giving the byte offset of the first.
+        pub fn with_encoding_lint(mut self, encoding_lint: bool) -> Self {
+            self.encoding_lint = encoding_lint;
+            self
+        }
+
+        // Builds pages marked `draft: true`, which are otherwise left out of the output and of
+        // everything generated from it, such as directory indexes and _redirects.
+        pub fn with_drafts(mut self, drafts: bool) -> Self {
+            self.drafts = drafts;
+            self
         }
 
-        pub fn build(&mut self) {
-            if let Err(e) = fs::remove_dir_all(&self.dest_dir) {
-                eprintln!("Failed to remove directory: {}", e);
+        // The environment <el-env> blocks are matched against, usually the --env overlay.
+        pub fn with_env(mut self, env: Option<String>) -> Self {
+            self.env = env;
+            self
+        }
+
+        // Once every page is built, reports each local <el-link to="..."> target that no page
+        // or asset was written to. A directory target is satisfied by its index file and an
+        // extensionless one by the same name with .html.
+        pub fn with_link_checking(mut self, check_links: bool) -> Self {
+            self.check_links = check_links;
+            self
+        }
+
+        // Narrows each component's <style> rules to that component's own elements.
+        pub fn with_scoped_styles(mut self, scoped_styles: bool) -> Self {
+            self.scoped_styles = scoped_styles;
+            self
+        }
+
+        // The file a directory URL is served from, e.g. "default.html" on some hosts.
+        pub fn with_index_file(mut self, index_file: Option<String>) -> Self {
+            if let Some(index_file) = index_file {
+                self.index_file = index_file;
             }
-            let src_dir = self.src_dir.clone();
-            self.process_files(&src_dir);
+            self
         }
 
-        fn copy_to_output(&self, path: &Path) {
-            let dest_path = self.dest_dir.join(path);
-            let src_path = self.src_dir.join(path);
-            if let Some(parent) = dest_path.parent() {
-                if let Err(e) = fs::create_dir_all(parent) {
-                    eprintln!("Failed to create directory: {}", e);
-                    return;
+        pub fn with_lint(mut self, lint: bool) -> Self {
+            self.lint = lint;
+            self
+        }
+
+        // Global constants available as {{ key }}; later definitions of a key win.
+        pub fn with_defines(mut self, defines: Vec<(String, String)>) -> Self {
+            self.defines.extend(defines);
+            self
+        }
+
+        // Outputs above this many bytes are not written; outside strict mode they are skipped.
+        pub fn with_max_file_size(mut self, max_file_size: Option<u64>) -> Self {
+            self.max_file_size = max_file_size;
+            self
+        }
+
+        // Writes a <page>.map sidecar recording which source produced each byte range.
+        pub fn with_source_map(mut self, source_map: bool) -> Self {
+            self.source_map = source_map;
+            self
+        }
+
+        // Inlines each page's local stylesheets, scripts and images into a self-contained file.
+        pub fn with_bundle(mut self, bundle: bool) -> Self {
+            self.bundle = bundle;
+            self
+        }
+
+        // Gives every local script and stylesheet a sha384 integrity attribute matching the
+        // file it loads.
+        pub fn with_integrity(mut self, integrity: bool) -> Self {
+            self.integrity = integrity;
+            self
+        }
+
+        // Keeps the previous output of every page whose source and dependencies have the same
+        // content as in the last incremental build, going by an index of content hashes kept
+        // next to dest_dir. Unlike modification times, hashes survive a fresh checkout. Pages
+        // showing {{ build.time }} keep the time of the build that last wrote them.
+        pub fn with_incremental(mut self, incremental: bool) -> Self {
+            self.incremental = incremental;
+            self
+        }
+
+        // Generates a random nonce for every page, available as {{ nonce }} and added to each
+        // of the page's <script> and <style> tags, for a nonce-based Content-Security-Policy.
+        pub fn with_csp_nonce(mut self, csp_nonce: bool) -> Self {
+            self.csp_nonce = csp_nonce;
+            self
+        }
+
+        // Copies only the assets some flattened page links to. Assets reached only from CSS,
+        // e.g. background images, count as unused.
+        pub fn with_prune_unused_assets(mut self, prune_unused_assets: bool) -> Self {
+            self.prune_unused_assets = prune_unused_assets;
+            self
+        }
+
+        // Lets several builders, e.g. one per site in build-all, expand shared components once.
+        // The expansion reflects the aliases and options of whichever site expanded it first.
+        pub fn with_shared_cache(mut self, cache: SharedCache) -> Self {
+            self.cache = cache;
+            self
+        }
+
+        // Lets <el-component src="https://..."> fetch its content at build time. Responses are
+        // kept under .elss-cache/remote and only refetched when caching is off.
+        pub fn with_allow_remote(mut self, allow_remote: bool) -> Self {
+            self.allow_remote = allow_remote;
+            self
+        }
+
+        pub fn with_remote_timeout(mut self, remote_timeout: Duration) -> Self {
+            self.remote_timeout = remote_timeout;
+            self
+        }
+
+        pub fn with_cache(mut self, use_cache: bool) -> Self {
+            self.use_cache = use_cache;
+            self
+        }
+
+        pub fn with_strict(mut self, strict: bool) -> Self {
+            self.strict = strict;
+            self
+        }
+
+        pub fn with_ssi(mut self, enable_ssi: bool) -> Self {
+            self.enable_ssi = enable_ssi;
+            self
+        }
+
+        pub fn with_strip_comments(mut self, strip_comments: bool) -> Self {
+            self.strip_comments = strip_comments;
+            self
+        }
+
+        // Every setting this builder would build with, once the config file, any overlay and
+        // the with_* calls made since have been applied. Settings that also exist in elss.toml
+        // use its keys; unset optional ones are left out.
+        pub fn resolved_config(&self) -> BTreeMap<String, Value> {
+            let string = |s: &str| Value::String(s.to_string());
+            let path = |path: &Path| Value::String(path.to_string_lossy().to_string());
+            let strings = |items: Vec<&str>| Value::Array(items.into_iter().map(string).collect());
+            let paths = |items: &[PathBuf]| Value::Array(items.iter().map(|item| path(item)).collect());
+            let table = |entries: &mut dyn Iterator<Item = (&String, &String)>| Value::Table(entries.map(|(key, value)| (key.clone(), string(value))).collect());
+            let sorted = |items: &HashSet<String>| {
+                let mut items: Vec<&str> = items.iter().map(String::as_str).collect();
+                items.sort();
+                strings(items)
+            };
+
+            let mut config = BTreeMap::new();
+            config.insert("base_dir".to_string(), path(&self.base_dir));
+            config.insert("src_dir".to_string(), path(&self.src_dir));
+            config.insert("dest_dir".to_string(), path(&self.dest_dir));
+            config.insert("aliases".to_string(), table(&mut self.aliases.iter()));
+            let component_roots: BTreeMap<_, _> = self.component_roots.iter()
+                .map(|(root, dir)| (root.clone(), string(dir.trim_start_matches("../"))))
+                .collect();
+            config.insert("component_roots".to_string(), Value::Table(component_roots));
+            let handlers = self.handlers.iter().map(|(extension, handler)| {
+                let handler = match handler {
+                    FileHandler::Flatten => "flatten",
+                    FileHandler::Copy => "copy",
+                };
+                (extension.clone(), string(handler))
+            });
+            config.insert("handlers".to_string(), Value::Table(handlers.collect()));
+            let mut global_components: Vec<&str> = self.global_components.values().map(String::as_str).collect();
+            global_components.sort();
+            config.insert("global_components".to_string(), strings(global_components));
+            config.insert("error_pages".to_string(), paths(&self.error_pages));
+            config.insert("verbatim".to_string(), paths(&self.verbatim_dirs));
+            if let Some(static_dir) = &self.static_dir {
+                config.insert("static_dir".to_string(), path(static_dir));
+            }
+            config.insert("ignore_files".to_string(), sorted(&self.ignored_files));
+            config.insert("ignore_extensions".to_string(), sorted(&self.ignored_extensions));
+            config.insert("allowed_output_extensions".to_string(), strings(self.allowed_output_extensions.iter().map(String::as_str).collect()));
+            if let Some(post_build) = &self.post_build {
+                config.insert("post_build".to_string(), string(post_build));
+            }
+            if let Some(critical_css) = &self.critical_css {
+                config.insert("critical_css".to_string(), path(critical_css));
+            }
+            let missing_content = match self.missing_content {
+                MissingContent::Drop => "drop",
+                MissingContent::Append => "append",
+                MissingContent::Error => "error",
+            };
+            config.insert("missing_content".to_string(), string(missing_content));
+            config.insert("content_tag".to_string(), string(&self.content_tag));
+            if let Some(missing_component) = &self.missing_component {
+                config.insert("missing_component".to_string(), string(missing_component));
+            }
+            let pass_order = match self.pass_order {
+                PassOrder::ComponentsFirst => "components-first",
+                PassOrder::LayoutFirst => "layout-first",
+            };
+            config.insert("pass_order".to_string(), string(pass_order));
+            config.insert("build_time_format".to_string(), string(&self.build_time_format));
+            config.insert("index_file".to_string(), string(&self.index_file));
+            config.insert("locales".to_string(), strings(self.locales.iter().map(String::as_str).collect()));
+            if let Some(default_locale) = &self.default_locale {
+                config.insert("default_locale".to_string(), string(default_locale));
+            }
+            if let Some(robots) = &self.robots {
+                let mut table = BTreeMap::new();
+                table.insert("rules".to_string(), strings(robots.rules.iter().map(String::as_str).collect()));
+                if let Some(sitemap) = &robots.sitemap {
+                    table.insert("sitemap".to_string(), string(sitemap));
                 }
+                config.insert("robots".to_string(), Value::Table(table));
+            }
+            let redirects = self.redirects.iter().map(|redirect| match redirect.status {
+                Some(status) => format!("{} -> {} {}", redirect.from, redirect.to, status),
+                None => format!("{} -> {}", redirect.from, redirect.to),
+            });
+            config.insert("redirects".to_string(), Value::Array(redirects.map(Value::String).collect()));
+            if let Some(auto_index) = &self.auto_index {
+                let mut table = BTreeMap::new();
+                table.insert("dirs".to_string(), strings(auto_index.dirs.iter().map(String::as_str).collect()));
+                table.insert("template".to_string(), string(&auto_index.template));
+                config.insert("auto_index".to_string(), Value::Table(table));
+            }
+
+            config.insert("defines".to_string(), table(&mut self.defines.iter()));
+            config.insert("pages".to_string(), paths(&self.pages));
+            if let Some(only) = &self.only {
+                config.insert("only".to_string(), path(only));
+            }
+            if let Some(mount) = &self.mount {
+                config.insert("mount".to_string(), string(mount));
+            }
+            if let Some(env) = &self.env {
+                config.insert("env".to_string(), string(env));
+            }
+            if let Some(build_id) = &self.build_id {
+                config.insert("build_id".to_string(), string(build_id));
+            }
+            if let Some(max_file_size) = self.max_file_size {
+                config.insert("max_file_size".to_string(), Value::Integer(i64::try_from(max_file_size).unwrap_or(i64::MAX)));
             }
-            if let Err(e) = fs::copy(src_path, dest_path) {
-                eprintln!("Failed to copy file [{}]: {}", path.to_str().unwrap_or_default(), e);
+            config.insert("copy_jobs".to_string(), Value::Integer(i64::try_from(self.copy_jobs).unwrap_or(i64::MAX)));
+            config.insert("remote_timeout".to_string(), string(&format!("{}s", self.remote_timeout.as_secs_f64())));
+            let switches = [
+                ("cache", self.use_cache),
+                ("strict", self.strict),
+                ("keep_going", self.keep_going),
+                ("strip_comments", self.strip_comments),
+                ("enable_ssi", self.enable_ssi),
+                ("warn_fragments", self.warn_fragments),
+                ("validate_refs", self.validate_references),
+                ("warn_duplicate_content", self.warn_duplicate_content),
+                ("skip_unchanged_assets", self.skip_unchanged_assets),
+                ("fsync", self.fsync),
+                ("dedupe_components", self.dedupe_components),
+                ("warn_empty", self.warn_empty),
+                ("validate_html", self.validate_html),
+                ("a11y_lint", self.a11y_lint),
+                ("encoding_lint", self.encoding_lint),
+                ("check_links", self.check_links),
+                ("drafts", self.drafts),
+                ("nojekyll", self.nojekyll),
+                ("relative_links", self.relative_links),
+                ("legacy_layout", self.legacy_layout),
+                ("version_meta", self.version_meta),
+                ("scoped_styles", self.scoped_styles),
+                ("lint", self.lint),
+                ("source_map", self.source_map),
+                ("bundle", self.bundle),
+                ("integrity", self.integrity),
+                ("csp_nonce", self.csp_nonce),
+                ("incremental", self.incremental),
+                ("prune_unused_assets", self.prune_unused_assets),
+                ("allow_remote", self.allow_remote),
+                ("no_clean", self.no_clean),
+                ("wait_for_lock", self.wait_for_lock),
+                ("trim_directives", self.trim_directives),
+            ];
+            for (key, enabled) in switches {
+                config.insert(key.to_string(), Value::Boolean(enabled));
             }
+            config
         }
 
-        fn directory_to_ignore(&self, path: &Path) -> bool {
-            path.starts_with(&self.components_dir) || path.starts_with(&self.layout_dir)
+        // Expands one source file, including its nested components, without writing anything.
+        pub fn render_component(&mut self, path: &Path) -> Result<String, BuildError> {
+            let mut processing = HashSet::new();
+            let result = self.replace_components(path, &mut processing)?;
+            Ok(self.strip_markers(result))
         }
 
-        fn process_file(&mut self, path: &Path) {
-            if path.extension().and_then(|s| s.to_str()) == Some("html") {
-                self.flatten_file(path);
-            } else {
-                self.copy_to_output(path);
+        // Flattens the given pages (relative to src_dir) in memory and joins them, in order,
+        // into one document to print, as html::combine_pages describes. Each page's anchor is
+        // its output path without the extension, e.g. guide-install for guide/install.html.
+        pub fn bundle_docs(&mut self, pages: &[PathBuf]) -> Result<String, BuildError> {
+            let previous_pages = std::mem::replace(&mut self.pages, pages.to_vec());
+            let result = self.build_to_memory();
+            self.pages = previous_pages;
+            let outputs = result?;
+            let mut anchors = HashSet::new();
+            let mut combined = Vec::new();
+            for page in pages {
+                let output = self.outputs.iter()
+                    .filter(|(output, source)| *source == page && output.extension().and_then(|s| s.to_str()) == Some("html"))
+                    .map(|(output, _)| output.clone())
+                    .min()
+                    .ok_or_else(|| BuildError::NotFound(self.src_dir.join(page)))?;
+                let text = String::from_utf8_lossy(outputs.get(&output).map_or(&[][..], Vec::as_slice)).to_string();
+                let slug: String = output.with_extension("").to_string_lossy()
+                    .chars()
+                    .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+                    .collect();
+                let mut anchor = slug.clone();
+                let mut suffix = 1;
+                while !anchors.insert(anchor.clone()) {
+                    suffix += 1;
+                    anchor = format!("{}-{}", slug, suffix);
+                }
+                combined.push((anchor, output, text));
             }
+            Ok(html::combine_pages(&combined))
         }
 
-        fn process_files(&mut self, path: &Path) {
-            if let Ok(entries) = fs::read_dir(path) {
-                for entry in entries.filter_map(Result::ok) {
-                    let entry_path = entry.path();
-                    let entry_path_relative = entry_path.strip_prefix(&self.src_dir).unwrap();
-                    if entry_path.is_dir() {
-                        if self.directory_to_ignore(&entry_path_relative) {
-                            continue;
-                        }
-                        self.process_files(&entry_path);
-                    } else {
-                        self.process_file(&entry_path_relative);
-                    }
-                }
+        // Flattens one page (relative to src_dir) in memory for a browser to print from a
+        // temporary file: root-relative links are made relative and a <base> points the rest
+        // at the page's directory under src_dir, where its stylesheets and images live.
+        pub fn render_for_print(&mut self, page: &Path) -> Result<String, BuildError> {
+            let previous_pages = std::mem::replace(&mut self.pages, vec![page.to_path_buf()]);
+            let result = self.build_to_memory();
+            self.pages = previous_pages;
+            let outputs = result?;
+            let output = self.outputs.iter()
+                .filter(|(output, source)| source.as_path() == page && output.extension().and_then(|s| s.to_str()) == Some("html"))
+                .map(|(output, _)| output.clone())
+                .min()
+                .ok_or_else(|| BuildError::NotFound(self.src_dir.join(page)))?;
+            let text = String::from_utf8_lossy(outputs.get(&output).map_or(&[][..], Vec::as_slice)).to_string();
+            let text = html::relativize_root_links(&text, output.components().count().saturating_sub(1));
+            let base_dir = fs::canonicalize(&self.src_dir)?.join(output.parent().unwrap_or(Path::new("")));
+            let base = format!("<base href=\"{}/\">", html::file_url(&base_dir).trim_end_matches('/'));
+            Ok(html::prepend_to_head(&text, &base).unwrap_or_else(|| base + &text))
+        }
+
+        // Builds one page (relative to src_dir) in memory, without the cache, and describes
+        // each step: the layouts applied, every component with the file it resolved to, slot
+        // fills, the variables substituted and the size of each output.
+        pub fn explain(&mut self, page: &Path) -> Result<Vec<String>, BuildError> {
+            let previous_pages = std::mem::replace(&mut self.pages, vec![page.to_path_buf()]);
+            let use_cache = std::mem::replace(&mut self.use_cache, false);
+            self.trace = Some(Vec::new());
+            let result = self.build_to_memory();
+            self.pages = previous_pages;
+            self.use_cache = use_cache;
+            let mut trace = self.trace.take().unwrap_or_default();
+            let outputs = result?;
+            let mut written: Vec<&PathBuf> = self.outputs.iter().filter(|(_, source)| *source == page).map(|(output, _)| output).collect();
+            written.sort();
+            for output in written {
+                let size = outputs.get(output).map_or(0, Vec::len);
+                trace.push(format!("output [{}]: {} bytes", output.display(), size));
             }
+            Ok(trace)
         }
 
-        fn replace_components(&mut self, path: &Path, processing: &mut HashSet<String>) -> String {
-            println!("Processing file: {}", path.display());
-            let dest_path = self.dest_dir.join(path);
-    
-            if let Some(file_contents) = self.cache.get(&dest_path) {
-                return file_contents.clone();
+        fn trace(&mut self, step: impl FnOnce() -> String) {
+            if let Some(trace) = &mut self.trace {
+                trace.push(step());
             }
-    
-            let src_path = self.src_dir.join(path);
-            let text = match fs::read_to_string(&src_path) {
-                Ok(content) => content,
-                Err(e) => {
-                    eprintln!("Failed to read file [{}]: {}", src_path.display(), e);
-                    return String::new();
-                }
-            };
-            let mut result = text.clone();
-    
-            let captures: Vec<_> = self.component_regex.captures_iter(&text).collect();
-            for captures in captures {
-                if let Some(src) = captures.get(1) {
-                    let component_path = format!("{}/{}", self.components_dir, src.as_str().trim_end_matches(".html").to_string() + ".html");
-                    
-                    if processing.contains(&component_path) {
-                        eprintln!("Circular dependency detected for component [{}]", component_path);
+        }
+
+        // A standalone page showing every component in components_dir. A <name>.toml next to
+        // <name>.html may give a `description` and an [example] table of variables to render
+        // the component with.
+        pub fn catalog(&mut self) -> Result<String, BuildError> {
+            self.init_variables();
+            let mut sections = String::new();
+            for file in self.source_files()? {
+                if !file.starts_with(&self.components_dir) || file.extension().and_then(|s| s.to_str()) != Some("html") {
+                    continue;
+                }
+                let metadata_path = self.src_dir.join(file.with_extension("toml"));
+                let metadata = if self.source_fs.is_file(&metadata_path) {
+                    config::parse_table(&self.source_fs.read_to_string(&metadata_path)?)
+                        .map_err(|e| BuildError::ConfigError(format!("{}: {}", metadata_path.display(), e)))?
+                } else {
+                    BTreeMap::new()
+                };
+                let mut variables = self.variables.clone();
+                if let Some(example) = metadata.get("example").and_then(Value::as_table) {
+                    for (key, value) in example {
+                        let value = match value {
+                            Value::String(value) => value.clone(),
+                            Value::Integer(value) => value.to_string(),
+                            Value::Boolean(value) => value.to_string(),
+                            _ => continue,
+                        };
+                        variables.insert(key.clone(), value);
+                    }
+                }
+                let rendered = match self.render_component(&file) {
+                    Ok(rendered) => variables::substitute(&rendered, &variables),
+                    Err(e) => {
+                        self.report_error(&format!("Failed to render component [{}]", file.display()), e)?;
                         continue;
                     }
-    
-                    processing.insert(component_path.clone());
-                    let file_contents = self.replace_components(Path::new(&component_path), processing);
-                    processing.remove(&component_path);
-    
-                    result = result.replace(&captures[0], &file_contents);
+                };
+                let name = file.strip_prefix(&self.components_dir).unwrap_or(&file).with_extension("");
+                let description = metadata.get("description").and_then(Value::as_str)
+                    .map(|description| format!("<p>{}</p>", html::escape(description)))
+                    .unwrap_or_default();
+                sections.push_str(&format!(
+                    "<section><h2>{}</h2>{}<div class=\"el-catalog-example\">{}</div><details><summary>Source</summary><pre>{}</pre></details></section>\n",
+                    html::escape(&name.to_string_lossy()), description, rendered, html::escape(&rendered)));
+            }
+            Ok(format!("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Components</title><style>{}</style></head><body><h1>Components</h1>\n{}</body></html>\n", CATALOG_STYLE, sections))
+        }
+
+        // Runs the component and layout passes over a document that does not live in src_dir.
+        pub fn render_string(&mut self, text: &str) -> Result<String, BuildError> {
+            let source = Path::new("<input>");
+            let (front_matter, body) = frontmatter::split(text);
+            self.front_matter.insert(source.to_path_buf(), front_matter);
+            let body = self.escape_markers(body);
+            let mut processing = HashSet::new();
+            let result = self.expand_components(source, &body, &mut processing)?;
+            let mut result = self.replace_layout(source, &result, None)?;
+            self.init_variables();
+            let variables = self.page_variables(source);
+            result = variables::substitute(&self.expand_switches(&self.expand_env_blocks(&result), &variables), &variables);
+            if self.strip_comments {
+                result = self.remove_comments(&result);
+            }
+            Ok(self.strip_markers(result))
+        }
+
+        fn flatten_file(&mut self, file: &Path, default_layout: Option<&str>) -> Result<Option<PathBuf>, BuildError> {
+            self.flatten_file_to(file, file, default_layout)
+        }
+
+        fn flatten_file_to(&mut self, file: &Path, output: &Path, default_layout: Option<&str>) -> Result<Option<PathBuf>, BuildError> {
+            if self.dedupe_components {
+                self.shared_components = Some(Vec::new());
+            }
+            self.dependencies.clear();
+            self.invocations.clear();
+            // Index pages depend on the walk as a whole, which no fingerprint covers.
+            let indexed = self.hash_index.is_some() && self.index_variables.is_empty();
+            if indexed {
+                if let Some(dest_path) = self.reuse_previous_output(file, output, default_layout)? {
+                    self.shared_components = None;
+                    return Ok(Some(dest_path));
+                }
+            }
+            let started = Instant::now();
+            let result = self.flatten_page(file, output, default_layout);
+            self.timing.pages.push((file.to_path_buf(), started.elapsed()));
+            self.shared_components = None;
+            let dependencies = std::mem::take(&mut self.dependencies);
+            if let (true, Ok(Some(dest_path))) = (indexed, &result) {
+                let mut dependencies: Vec<PathBuf> = dependencies.iter().cloned().collect();
+                dependencies.sort();
+                let mapped = dest_path.strip_prefix(&self.dest_dir).unwrap_or(dest_path).to_path_buf();
+                self.index_output(file, output, mapped, dependencies, default_layout);
+            }
+            self.page_dependencies.insert(file.to_path_buf(), dependencies);
+            let invocations = std::mem::take(&mut self.invocations);
+            if let Some(usage) = &mut self.usage {
+                for invoked in invocations.into_iter().filter(|invoked| invoked != file) {
+                    let (count, pages) = usage.entry(invoked).or_default();
+                    *count += 1;
+                    pages.insert(file.to_path_buf());
                 }
             }
-    
-            self.cache.insert(dest_path, result.clone());
             result
         }
-        
-        fn replace_layout(&mut self, content: &str) -> String {
-            let mut result = content.to_string();
-            if let Some(captures) = self.layout_regex.captures(content) {
-                if let Some(src) = captures.get(1) {
-                    let file_path = format!("{}/{}", self.layout_dir, src.as_str().trim_end_matches(".html").to_string() + ".html");
-                    let mut processing = HashSet::new();
-                    let file_contents = self.replace_components(Path::new(&file_path), &mut processing);
-                    let layout_content = self.layout_content_regex.replace_all(&file_contents, content);
-                    result = layout_content.to_string();
+
+        // The previous output stands in for a page when the fingerprint of its recorded
+        // dependencies still matches. It is copied into the staging directory and the page's
+        // front matter is read again for what later steps take from it.
+        fn reuse_previous_output(&mut self, file: &Path, output: &Path, default_layout: Option<&str>) -> Result<Option<PathBuf>, BuildError> {
+            let key = (file.to_path_buf(), output.to_path_buf());
+            let indexed = match self.previous_hash_index.get(&key) {
+                Some(indexed) => indexed.clone(),
+                None => return Ok(None),
+            };
+            let previous = self.dest_dir.join(&indexed.output);
+            if !previous.is_file() || self.fingerprint(&indexed.dependencies, default_layout).as_ref() != Some(&indexed.fingerprint) {
+                return Ok(None);
+            }
+            let text = self.source_fs.read_to_string(&self.src_dir.join(file))?;
+            self.front_matter.insert(file.to_path_buf(), frontmatter::split(&text).0);
+            // A moved permalink or a conflict is left for the normal build to deal with.
+            let unmapped = self.page_output_path(file, output)?;
+            let mapped = self.map_output(&unmapped)?;
+            if mapped != indexed.output || self.outputs.get(&mapped).is_some_and(|existing| existing != file) {
+                return Ok(None);
+            }
+            self.claim_output(&mapped, file)?;
+            let write_path = self.output_root().join(&mapped);
+            if write_path != previous {
+                if let Some(parent) = write_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(&previous, &write_path)?;
+                let mut map_path = mapped.as_os_str().to_owned();
+                map_path.push(".map");
+                if self.source_map && self.dest_dir.join(&map_path).is_file() {
+                    fs::copy(self.dest_dir.join(&map_path), self.output_root().join(&map_path))?;
                 }
             }
-            result
+            if self.auto_index.is_some() || self.prune_unused_assets {
+                let page = fs::read_to_string(&previous)?;
+                if self.auto_index.is_some() {
+                    let title = self.page_variables(file).get("title").cloned()
+                        .or_else(|| html::title(&page))
+                        .unwrap_or_else(|| file.file_stem().unwrap_or_default().to_string_lossy().to_string());
+                    self.page_titles.insert(unmapped.clone(), (mapped.clone(), title));
+                }
+                if self.prune_unused_assets {
+                    let output_dir = unmapped.parent().unwrap_or(Path::new(""));
+                    for url in html::local_references(&page) {
+                        self.referenced_assets.extend(html::resolve_url(output_dir, url));
+                    }
+                }
+            }
+            self.collect_redirects(file, &mapped);
+            self.page_dependencies.insert(file.to_path_buf(), indexed.dependencies.iter().cloned().collect());
+            self.hash_index.get_or_insert_with(HashMap::new).insert(key, indexed);
+            self.reused.push(self.dest_dir.join(&mapped));
+            Ok(Some(self.dest_dir.join(&mapped)))
+        }
+
+        fn index_output(&mut self, file: &Path, output: &Path, mapped: PathBuf, dependencies: Vec<PathBuf>, default_layout: Option<&str>) {
+            if let Some(fingerprint) = self.fingerprint(&dependencies, default_layout) {
+                let indexed = IndexedOutput { output: mapped, fingerprint, dependencies };
+                self.hash_index.get_or_insert_with(HashMap::new).insert((file.to_path_buf(), output.to_path_buf()), indexed);
+            }
+        }
+
+        // Covers the settings, the inherited layout and the path and content of every
+        // dependency. None when a dependency can no longer be read.
+        fn fingerprint(&mut self, dependencies: &[PathBuf], default_layout: Option<&str>) -> Option<String> {
+            let mut input = self.config_fingerprint.clone().into_bytes();
+            for part in [default_layout, self.critical_css_content.as_deref()] {
+                input.extend(part.unwrap_or_default().bytes());
+                input.push(0);
+            }
+            for dependency in dependencies {
+                let hash = match self.file_hashes.get(dependency) {
+                    Some(hash) => *hash,
+                    None => {
+                        let hash = encoding::sha384(&self.source_fs.read(&self.src_dir.join(dependency)).ok()?);
+                        self.file_hashes.insert(dependency.clone(), hash);
+                        hash
+                    }
+                };
+                input.extend(dependency.to_string_lossy().bytes());
+                input.push(0);
+                input.extend(hash);
+            }
+            Some(encoding::base64(&encoding::sha384(&input)))
+        }
+
+        fn hash_index_path(&self) -> PathBuf {
+            let name = self.dest_dir.file_name().and_then(|s| s.to_str()).unwrap_or("build");
+            self.dest_dir.with_file_name(format!(".{}.elss-hashes", name))
+        }
+
+        // Each line is the page, the output asked for, the output written, the fingerprint and
+        // the dependencies, separated by tabs. Lines that don't parse are rebuilt.
+        fn load_hash_index(&mut self) -> Result<(), BuildError> {
+            self.previous_hash_index.clear();
+            self.file_hashes.clear();
+            self.hash_index = None;
+            if !self.incremental || !self.locales.is_empty() {
+                return Ok(());
+            }
+            let mut config = self.resolved_config();
+            config.remove("pages");
+            config.remove("only");
+            self.config_fingerprint = config::to_toml(&config);
+            let text = match fs::read_to_string(self.hash_index_path()) {
+                Ok(text) => text,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(BuildError::IoError(e)),
+            };
+            for line in text.lines() {
+                let mut fields = line.split('\t');
+                if let (Some(page), Some(output), Some(mapped), Some(fingerprint)) = (fields.next(), fields.next(), fields.next(), fields.next()) {
+                    let indexed = IndexedOutput {
+                        output: PathBuf::from(mapped),
+                        fingerprint: fingerprint.to_string(),
+                        dependencies: fields.map(PathBuf::from).collect(),
+                    };
+                    self.previous_hash_index.insert((PathBuf::from(page), PathBuf::from(output)), indexed);
+                }
+            }
+            // Partial builds leave the other pages' outputs, and so their entries, in place.
+            self.hash_index = Some(if self.no_clean || self.only.is_some() { self.previous_hash_index.clone() } else { HashMap::new() });
+            Ok(())
+        }
+
+        fn save_hash_index(&mut self) -> Result<(), BuildError> {
+            let hash_index = match self.hash_index.take() {
+                Some(hash_index) => hash_index,
+                None => return Ok(()),
+            };
+            let mut lines: Vec<String> = hash_index.iter().map(|((page, output), indexed)| {
+                let mut fields = vec![page.to_string_lossy(), output.to_string_lossy(), indexed.output.to_string_lossy(), indexed.fingerprint.as_str().into()];
+                fields.extend(indexed.dependencies.iter().map(|dependency| dependency.to_string_lossy()));
+                fields.join("\t")
+            }).collect();
+            lines.sort();
+            fs::write(self.hash_index_path(), lines.iter().map(|line| format!("{}\n", line)).collect::<String>())?;
+            Ok(())
+        }
+
+        fn flatten_page(&mut self, file: &Path, output: &Path, default_layout: Option<&str>) -> Result<Option<PathBuf>, BuildError> {
+            if self.pass_order == PassOrder::LayoutFirst {
+                return self.flatten_page_layout_first(file, output, default_layout);
+            }
+            let mut processing = HashSet::new();
+            let started = Instant::now();
+            let result = self.replace_components(file, &mut processing)?;
+            self.timing.components += started.elapsed();
+            let result = self.mark_source(file, result);
+            if self.is_skipped_draft(file) {
+                return Ok(None);
+            }
+            let (unmapped, output) = match self.claim_page_output(file, output)? {
+                Some(outputs) => outputs,
+                None => return Ok(None),
+            };
+            self.record_reading_time(file, &result);
+            let started = Instant::now();
+            let result = self.replace_layout(file, &result, default_layout)?;
+            self.timing.layouts += started.elapsed();
+            self.finish_page(file, &unmapped, &output, result)
+        }
+
+        fn flatten_page_layout_first(&mut self, file: &Path, output: &Path, default_layout: Option<&str>) -> Result<Option<PathBuf>, BuildError> {
+            let src_path = self.src_dir.join(file);
+            let text = self.source_fs.read_to_string(&src_path);
+            self.lint_encoding(&src_path, text.as_deref().ok());
+            let text = text.map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => BuildError::NotFound(src_path.clone()),
+                _ => BuildError::IoError(e),
+            })?;
+            let (front_matter, body) = frontmatter::split(&text);
+            self.front_matter.insert(file.to_path_buf(), front_matter);
+            self.dependencies.insert(file.to_path_buf());
+            if self.is_skipped_draft(file) {
+                return Ok(None);
+            }
+            let (unmapped, output) = match self.claim_page_output(file, output)? {
+                Some(outputs) => outputs,
+                None => return Ok(None),
+            };
+            let body = self.mark_source(file, body.to_string());
+            // Components are still unexpanded here, so only the page's own text is counted.
+            self.record_reading_time(file, &body);
+            let started = Instant::now();
+            let result = self.replace_layout(file, &body, default_layout)?;
+            self.timing.layouts += started.elapsed();
+            let mut processing = HashSet::new();
+            let started = Instant::now();
+            let result = self.expand_components(file, &result, &mut processing)?;
+            self.timing.components += started.elapsed();
+            self.finish_page(file, &unmapped, &output, result)
+        }
+
+        // The page's output path before and after mapping, or None when another source
+        // already claimed it. Links are rebased against the path before mapping, which the
+        // mapper is expected to apply to pages and assets alike.
+        fn claim_page_output(&mut self, file: &Path, output: &Path) -> Result<Option<(PathBuf, PathBuf)>, BuildError> {
+            let unmapped = self.page_output_path(file, output)?;
+            let output = self.map_output(&unmapped)?;
+            if !self.claim_output(&output, file)? {
+                return Ok(None);
+            }
+            Ok(Some((unmapped, output)))
+        }
+
+        // Everything after the component and layout passes, ending with the write.
+        fn finish_page(&mut self, file: &Path, unmapped: &Path, output: &Path, mut result: String) -> Result<Option<PathBuf>, BuildError> {
+            result = self.expand_each_file(file, &result)?;
+            let mut variables = self.page_variables(file);
+            let nonce = self.csp_nonce.then(encoding::random_nonce);
+            if let Some(nonce) = &nonce {
+                variables.insert("nonce".to_string(), nonce.clone());
+            }
+            if self.trace.is_some() {
+                let mut used = Vec::new();
+                for name in variables::used(&result, &variables) {
+                    let substitution = format!("{} = {}", name, variables[name]);
+                    if !used.contains(&substitution) {
+                        used.push(substitution);
+                    }
+                }
+                let used = if used.is_empty() { "none".to_string() } else { used.join(", ") };
+                self.trace(|| format!("variables substituted in [{}]: {}", file.display(), used));
+            }
+            result = self.expand_conditional_includes(file, &result, &variables)?;
+            result = variables::substitute(&self.expand_switches(&self.expand_env_blocks(&result), &variables), &variables);
+            result = self.expand_links(file, &result)?;
+            result = self.expand_asset_inlines(file, &result)?;
+            for (tag, handler) in &self.custom_directives {
+                result = tag.replace_all(&result, |captures: &regex::Captures| {
+                    let attributes = directives::parse_attributes(captures.get(1).map_or("", |attributes| attributes.as_str()));
+                    let context = DirectiveContext { page: file, variables: &variables, content: captures.get(2).map(|content| content.as_str()) };
+                    handler(&attributes, &context)
+                }).to_string();
+            }
+            if self.auto_index.is_some() {
+                let title = variables.get("title").cloned()
+                    .or_else(|| html::title(&result))
+                    .unwrap_or_else(|| file.file_stem().unwrap_or_default().to_string_lossy().to_string());
+                self.page_titles.insert(unmapped.to_path_buf(), (output.to_path_buf(), title));
+            }
+            let (source_dir, output_dir) = (file.parent().unwrap_or(Path::new("")), unmapped.parent().unwrap_or(Path::new("")));
+            if source_dir != output_dir {
+                result = html::rebase_relative_links(&result, source_dir, output_dir);
+            }
+            if let Some(shared) = self.shared_components.as_mut().filter(|shared| !shared.is_empty()) {
+                let mut templates = String::new();
+                for (index, content) in shared.drain(..).enumerate() {
+                    let placeholder = format!(r#"<template data-el-use="el-shared-{}"></template>"#, index);
+                    let uses = result.matches(&placeholder).count();
+                    self.bytes_saved += (uses * content.len()) as i64 - (uses * placeholder.len()) as i64;
+                    templates.push_str(&format!(r#"<template id="el-shared-{}">{}</template>"#, index, content));
+                }
+                self.bytes_saved -= (templates.len() + DEDUPE_SCRIPT.len()) as i64;
+                result = html::append_to_body(&result, &(templates + DEDUPE_SCRIPT));
+            }
+            result = self.hoist_head_content(file, &result);
+            for transform in &mut self.transforms {
+                result = transform(file, result);
+            }
+            if self.strip_comments {
+                let before = result.len();
+                result = self.remove_comments(&result);
+                self.bytes_saved += (before - result.len()) as i64;
+            }
+            if self.prune_unused_assets {
+                for url in html::local_references(&result) {
+                    self.referenced_assets.extend(html::resolve_url(output_dir, url));
+                }
+            }
+            if self.integrity {
+                result = self.add_integrity(file, unmapped, &result);
+            }
+            self.collect_redirects(file, output);
+            if let Some(locale) = &self.locale {
+                result = html::prefix_root_links(&result, locale);
+            }
+            // Relative links need no mount prefix; the locale directory is part of the depth.
+            if self.relative_links {
+                let depth = unmapped.components().count().saturating_sub(1) + usize::from(self.locale.is_some());
+                result = html::relativize_root_links(&result, depth);
+            }
+            if let Some(mount) = &self.mount {
+                result = html::prefix_root_links(&result, mount);
+            }
+            if self.version_meta {
+                match html::insert_into_head(&result, &self.version_meta_tags()) {
+                    Some(stamped) => result = stamped,
+                    None => self.warn_in(file, None, &format!("page [{}] has no <head>, version meta tags not added", file.display())),
+                }
+            }
+            if let (Some(css_path), Some(css)) = (&self.critical_css, &self.critical_css_content) {
+                match html::inline_critical_css(&result, css, &css_path.to_string_lossy()) {
+                    Some(inlined) => result = inlined,
+                    None => self.warn_in(file, None, &format!("page [{}] has no <head>, critical CSS not inlined", file.display())),
+                }
+            }
+            if self.bundle {
+                result = self.bundle_page(file, unmapped, &result);
+            }
+            if let Some(nonce) = &nonce {
+                result = html::add_nonce(&result, nonce);
+            }
+            if self.reject_unknown_directives {
+                self.check_unknown_directives(file, &result)?;
+            }
+            if self.lint {
+                self.lint_leftover_directives(file, &result);
+            }
+            if self.validate_html {
+                for message in html::tag_balance_problems(&result) {
+                    self.report_error("Validation failed", BuildError::InvalidHtml { file: file.to_path_buf(), message })?;
+                }
+            }
+            if self.a11y_lint {
+                for problem in html::accessibility_problems(&result) {
+                    self.warn_in(file, None, &format!("page [{}]: {}", file.display(), problem));
+                }
+            }
+            if self.warn_fragments && !result.to_ascii_lowercase().contains("<html") {
+                self.fragment_pages.push(file.to_path_buf());
+            }
+            if self.source_map {
+                let (clean, ranges) = sourcemap::strip(&result);
+                result = clean;
+                let map = sourcemap::to_json(output, &ranges, &self.source_map_sources);
+                let mut map_path = output.as_os_str().to_owned();
+                map_path.push(".map");
+                self.write_file(Path::new(&map_path), map.into_bytes())?;
+            }
+            result = self.postprocess(output, result);
+            if !self.within_size_limit(file, result.len() as u64)? {
+                return Ok(None);
+            }
+            self.write_file(output, result.into_bytes())?;
+            Ok(Some(self.dest_dir.join(output)))
+        }
+
+        fn postprocess(&mut self, output: &Path, mut content: String) -> String {
+            if let Some(postprocessors) = self.postprocessors.get_mut(&output_extension(output)) {
+                for postprocessor in postprocessors {
+                    content = postprocessor(output, content);
+                }
+            }
+            content
+        }
+
+        fn write_file(&mut self, output: &Path, contents: Vec<u8>) -> Result<(), BuildError> {
+            let started = Instant::now();
+            self.bytes_written += contents.len() as u64;
+            if let Some(memory_output) = &mut self.memory_output {
+                memory_output.insert(output.to_path_buf(), contents);
+            } else if !self.dry_run {
+                let write_path = self.output_root().join(output);
+                if let Some(parent) = write_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                if self.fsync {
+                    let mut file = fs::File::create(&write_path)?;
+                    file.write_all(&contents)?;
+                    file.sync_all()?;
+                } else {
+                    fs::write(&write_path, contents)?;
+                }
+            }
+            self.timing.io += started.elapsed();
+            Ok(())
+        }
+
+        // Source text is escaped so the characters sourcemap uses as markers stay literal.
+        fn escape_markers<'a>(&self, text: &'a str) -> std::borrow::Cow<'a, str> {
+            if self.source_map { sourcemap::escape(text) } else { std::borrow::Cow::Borrowed(text) }
+        }
+
+        fn strip_markers(&self, text: String) -> String {
+            if self.source_map { sourcemap::strip(&text).0 } else { text }
+        }
+
+        fn mark_source(&mut self, path: &Path, content: String) -> String {
+            if !self.source_map {
+                return content;
+            }
+            let source = path.to_string_lossy().to_string();
+            let id = match self.source_map_sources.iter().position(|known| *known == source) {
+                Some(id) => id,
+                None => {
+                    self.source_map_sources.push(source);
+                    self.source_map_sources.len() - 1
+                }
+            };
+            sourcemap::wrap(id, &content)
+        }
+
+        // Pages marked `draft: true` in their front matter are only built with drafts on.
+        fn is_skipped_draft(&self, file: &Path) -> bool {
+            !self.drafts && self.front_matter.get(file).and_then(|front_matter| front_matter.get("draft")) == Some("true")
+        }
+
+        // A `permalink` in the page front matter replaces the source-mirrored output path.
+        // Permalinks ending in a slash get the index file appended.
+        fn page_output_path(&self, file: &Path, output: &Path) -> Result<PathBuf, BuildError> {
+            let permalink = match self.front_matter.get(file).and_then(|front_matter| front_matter.get("permalink")) {
+                Some(permalink) => permalink,
+                None => return Ok(output.to_path_buf()),
+            };
+            let mut path = safe_relative_path(permalink).ok_or_else(|| BuildError::PathTraversal(permalink.to_string()))?;
+            if permalink.ends_with('/') {
+                path.push(&self.index_file);
+            }
+            Ok(path)
+        }
+
+        fn map_output(&self, output: &Path) -> Result<PathBuf, BuildError> {
+            let output = match &self.locale {
+                Some(locale) => Path::new(locale).join(output),
+                None => output.to_path_buf(),
+            };
+            let mapped = match &self.path_mapper {
+                Some(mapper) => {
+                    let mapped = mapper(&output).to_string_lossy().to_string();
+                    safe_relative_path(&mapped).ok_or(BuildError::PathTraversal(mapped))?
+                }
+                None => output,
+            };
+            // Outputs without an extension, e.g. CNAME, are always allowed.
+            let extension = output_extension(&mapped);
+            if !extension.is_empty() && !self.allowed_output_extensions.is_empty() && !self.allowed_output_extensions.contains(&extension) {
+                return Err(BuildError::DisallowedExtension(mapped));
+            }
+            Ok(mapped)
+        }
+
+        // Builds like build, calling handler with each event as it happens. Warnings are
+        // still logged as well.
+        pub fn build_with_progress<F>(&mut self, handler: F) -> Result<BuildReport, BuildError>
+        where
+            F: FnMut(BuildEvent) + 'static,
+        {
+            self.progress_handler = Some(RefCell::new(Box::new(handler)));
+            self.emit(BuildEvent::Started);
+            let result = self.build();
+            if let Ok(report) = &result {
+                self.emit(BuildEvent::Finished { report: report.clone() });
+            }
+            self.progress_handler = None;
+            result
+        }
+
+        // Everything is written into a sibling staging directory which replaces dest_dir only
+        // once the whole build succeeded, so a failed build leaves the previous output intact.
+        pub fn build(&mut self) -> Result<BuildReport, BuildError> {
+            // One timestamp for the whole build, so every page shows the same time.
+            self.init_variables();
+            if self.dry_run {
+                return self.build_into_staging();
+            }
+            let _lock = self.lock_output()?;
+            self.load_hash_index()?;
+            if self.no_clean || self.only.is_some() {
+                fs::create_dir_all(&self.dest_dir)?;
+                let report = self.build_into_staging()?;
+                self.save_hash_index()?;
+                self.run_post_build()?;
+                return Ok(report);
+            }
+            let staging_dir = self.staging_path();
+            if staging_dir.exists() {
+                fs::remove_dir_all(&staging_dir)?;
+            }
+            fs::create_dir_all(&staging_dir)?;
+            self.staging_dir = Some(staging_dir.clone());
+
+            let result = self.build_into_staging();
+            self.staging_dir = None;
+            match result {
+                Ok(report) => {
+                    self.swap_into_place(&staging_dir)?;
+                    self.save_hash_index()?;
+                    self.run_post_build()?;
+                    Ok(report)
+                }
+                Err(e) => {
+                    if let Err(cleanup) = fs::remove_dir_all(&staging_dir) {
+                        log::error(&format!("Failed to remove directory [{}]: {}", staging_dir.display(), cleanup));
+                    }
+                    Err(e)
+                }
+            }
+        }
+
+        // <el-head> blocks from anywhere in the page move into its <head>, each distinct block
+        // once. Without a <head> their content stays where it was.
+        fn hoist_head_content(&self, file: &Path, page: &str) -> String {
+            let mut blocks: Vec<&str> = Vec::new();
+            for captures in self.directives.head().captures_iter(page) {
+                let block = captures.get(1).unwrap().as_str().trim();
+                if !blocks.contains(&block) {
+                    blocks.push(block);
+                }
+            }
+            if blocks.is_empty() {
+                return page.to_string();
+            }
+            let stripped = self.directives.head().replace_all(page, "");
+            match html::insert_into_head(&stripped, &blocks.concat()) {
+                Some(hoisted) => hoisted,
+                None => {
+                    self.warn_in(file, None, &format!("page [{}] has no <head> for <el-head> content", file.display()));
+                    self.directives.head().replace_all(page, "$1").to_string()
+                }
+            }
+        }
+
+        // Rebuilds only what the changed sources (relative to src_dir) affect, writing straight
+        // into dest_dir: dependent pages for components, layouts and includes, the page itself,
+        // or a fresh copy of an asset. Falls back to a full build when there is no previous
+        // build to go on or the change cannot be traced, e.g. a deleted file or a _layout.html.
+        pub fn rebuild(&mut self, changed: &[PathBuf]) -> Result<BuildReport, BuildError> {
+            // Locales rebuild every page once each, which only a full build does.
+            if self.page_dependencies.is_empty() || !self.locales.is_empty() {
+                return self.build();
+            }
+            let mut pages = Vec::new();
+            let mut assets = Vec::new();
+            for path in changed {
+                let path = path.strip_prefix(&self.src_dir).unwrap_or(path).to_path_buf();
+                let src_path = self.src_dir.join(&path);
+                if !self.source_fs.is_file(&src_path) || path.file_name().and_then(|s| s.to_str()) == Some(LAYOUT_MARKER) {
+                    return self.build();
+                }
+                if self.is_ignored_file(&path) {
+                    continue;
+                }
+                let dependents: Vec<PathBuf> = self.page_dependencies.iter()
+                    .filter(|(page, dependencies)| **page == path || dependencies.contains(&path))
+                    .map(|(page, _)| page.clone())
+                    .collect();
+                if !dependents.is_empty() {
+                    pages.extend(dependents);
+                } else if self.directory_to_ignore(&path) {
+                    continue;
+                } else if self.is_page(&path) {
+                    pages.push(path);
+                } else {
+                    assets.push(path);
+                }
+            }
+            pages.sort();
+            pages.dedup();
+
+            let _lock = if self.dry_run { None } else { Some(self.lock_output()?) };
+            // Expansions of unchanged files may embed changed ones, so nothing cached survives.
+            self.cache.lock().unwrap().clear();
+            self.component_index = OnceCell::new();
+            self.pure_components.clear();
+            self.bytes_written = 0;
+            self.bytes_saved = 0;
+            self.init_variables();
+            self.timing = Timing::default();
+            self.errors_reported.set(0);
+            let mut report = BuildReport::default();
+            for page in pages {
+                let default_layout = self.inherited_layout(&page);
+                let mut outputs = Vec::new();
+                if !self.directory_to_ignore(&page) {
+                    outputs.push(page.clone());
+                }
+                if let Some(name) = page.file_name().filter(|_| self.error_pages.contains(&page)) {
+                    if Path::new(name) != page {
+                        outputs.push(PathBuf::from(name));
+                    }
+                }
+                for output in outputs {
+                    match self.flatten_file_to(&page, &output, default_layout.as_deref()) {
+                        Ok(Some(dest_path)) => {
+                            report.written.push(dest_path);
+                            self.emit(BuildEvent::FileProcessed { path: page.clone() });
+                        }
+                        Ok(None) => {}
+                        Err(e) => self.report_error(&format!("Failed to process file [{}]", page.display()), e)?,
+                    }
+                }
+            }
+            for asset in assets {
+                if self.prune_unused_assets && !self.referenced_assets.contains(&self.asset_output(&asset)) {
+                    self.count_pruned(&asset);
+                    continue;
+                }
+                if let Some(dest_path) = self.copy_to_output(&asset)? {
+                    report.written.push(dest_path);
+                    self.emit(BuildEvent::FileProcessed { path: asset });
+                }
+            }
+            self.run_pending_copies()?;
+            self.check_errors_reported()?;
+            report.timing = std::mem::take(&mut self.timing);
+            report.bytes_written = std::mem::take(&mut self.bytes_written);
+            report.bytes_saved = std::mem::take(&mut self.bytes_saved);
+            report.reused = std::mem::take(&mut self.reused);
+            Ok(report)
+        }
+
+        // Runs the build like build() but returns what would be written, keyed by path relative
+        // to dest_dir, instead of touching the output directory. No post-build command runs.
+        pub fn build_to_memory(&mut self) -> Result<HashMap<PathBuf, Vec<u8>>, BuildError> {
+            self.init_variables();
+            self.memory_output = Some(HashMap::new());
+            let result = self.build_into_staging();
+            let memory_output = self.memory_output.take().unwrap_or_default();
+            result.map(|_| memory_output)
+        }
+
+        // Builds the site twice in memory, from an empty cache each time, and returns every
+        // output whose contents differ between the two, or that only one of them wrote.
+        pub fn check_deterministic(&mut self) -> Result<Vec<PathBuf>, BuildError> {
+            self.cache.lock().unwrap().clear();
+            let first = self.build_to_memory()?;
+            self.cache.lock().unwrap().clear();
+            let second = self.build_to_memory()?;
+            let mut differing: Vec<PathBuf> = first.keys().chain(second.keys())
+                .filter(|output| first.get(*output) != second.get(*output))
+                .cloned()
+                .collect();
+            differing.sort();
+            differing.dedup();
+            Ok(differing)
+        }
+
+        // Computed once per build. Defines override the built-in build.* variables.
+        fn init_variables(&mut self) {
+            self.variables = variables::build_variables(variables::build_timestamp(), &self.build_time_format);
+            self.variables.extend(self.defines.clone());
+            if self.version_meta && self.build_id.is_none() {
+                self.build_id = git_revision(&self.base_dir);
+            }
+        }
+
+        fn version_meta_tags(&self) -> String {
+            let mut tags = format!(r#"<meta name="generator" content="elss {}">"#, env!("CARGO_PKG_VERSION"));
+            if let Some(build_id) = &self.build_id {
+                tags.push_str(&format!(r#"<meta name="build-id" content="{}">"#, html::escape(build_id)));
+            }
+            tags
+        }
+
+        // {{ page.reading_time }} is in minutes at 200 words a minute, rounded up, and counts
+        // the page's content without its layout.
+        fn record_reading_time(&mut self, file: &Path, content: &str) {
+            let minutes = html::word_count(content).div_ceil(200).max(1);
+            self.reading_times.insert(file.to_path_buf(), minutes);
+        }
+
+        // A page's own front matter and <el-layout> attributes take precedence over everything
+        // global.
+        fn page_variables(&self, file: &Path) -> BTreeMap<String, String> {
+            let mut variables = self.variables.clone();
+            if let Some(minutes) = self.reading_times.get(file) {
+                variables.insert("page.reading_time".to_string(), minutes.to_string());
+            }
+            if let Some(front_matter) = self.front_matter.get(file) {
+                variables.extend(front_matter.iter().map(|(key, value)| (key.to_string(), value.to_string())));
+            }
+            // Extra attributes on the page's <el-layout> tag, e.g. title="Home", come last.
+            if let Some(layout_variables) = self.layout_variables.get(file) {
+                variables.extend(layout_variables.clone());
+            }
+            variables.extend(self.index_variables.clone());
+            variables
+        }
+
+        // Assets are read from src_dir; links are already relative to the output location.
+        fn bundle_page(&self, file: &Path, output: &Path, page: &str) -> String {
+            let page_dir = output.parent().unwrap_or(Path::new(""));
+            html::bundle_assets(page, |url| {
+                let asset = html::resolve_url(page_dir, url)?;
+                match self.source_fs.read(&self.src_dir.join(&asset)) {
+                    Ok(bytes) => Some(bytes),
+                    Err(e) => {
+                        self.warn_in(file, None, &format!("page [{}] references [{}], which could not be bundled: {}", file.display(), url, e));
+                        None
+                    }
+                }
+            })
+        }
+
+        // Hashes are taken from the sources, which assets are copied from unchanged. External
+        // URLs and missing files are skipped with a warning.
+        fn add_integrity(&self, file: &Path, output: &Path, page: &str) -> String {
+            let page_dir = output.parent().unwrap_or(Path::new(""));
+            html::add_integrity(page, |url| {
+                if !html::is_local_url(url) {
+                    self.warn_in(file, None, &format!("page [{}] loads external [{}], which gets no integrity attribute", file.display(), url));
+                    return None;
+                }
+                let asset = html::resolve_url(page_dir, url)?;
+                match self.source_fs.read(&self.src_dir.join(&asset)) {
+                    Ok(bytes) => Some(format!("sha384-{}", encoding::base64(&encoding::sha384(&bytes)))),
+                    Err(e) => {
+                        self.warn_in(file, None, &format!("page [{}] references [{}], which could not be hashed: {}", file.display(), url, e));
+                        None
+                    }
+                }
+            })
+        }
+
+        // <el-link to="/about">About</el-link> becomes an <a> keeping the tag's other attributes.
+        // Without content the link shows its target. Links are written as in the source, so
+        // relative ones are rebased with the rest of the page.
+        fn expand_links(&mut self, file: &Path, text: &str) -> Result<String, BuildError> {
+            let tags: Vec<_> = self.directives.link().captures_iter(text).collect();
+            let mut replacements = Vec::new();
+            for captures in &tags {
+                let attributes = directives::parse_attributes(captures.get(1).map_or("", |attributes| attributes.as_str()));
+                let to = match attributes.iter().find(|(key, _)| key.eq_ignore_ascii_case("to")) {
+                    Some((_, to)) => *to,
+                    None => {
+                        let message = format!("<{}> needs a to attribute", directives::LINK_TAG);
+                        self.report_error("Failed to expand link", BuildError::Malformed { file: file.to_path_buf(), message })?;
+                        replacements.push((captures.get(0).unwrap().range(), captures[0].to_string()));
+                        continue;
+                    }
+                };
+                let mut anchor = format!(r#"<a href="{}""#, to);
+                for (key, value) in attributes.iter().filter(|(key, _)| !key.eq_ignore_ascii_case("to")) {
+                    anchor.push_str(&format!(r#" {}="{}""#, key, value.replace('"', "&quot;")));
+                }
+                let content = captures.get(2).map_or_else(|| html::escape(to), |content| content.as_str().to_string());
+                anchor.push_str(&format!(">{}</a>", content));
+                replacements.push((captures.get(0).unwrap().range(), anchor));
+                if self.check_links && html::is_local_url(to) && !to.starts_with('#') {
+                    self.register_link(file, to);
+                }
+            }
+            if replacements.is_empty() {
+                return Ok(text.to_string());
+            }
+            Ok(splice(text, replacements.iter().map(|(range, replacement)| (range.clone(), replacement.as_str())), false))
+        }
+
+        // <el-asset-inline src="icon.svg" max="4096" /> puts an SVG's markup, or any other file
+        // as a data URI <img>, straight into the page when it is at most max bytes; larger
+        // files become a plain <img> referencing them. Without max every size is inlined.
+        // Other attributes go on the <img>, or on the root <svg> tag.
+        fn expand_asset_inlines(&mut self, file: &Path, text: &str) -> Result<String, BuildError> {
+            let tags: Vec<_> = self.directives.asset_inline().captures_iter(text).collect();
+            let mut replacements = Vec::new();
+            for captures in &tags {
+                let whole = captures.get(0).unwrap().range();
+                let attributes = directives::parse_attributes(captures.get(1).map_or("", |attributes| attributes.as_str()));
+                let find = |wanted: &str| attributes.iter().find(|(key, _)| key.eq_ignore_ascii_case(wanted)).map(|(_, value)| *value);
+                let malformed = |message: String| BuildError::Malformed { file: file.to_path_buf(), message };
+                let src = match find("src").filter(|src| html::is_local_url(src)) {
+                    Some(src) => src,
+                    None => {
+                        let error = malformed(format!("<{}> needs a local src", directives::ASSET_INLINE_TAG));
+                        self.report_error("Failed to inline asset", error)?;
+                        replacements.push((whole, String::new()));
+                        continue;
+                    }
+                };
+                let max = match find("max").map(|max| max.trim().parse::<u64>()) {
+                    None => None,
+                    Some(Ok(max)) => Some(max),
+                    Some(Err(_)) => {
+                        let error = malformed(format!("<{}> max [{}] is not a number of bytes", directives::ASSET_INLINE_TAG, find("max").unwrap_or_default()));
+                        self.report_error("Failed to inline asset", error)?;
+                        replacements.push((whole, String::new()));
+                        continue;
+                    }
+                };
+                let asset = match html::resolve_url(file.parent().unwrap_or(Path::new("")), src) {
+                    Some(asset) => asset,
+                    None => {
+                        self.report_error("Failed to inline asset", BuildError::PathTraversal(src.to_string()))?;
+                        replacements.push((whole, String::new()));
+                        continue;
+                    }
+                };
+                let src_path = self.src_dir.join(&asset);
+                let size = match self.source_fs.file_size(&src_path) {
+                    Ok(size) => size,
+                    Err(_) => {
+                        self.report_error("Failed to inline asset", BuildError::NotFound(src_path))?;
+                        replacements.push((whole, String::new()));
+                        continue;
+                    }
+                };
+                self.dependencies.insert(asset.clone());
+                let mut extra = String::new();
+                for (key, value) in attributes.iter().filter(|(key, _)| !key.eq_ignore_ascii_case("src") && !key.eq_ignore_ascii_case("max")) {
+                    extra.push_str(&format!(r#" {}="{}""#, key, value.replace('"', "&quot;")));
+                }
+                if max.is_some_and(|max| size > max) {
+                    replacements.push((whole, format!(r#"<img src="{}"{}>"#, src, extra)));
+                    continue;
+                }
+                let bytes = self.source_fs.read(&src_path)?;
+                let extension = asset.extension().and_then(|s| s.to_str()).unwrap_or_default();
+                let inlined = if extension.eq_ignore_ascii_case("svg") {
+                    let svg = String::from_utf8_lossy(&bytes);
+                    // An XML declaration is not allowed inside an HTML document.
+                    let svg = match svg.trim_start().strip_prefix("<?xml") {
+                        Some(rest) => rest.split_once("?>").map_or("", |(_, rest)| rest).trim_start().to_string(),
+                        None => svg.trim_start().to_string(),
+                    };
+                    match svg.find("<svg") {
+                        Some(open) if !extra.is_empty() => format!("{}{}{}", &svg[..open + 4], extra, &svg[open + 4..]),
+                        _ => svg,
+                    }
+                } else {
+                    format!(r#"<img src="data:{};base64,{}"{}>"#, encoding::mime_type(extension), encoding::base64(&bytes), extra)
+                };
+                replacements.push((whole, inlined.trim_end().to_string()));
+            }
+            if replacements.is_empty() {
+                return Ok(text.to_string());
+            }
+            Ok(splice(text, replacements.iter().map(|(range, replacement)| (range.clone(), replacement.as_str())), false))
+        }
+
+        fn register_link(&mut self, file: &Path, to: &str) {
+            // A link climbing out of the site can't be satisfied.
+            let target = match html::resolve_url(file.parent().unwrap_or(Path::new("")), to) {
+                Some(target) => target,
+                None => return self.links.push((file.to_path_buf(), to.to_string(), Vec::new())),
+            };
+            let mut candidates = vec![target.join(&self.index_file)];
+            if !target.as_os_str().is_empty() {
+                if target.extension().is_none() {
+                    candidates.push(target.with_extension("html"));
+                }
+                candidates.push(target);
+            }
+            let candidates = candidates.iter().filter_map(|candidate| self.map_output(candidate).ok()).collect();
+            self.links.push((file.to_path_buf(), to.to_string(), candidates));
+        }
+
+        fn report_dead_links(&mut self) -> Result<(), BuildError> {
+            for (file, link, candidates) in std::mem::take(&mut self.links) {
+                if !candidates.iter().any(|candidate| self.outputs.contains_key(candidate)) {
+                    self.report_error("Dead link", BuildError::DeadLink { file, link })?;
+                }
+            }
+            Ok(())
+        }
+
+        // <el-each-file dir="images" ext="jpg,png"> renders its body once per matching file
+        // directly in dir (relative to src_dir), in name order, with {{ file.name }},
+        // {{ file.stem }}, {{ file.ext }} and the root-relative {{ file.path }} set. Without
+        // ext every file matches.
+        fn expand_each_file(&mut self, file: &Path, text: &str) -> Result<String, BuildError> {
+            let blocks: Vec<_> = self.directives.each_file().captures_iter(text).collect();
+            let mut replacements = Vec::new();
+            for captures in &blocks {
+                let attributes = captures.get(1).map_or("", |attributes| attributes.as_str());
+                let body = captures.get(2).map_or("", |body| body.as_str());
+                let dir = directives::attribute(attributes, "dir").unwrap_or("");
+                let dir = match safe_relative_path(dir) {
+                    Some(dir) => dir,
+                    None if matches!(dir.trim_matches('/'), "" | ".") => PathBuf::new(),
+                    None => {
+                        self.report_error("Failed to list files", BuildError::PathTraversal(dir.to_string()))?;
+                        replacements.push((captures.get(0).unwrap().range(), String::new()));
+                        continue;
+                    }
+                };
+                let extensions: Vec<String> = directives::attribute(attributes, "ext").unwrap_or("")
+                    .split(',')
+                    .map(|ext| ext.trim().trim_start_matches('.').to_ascii_lowercase())
+                    .filter(|ext| !ext.is_empty())
+                    .collect();
+                let mut files = match self.source_fs.read_dir(&self.src_dir.join(&dir)) {
+                    Ok(entries) => entries,
+                    Err(e) => {
+                        let error = match e.kind() {
+                            std::io::ErrorKind::NotFound => BuildError::NotFound(self.src_dir.join(&dir)),
+                            _ => BuildError::IoError(e),
+                        };
+                        self.report_error(&format!("Failed to list files for [{}]", file.display()), error)?;
+                        Vec::new()
+                    }
+                };
+                files.retain(|entry| {
+                    let extension = entry.extension().and_then(|s| s.to_str()).unwrap_or_default().to_ascii_lowercase();
+                    !self.source_fs.is_dir(entry) && (extensions.is_empty() || extensions.contains(&extension))
+                });
+                files.sort();
+                let mut rendered = String::new();
+                for entry in files {
+                    let relative = dir.join(entry.file_name().unwrap_or_default());
+                    if self.is_ignored_file(&relative) {
+                        continue;
+                    }
+                    self.dependencies.insert(relative.clone());
+                    let mut variables = BTreeMap::new();
+                    variables.insert("file.name".to_string(), entry.file_name().and_then(|s| s.to_str()).unwrap_or_default().to_string());
+                    variables.insert("file.stem".to_string(), entry.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_string());
+                    variables.insert("file.ext".to_string(), entry.extension().and_then(|s| s.to_str()).unwrap_or_default().to_string());
+                    variables.insert("file.path".to_string(), format!("/{}", relative.to_string_lossy().replace('\\', "/")));
+                    rendered.push_str(&variables::substitute(body, &variables));
+                }
+                replacements.push((captures.get(0).unwrap().range(), rendered));
+            }
+            Ok(splice(text, replacements.iter().map(|(range, rendered)| (range.clone(), rendered.as_str())), self.trim_directives))
+        }
+
+        // <el-env name="..."> keeps its body only in a build for that --env.
+        fn expand_env_blocks(&self, text: &str) -> String {
+            self.directives.env().replace_all(text, |captures: &regex::Captures| {
+                if self.env.as_deref() == Some(&captures[1]) { captures[2].to_string() } else { String::new() }
+            }).to_string()
+        }
+
+        // <el-define name="..."> blocks are removed and every <el-use name="..." /> in the same
+        // file, before or after the definition, becomes a copy of one. Definitions are local to
+        // the file that makes them, and a snippet's own <el-use> tags are left as written.
+        fn expand_snippets(&self, path: &Path, text: &str) -> Result<String, BuildError> {
+            if !self.directives.define().is_match(text) && !self.directives.snippet_use().is_match(text) {
+                return Ok(text.to_string());
+            }
+            let mut snippets = HashMap::new();
+            let text = self.directives.define().replace_all(text, |captures: &regex::Captures| {
+                snippets.insert(captures[1].to_string(), captures[2].to_string());
+                String::new()
+            });
+            let mut unknown = Vec::new();
+            let text = self.directives.snippet_use().replace_all(&text, |captures: &regex::Captures| {
+                match snippets.get(&captures[1]) {
+                    Some(snippet) => snippet.clone(),
+                    None => {
+                        unknown.push(captures[1].to_string());
+                        captures[0].to_string()
+                    }
+                }
+            }).to_string();
+            for name in unknown {
+                self.report_error("Failed to expand snippet", BuildError::UnknownSnippet { file: path.to_path_buf(), name })?;
+            }
+            Ok(text)
+        }
+
+        // <el-switch var="..."> keeps the first <el-case> whose value equals the variable, else
+        // its <el-default>, else nothing. Unset variables compare as empty.
+        fn expand_switches(&self, text: &str, variables: &BTreeMap<String, String>) -> String {
+            self.directives.switch().replace_all(text, |captures: &regex::Captures| {
+                let value = variables.get(&captures[1]).map_or("", String::as_str);
+                let body = &captures[2];
+                self.directives.case().captures_iter(body)
+                    .find(|case| &case[1] == value)
+                    .and_then(|case| case.get(2))
+                    .or_else(|| self.directives.default_case().captures(body).and_then(|default| default.get(1)))
+                    .map_or(String::new(), |branch| branch.as_str().to_string())
+            }).to_string()
+        }
+
+        // Runs in base_dir through the platform shell, with ELSS_DEST_DIR pointing at the output.
+        fn run_post_build(&self) -> Result<(), BuildError> {
+            let command = match &self.post_build {
+                Some(command) => command,
+                None => return Ok(()),
+            };
+            let mut shell = if cfg!(windows) {
+                let mut shell = Command::new("cmd");
+                shell.arg("/C");
+                shell
+            } else {
+                let mut shell = Command::new("sh");
+                shell.arg("-c");
+                shell
+            };
+            let dest_dir = fs::canonicalize(&self.dest_dir).unwrap_or_else(|_| self.dest_dir.clone());
+            let status = shell.arg(command)
+                .current_dir(&self.base_dir)
+                .env("ELSS_DEST_DIR", dest_dir)
+                .status()
+                ?;
+            if !status.success() {
+                return Err(BuildError::PostBuildFailed(command.clone(), status.code()));
+            }
+            Ok(())
+        }
+
+        fn build_into_staging(&mut self) -> Result<BuildReport, BuildError> {
+            self.outputs.clear();
+            self.fragment_pages.clear();
+            self.included_files.clear();
+            self.timing = Timing::default();
+            self.errors_reported.set(0);
+            self.deferred_assets.clear();
+            self.referenced_assets.clear();
+            self.page_redirects.clear();
+            self.page_titles.clear();
+            self.reused.clear();
+            self.links.clear();
+            self.component_index = OnceCell::new();
+            self.pure_components.clear();
+            self.bytes_written = 0;
+            self.bytes_saved = 0;
+            self.load_critical_css()?;
+            if self.validate_references {
+                self.report_missing_references()?;
+            }
+            let mut report = BuildReport::default();
+            if !self.locales.is_empty() {
+                let global_variables = self.variables.clone();
+                let result = self.build_locales(&mut report);
+                self.variables = global_variables;
+                self.locale = None;
+                result?;
+            } else if let Some(only) = self.only.clone() {
+                let only = safe_relative_path(&only.to_string_lossy()).ok_or_else(|| BuildError::PathTraversal(only.display().to_string()))?;
+                if !self.source_fs.is_dir(&self.src_dir.join(&only)) {
+                    return Err(BuildError::NotFound(self.src_dir.join(&only)));
+                }
+                let default_layout = self.inherited_layout(&only);
+                self.process_files(&self.src_dir.join(&only), default_layout, &mut report)?;
+            } else if self.pages.is_empty() {
+                let src_dir = self.src_dir.clone();
+                self.process_files(&src_dir, None, &mut report)?;
+                self.build_error_pages(&mut report)?;
+                self.build_auto_indexes(&mut report)?;
+            } else {
+                self.process_pages(&mut report)?;
+            }
+            self.copy_referenced_assets(&mut report)?;
+            self.run_pending_copies()?;
+            if let Some(dest_path) = self.write_robots()? {
+                report.written.push(dest_path);
+            }
+            if let Some(dest_path) = self.write_redirects()? {
+                report.written.push(dest_path);
+            }
+            if self.nojekyll {
+                let output = Path::new(".nojekyll");
+                if self.claim_output(output, Path::new(CONFIG_FILE))? {
+                    self.write_file(output, Vec::new())?;
+                    report.written.push(self.dest_dir.join(output));
+                }
+            }
+            self.report_dead_links()?;
+            self.warn_about_fragments();
+            self.write_usage_report()?;
+            self.check_errors_reported()?;
+            report.timing = std::mem::take(&mut self.timing);
+            report.bytes_written = std::mem::take(&mut self.bytes_written);
+            report.bytes_saved = std::mem::take(&mut self.bytes_saved);
+            report.reused = std::mem::take(&mut self.reused);
+            Ok(report)
+        }
+
+        fn build_locales(&mut self, report: &mut BuildReport) -> Result<(), BuildError> {
+            let global_variables = self.variables.clone();
+            let fallback = match &self.default_locale {
+                Some(default_locale) => self.load_translations(default_locale)?,
+                None => BTreeMap::new(),
+            };
+            for locale in self.locales.clone() {
+                let mut translations = fallback.clone();
+                translations.extend(self.load_translations(&locale)?);
+                self.variables = global_variables.clone();
+                self.variables.extend(translations);
+                self.variables.insert("locale".to_string(), locale.clone());
+                self.locale = Some(locale);
+                let src_dir = self.src_dir.clone();
+                self.process_files(&src_dir, None, report)?;
+                self.build_error_pages(report)?;
+                self.build_auto_indexes(report)?;
+                self.copy_referenced_assets(report)?;
+            }
+            Ok(())
+        }
+
+        fn load_translations(&self, locale: &str) -> Result<BTreeMap<String, String>, BuildError> {
+            let path = self.base_dir.join(I18N_DIR).join(format!("{}.yaml", locale));
+            let text = self.source_fs.read_to_string(&path).map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => BuildError::NotFound(path.clone()),
+                _ => BuildError::IoError(e),
+            })?;
+            Ok(frontmatter::parse(&text).iter().map(|(key, value)| (format!("t.{}", key), value.to_string())).collect())
+        }
+
+        fn report_missing_references(&self) -> Result<(), BuildError> {
+            let missing = self.find_missing_references()?;
+            for reference in &missing {
+                let message = format!("Missing reference [{}] in [{}]: expected [{}]", reference.name, reference.file.display(), reference.expected.display());
+                log::error_at(&message, Some(&self.source_location(&reference.file)), None);
+            }
+            if self.strict && !missing.is_empty() {
+                return Err(BuildError::MissingReferences(missing.len()));
+            }
+            Ok(())
+        }
+
+        // Scans every HTML source, components and layouts included, for directive names that
+        // do not resolve to an existing file.
+        pub fn find_missing_references(&self) -> Result<Vec<MissingReference>, BuildError> {
+            let mut missing = Vec::new();
+            for file in self.source_files()? {
+                if !self.is_page(&file) {
+                    continue;
+                }
+                let text = self.source_fs.read_to_string(&self.src_dir.join(&file))?;
+                let (_, text) = frontmatter::split(&text);
+                let components = self.directives.component().captures_iter(text)
+                    .map(|captures| (directives::name(&captures).to_string(), &self.components_dir));
+                let layouts = self.directives.layout().captures_iter(text)
+                    .map(|captures| (directives::name(&captures).to_string(), &self.layout_dir));
+                for (name, dir) in components.chain(layouts).collect::<Vec<_>>() {
+                    if name.trim().is_empty() || (dir == &self.layout_dir && name == NO_LAYOUT) {
+                        continue;
+                    }
+                    // Ambiguous names are left for the build to report.
+                    let expected = if dir == &self.components_dir {
+                        match self.component_path(&file, &name) {
+                            Ok(component_path) => PathBuf::from(component_path),
+                            Err(_) => continue,
+                        }
+                    } else {
+                        Path::new(dir).join(name.trim_end_matches(".html").to_string() + ".html")
+                    };
+                    if !self.source_fs.is_file(&self.src_dir.join(&expected)) {
+                        missing.push(MissingReference { file: file.clone(), name: name.clone(), expected });
+                    }
+                }
+            }
+            Ok(missing)
+        }
+
+        // The components, layouts and includes a page (relative to src_dir) pulls in, directly
+        // or through one another, in a stable order. Nothing is written.
+        pub fn resolve_dependencies(&mut self, page: &Path) -> Result<Vec<PathBuf>, BuildError> {
+            let page = page.strip_prefix(&self.src_dir).unwrap_or(page).to_path_buf();
+            let default_layout = self.inherited_layout(&page);
+            let outer = std::mem::take(&mut self.dependencies);
+            let mut processing = HashSet::new();
+            let result = self.replace_components(&page, &mut processing)
+                .and_then(|content| self.replace_layout(&page, &content, default_layout.as_deref()));
+            let dependencies = std::mem::replace(&mut self.dependencies, outer);
+            result?;
+            let mut dependencies: Vec<PathBuf> = dependencies.into_iter().filter(|dependency| *dependency != page).collect();
+            dependencies.sort();
+            Ok(dependencies)
+        }
+
+        // Counts opening directive tags across every HTML source, components and layouts included.
+        pub fn directive_usage(&self) -> Result<DirectiveUsage, BuildError> {
+            let mut usage = DirectiveUsage::default();
+            for file in self.source_files()? {
+                if !self.is_page(&file) {
+                    continue;
+                }
+                let text = self.source_fs.read_to_string(&self.src_dir.join(&file))?;
+                let (_, text) = frontmatter::split(&text);
+                for tag in self.directives.any_directive().find_iter(text) {
+                    *usage.tags.entry(tag.as_str()[1..].to_string()).or_default() += 1;
+                }
+                for captures in self.directives.component().captures_iter(text) {
+                    usage.components.insert(directives::name(&captures).to_string());
+                }
+                for captures in self.directives.layout().captures_iter(text).chain(self.directives.wrapper_open().captures_iter(text)) {
+                    usage.layouts.insert(directives::name(&captures).to_string());
+                }
+            }
+            Ok(usage)
+        }
+
+        // Every TODO and FIXME comment in the HTML sources, by file and then line. Line numbers
+        // count the front matter, so they match the file as it is on disk.
+        pub fn todo_markers(&self) -> Result<Vec<TodoMarker>, BuildError> {
+            let mut markers = Vec::new();
+            for file in self.source_files()? {
+                if !self.is_page(&file) {
+                    continue;
+                }
+                let text = self.source_fs.read_to_string(&self.src_dir.join(&file))?;
+                for (line, marker, note) in html::todo_comments(&text) {
+                    markers.push(TodoMarker { file: file.clone(), line, marker: marker.to_string(), note });
+                }
+            }
+            Ok(markers)
+        }
+
+        // All files under src_dir, relative to it, in a stable order.
+        fn source_files(&self) -> Result<Vec<PathBuf>, BuildError> {
+            let mut files = Vec::new();
+            let mut pending = vec![self.src_dir.clone()];
+            while let Some(dir) = pending.pop() {
+                for entry_path in self.source_fs.read_dir(&dir)? {
+                    if self.source_fs.is_dir(&entry_path) {
+                        pending.push(entry_path);
+                    } else if let Ok(relative) = entry_path.strip_prefix(&self.src_dir) {
+                        if !self.is_ignored_file(relative) {
+                            files.push(relative.to_path_buf());
+                        }
+                    }
+                }
+            }
+            files.sort();
+            Ok(files)
+        }
+
+        fn load_critical_css(&mut self) -> Result<(), BuildError> {
+            self.critical_css_content = None;
+            if let Some(css_path) = &self.critical_css {
+                let src_path = self.src_dir.join(css_path);
+                match self.source_fs.read_to_string(&src_path) {
+                    Ok(css) => self.critical_css_content = Some(css),
+                    Err(e) => self.report_error(&format!("Failed to read critical CSS [{}]", src_path.display()), BuildError::IoError(e))?,
+                }
+            }
+            Ok(())
+        }
+
+        fn check_unknown_directives(&self, file: &Path, content: &str) -> Result<(), BuildError> {
+            for tag in self.directives.any_directive().find_iter(content) {
+                if tag.as_str()[1..] != *RAW_TAG {
+                    let error = BuildError::Malformed { file: file.to_path_buf(), message: format!("unresolved directive {}>", tag.as_str()) };
+                    self.report_error("Unknown directive", error)?;
+                }
+            }
+            Ok(())
+        }
+
+        // Anything still looking like a directive after expansion was most likely misspelled.
+        fn lint_leftover_directives(&self, file: &Path, content: &str) {
+            let mut reported = HashSet::new();
+            for tag in self.directives.any_directive().find_iter(content) {
+                let name = &tag.as_str()[1..];
+                if name == RAW_TAG || !reported.insert(name) {
+                    continue;
+                }
+                match self.directives.suggest(name) {
+                    Some(suggestion) => self.warn_in(file, None, &format!("page [{}] contains unknown directive <{}>, did you mean <{}>?", file.display(), name, suggestion)),
+                    None => self.warn_in(file, None, &format!("page [{}] contains unknown directive <{}>", file.display(), name)),
+                }
+            }
+        }
+
+        // Fragments pulled into other pages through an include are intentional.
+        fn warn_about_fragments(&self) {
+            for page in &self.fragment_pages {
+                if !self.included_files.contains(page) {
+                    self.warn_in(page, None, &format!("page [{}] has no <html> root element", page.display()));
+                }
+            }
+        }
+
+        // Held until the returned file is dropped. The lock sits next to dest_dir rather than
+        // inside it, since the staging swap replaces dest_dir.
+        fn lock_output(&self) -> Result<fs::File, BuildError> {
+            let name = self.dest_dir.file_name().and_then(|s| s.to_str()).unwrap_or("build");
+            let lock_path = self.dest_dir.with_file_name(format!(".{}.elss-lock", name));
+            if let Some(parent) = lock_path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)?;
+            }
+            let lock = fs::File::options().create(true).truncate(false).write(true).open(&lock_path)?;
+            if self.wait_for_lock {
+                lock.lock()?;
+                return Ok(lock);
+            }
+            match lock.try_lock() {
+                Ok(()) => Ok(lock),
+                Err(fs::TryLockError::WouldBlock) => Err(BuildError::BuildLocked(lock_path)),
+                Err(fs::TryLockError::Error(e)) => Err(BuildError::IoError(e)),
+            }
+        }
+
+        fn staging_path(&self) -> PathBuf {
+            let name = self.dest_dir.file_name().and_then(|s| s.to_str()).unwrap_or("build");
+            self.dest_dir.with_file_name(format!(".{}.elss-tmp", name))
+        }
+
+        // Directories cannot be exchanged in one rename, so the old output is moved aside
+        // first and only deleted after the new one is in place.
+        fn swap_into_place(&self, staging_dir: &Path) -> Result<(), BuildError> {
+            let backup_dir = staging_dir.with_extension("elss-old");
+            if backup_dir.exists() {
+                fs::remove_dir_all(&backup_dir)?;
+            }
+            let had_previous = self.dest_dir.exists();
+            if had_previous {
+                fs::rename(&self.dest_dir, &backup_dir)?;
+            }
+            if let Err(e) = fs::rename(staging_dir, &self.dest_dir) {
+                if had_previous {
+                    let _ = fs::rename(&backup_dir, &self.dest_dir);
+                }
+                return Err(BuildError::IoError(e));
+            }
+            if had_previous {
+                if let Err(e) = fs::remove_dir_all(&backup_dir) {
+                    log::error(&format!("Failed to remove directory [{}]: {}", backup_dir.display(), e));
+                }
+            }
+            Ok(())
+        }
+
+        fn output_root(&self) -> &Path {
+            self.staging_dir.as_deref().unwrap_or(&self.dest_dir)
+        }
+
+        fn build_error_pages(&mut self, report: &mut BuildReport) -> Result<(), BuildError> {
+            let default_layout = self.read_layout_marker(&self.src_dir);
+            for template in self.error_pages.clone() {
+                let output = match template.file_name() {
+                    Some(name) => PathBuf::from(name),
+                    None => continue,
+                };
+                if self.outputs.get(&output) == Some(&template) {
+                    continue;
+                }
+                match self.flatten_file_to(&template, &output, default_layout.as_deref()) {
+                    Ok(Some(dest_path)) => report.written.push(dest_path),
+                    Ok(None) => {}
+                    Err(e) => self.report_error(&format!("Failed to build error page [{}]", template.display()), e)?,
+                }
+            }
+            Ok(())
+        }
+
+        // Runs after the walk, so every page's title is known. Links are root-relative, which
+        // keeps them pointing at the pages wherever the template itself lives.
+        fn build_auto_indexes(&mut self, report: &mut BuildReport) -> Result<(), BuildError> {
+            let auto_index = match self.auto_index.clone() {
+                Some(auto_index) => auto_index,
+                None => return Ok(()),
+            };
+            let titles = std::mem::take(&mut self.page_titles);
+            let template = PathBuf::from(&auto_index.template);
+            for dir in &auto_index.dirs {
+                let dir = safe_relative_path(dir).ok_or_else(|| BuildError::PathTraversal(dir.clone()))?;
+                let output = dir.join(&self.index_file);
+                if self.source_fs.is_file(&self.src_dir.join(&output)) || titles.contains_key(&output) {
+                    continue;
+                }
+                let pages: Vec<&(PathBuf, String)> = titles.iter()
+                    .filter(|(unmapped, _)| unmapped.parent() == Some(dir.as_path()))
+                    .map(|(_, page)| page)
+                    .collect();
+                if pages.is_empty() {
+                    self.warn(&format!("auto_index directory [{}] has no pages, no index generated", dir.display()));
+                    continue;
+                }
+                let mut entries = String::from("<ul>\n");
+                for (page, title) in &pages {
+                    let url = page.to_string_lossy().replace('\\', "/");
+                    entries.push_str(&format!("<li><a href=\"/{}\">{}</a></li>\n", html::escape(&url), title));
+                }
+                entries.push_str("</ul>");
+                self.index_variables = BTreeMap::from([
+                    ("index.dir".to_string(), dir.to_string_lossy().replace('\\', "/")),
+                    ("index.count".to_string(), pages.len().to_string()),
+                    ("index.entries".to_string(), entries),
+                ]);
+                let default_layout = self.inherited_layout(&output);
+                let result = self.flatten_file_to(&template, &output, default_layout.as_deref());
+                self.index_variables.clear();
+                match result {
+                    Ok(Some(dest_path)) => report.written.push(dest_path),
+                    Ok(None) => {}
+                    Err(e) => self.report_error(&format!("Failed to build index for [{}]", dir.display()), e)?,
+                }
+            }
+            Ok(())
+        }
+
+        // Warnings go to the log and to any progress handler.
+        fn warn(&self, message: &str) {
+            log::warning(message);
+            self.emit(BuildEvent::Warning { message: message.to_string() });
+        }
+
+        // A warning about one source file, given relative to src_dir or under it, and a line
+        // in that file when the problem was found in the source rather than in the output.
+        fn warn_in(&self, file: &Path, line: Option<usize>, message: &str) {
+            log::warning_at(message, Some(&self.source_location(file)), line);
+            self.emit(BuildEvent::Warning { message: message.to_string() });
+        }
+
+        fn source_location(&self, file: &Path) -> PathBuf {
+            if file.starts_with(&self.src_dir) { file.to_path_buf() } else { self.src_dir.join(file) }
+        }
+
+        fn emit(&self, event: BuildEvent) {
+            if let Some(handler) = &self.progress_handler {
+                (handler.borrow_mut())(event);
+            }
+        }
+
+        // In strict mode problems abort the build; otherwise they are reported and skipped.
+        fn report_error(&self, context: &str, error: BuildError) -> Result<(), BuildError> {
+            // allowed_output_extensions is a safety net, so it fails the build even when not strict.
+            if self.strict || matches!(error, BuildError::DisallowedExtension(_)) {
+                return Err(error);
+            }
+            log::error_at(&format!("{}: {}", context, error), error.file().map(|file| self.source_location(file)).as_deref(), None);
+            self.errors_reported.set(self.errors_reported.get() + 1);
+            Ok(())
+        }
+
+        fn check_errors_reported(&self) -> Result<(), BuildError> {
+            match self.errors_reported.replace(0) {
+                count if self.keep_going && count > 0 => Err(BuildError::ErrorsReported(count)),
+                _ => Ok(()),
+            }
+        }
+
+        // Records which source produces each output. A second source mapping to the same
+        // output is a conflict; outside strict mode the first one wins.
+        fn claim_output(&mut self, output: &Path, source: &Path) -> Result<bool, BuildError> {
+            match self.outputs.get(output) {
+                Some(existing) if self.is_static(source) && !self.is_static(existing) => {
+                    self.warn_in(source, None, &format!("static file [{}] is shadowed by [{}], which also writes to [{}]", source.display(), existing.display(), output.display()));
+                    Ok(false)
+                }
+                Some(existing) if self.is_static(existing) && !self.is_static(source) => {
+                    self.warn_in(existing, None, &format!("static file [{}] is shadowed by [{}], which also writes to [{}]", existing.display(), source.display(), output.display()));
+                    let existing = existing.clone();
+                    self.pending_copies.retain(|copy| copy.asset != existing);
+                    self.outputs.insert(output.to_path_buf(), source.to_path_buf());
+                    Ok(true)
+                }
+                Some(existing) if existing != source => {
+                    let error = BuildError::DuplicateOutput {
+                        output: output.to_path_buf(),
+                        first: existing.clone(),
+                        second: source.to_path_buf(),
+                    };
+                    self.report_error("Skipping conflicting output", error)?;
+                    Ok(false)
+                }
+                _ => {
+                    self.outputs.insert(output.to_path_buf(), source.to_path_buf());
+                    Ok(true)
+                }
+            }
+        }
+
+        fn within_size_limit(&self, file: &Path, size: u64) -> Result<bool, BuildError> {
+            match self.max_file_size {
+                Some(limit) if size > limit => {
+                    self.report_error("Skipping oversized output", BuildError::FileTooLarge { file: file.to_path_buf(), size, limit })?;
+                    Ok(false)
+                }
+                _ => Ok(true),
+            }
+        }
+
+        fn copy_to_output(&mut self, path: &Path) -> Result<Option<PathBuf>, BuildError> {
+            let output = self.map_output(&self.asset_output(path))?;
+            if !self.claim_output(&output, path)? {
+                return Ok(None);
+            }
+            if self.postprocessors.contains_key(&output_extension(&output)) {
+                let content = self.postprocess(&output, self.source_fs.read_to_string(&self.src_dir.join(path))?);
+                if !self.within_size_limit(path, content.len() as u64)? {
+                    return Ok(None);
+                }
+                self.write_file(&output, content.into_bytes())?;
+                return Ok(Some(self.dest_dir.join(&output)));
+            }
+            let size = self.source_fs.file_size(&self.src_dir.join(path))?;
+            if !self.within_size_limit(path, size)? {
+                return Ok(None);
+            }
+            self.bytes_written += size;
+            if self.memory_output.is_some() {
+                let contents = self.source_fs.read(&self.src_dir.join(path))?;
+                if let Some(memory_output) = &mut self.memory_output {
+                    memory_output.insert(output.clone(), contents);
+                }
+            } else if !self.dry_run {
+                let copy = PendingCopy {
+                    asset: path.to_path_buf(),
+                    src_path: self.src_dir.join(path),
+                    write_path: self.output_root().join(&output),
+                    previous: self.dest_dir.join(&output),
+                };
+                if let Some(parent) = copy.write_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                if self.copy_jobs > 1 {
+                    self.pending_copies.push(copy);
+                } else {
+                    copy_asset(&*self.source_fs, &copy, self.skip_unchanged_assets, self.fsync)?;
+                }
+            }
+            Ok(Some(self.dest_dir.join(&output)))
+        }
+
+        // Workers take the next queued copy until none are left. Failures are reported in
+        // asset order once all of them are done.
+        fn run_pending_copies(&mut self) -> Result<(), BuildError> {
+            let copies = std::mem::take(&mut self.pending_copies);
+            let next = AtomicUsize::new(0);
+            let failures = Mutex::new(Vec::new());
+            let (source_fs, skip_unchanged, fsync) = (&*self.source_fs, self.skip_unchanged_assets, self.fsync);
+            std::thread::scope(|scope| {
+                for _ in 0..self.copy_jobs.min(copies.len()) {
+                    scope.spawn(|| {
+                        while let Some(copy) = copies.get(next.fetch_add(1, Ordering::Relaxed)) {
+                            if let Err(e) = copy_asset(source_fs, copy, skip_unchanged, fsync) {
+                                failures.lock().unwrap().push((copy.asset.clone(), e));
+                            }
+                        }
+                    });
+                }
+            });
+            let mut failures = failures.into_inner().unwrap();
+            failures.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (asset, e) in failures {
+                self.report_error(&format!("Failed to process file [{}]", asset.display()), BuildError::IoError(e))?;
+            }
+            Ok(())
+        }
+
+        fn directory_to_ignore(&self, path: &Path) -> bool {
+            path.starts_with(&self.components_dir) || path.starts_with(&self.layout_dir)
+        }
+
+        fn is_verbatim(&self, path: &Path) -> bool {
+            self.verbatim_dirs.iter().any(|dir| path.starts_with(dir)) || self.is_static(path)
+        }
+
+        fn is_static(&self, path: &Path) -> bool {
+            self.static_dir.as_ref().is_some_and(|dir| path.starts_with(dir))
+        }
+
+        // Where an asset is copied to before path mapping: static files lose the static_dir
+        // prefix, everything else mirrors its source.
+        fn asset_output(&self, path: &Path) -> PathBuf {
+            match &self.static_dir {
+                Some(dir) => path.strip_prefix(dir).unwrap_or(path).to_path_buf(),
+                None => path.to_path_buf(),
+            }
+        }
+
+        // HTML sources outside the verbatim directories are the ones that get flattened.
+        fn is_page(&self, path: &Path) -> bool {
+            let extension = path.extension().and_then(|s| s.to_str()).unwrap_or_default();
+            self.handlers.get(extension) == Some(&FileHandler::Flatten) && !self.is_verbatim(path)
+        }
+
+        fn is_ignored_file(&self, path: &Path) -> bool {
+            let file_name = path.file_name().and_then(|s| s.to_str()).unwrap_or_default();
+            let extension = path.extension().and_then(|s| s.to_str()).unwrap_or_default();
+            file_name.ends_with('~') || self.ignored_files.contains(file_name) || self.ignored_extensions.contains(extension)
+        }
+
+        fn process_file(&mut self, path: &Path, default_layout: Option<&str>) -> Result<Option<PathBuf>, BuildError> {
+            if self.is_ignored_file(path) || (path.file_name().and_then(|s| s.to_str()) == Some(LAYOUT_MARKER) && !self.is_verbatim(path)) {
+                return Ok(None);
+            }
+            if self.is_page(path) {
+                self.flatten_file(path, default_layout)
+            } else if self.prune_unused_assets {
+                // Which assets are used is only known once every page is flattened.
+                self.deferred_assets.push(path.to_path_buf());
+                Ok(None)
+            } else {
+                self.copy_to_output(path)
+            }
+        }
+
+        fn write_robots(&mut self) -> Result<Option<PathBuf>, BuildError> {
+            let robots = match &self.robots {
+                Some(robots) => robots.clone(),
+                None => return Ok(None),
+            };
+            let output = Path::new("robots.txt");
+            if !self.claim_output(output, Path::new(CONFIG_FILE))? {
+                return Ok(None);
+            }
+            let mut text = String::new();
+            if robots.rules.is_empty() {
+                text.push_str("User-agent: *\nAllow: /\n");
+            }
+            for rule in &robots.rules {
+                text.push_str(rule);
+                text.push('\n');
+            }
+            if let Some(sitemap) = &robots.sitemap {
+                text.push_str(&format!("\nSitemap: {}\n", sitemap));
+            }
+            self.write_file(output, text.into_bytes())?;
+            Ok(Some(self.dest_dir.join(output)))
+        }
+
+        // One entry per component or layout source, e.g.
+        // "el-components/card.html": {"count": 3, "pages": ["a.html", "b.html"]}.
+        fn write_usage_report(&mut self) -> Result<(), BuildError> {
+            let usage = match &mut self.usage {
+                Some(usage) => std::mem::take(usage),
+                None => return Ok(()),
+            };
+            if self.dry_run || self.memory_output.is_some() {
+                return Ok(());
+            }
+            let quote = |path: &Path| format!("\"{}\"", log::json_escape(&path.to_string_lossy().replace('\\', "/")));
+            let entries: Vec<String> = usage.iter().map(|(source, (count, pages))| {
+                let pages: Vec<String> = pages.iter().map(|page| quote(page)).collect();
+                format!("  {}: {{\"count\": {}, \"pages\": [{}]}}", quote(source), count, pages.join(", "))
+            }).collect();
+            fs::write(self.base_dir.join("usage.json"), format!("{{\n{}\n}}\n", entries.join(",\n")))?;
+            Ok(())
+        }
+
+        // `redirect_from: [/old, /older]` in a page's front matter sends those URLs to the page.
+        fn collect_redirects(&mut self, file: &Path, output: &Path) {
+            let sources = match self.front_matter.get(file).and_then(|front_matter| front_matter.get("redirect_from")) {
+                Some(sources) => frontmatter::list(sources),
+                None => return,
+            };
+            let mut url = output.to_string_lossy().replace('\\', "/");
+            if url == self.index_file || url.ends_with(&format!("/{}", self.index_file)) {
+                url.truncate(url.len() - self.index_file.len());
+            }
+            let to = match &self.mount {
+                Some(mount) => format!("/{}/{}", mount, url),
+                None => format!("/{}", url),
+            };
+            for from in sources {
+                self.page_redirects.push(Redirect { from, to: to.clone(), status: Some(301) });
+            }
+        }
+
+        fn write_redirects(&mut self) -> Result<Option<PathBuf>, BuildError> {
+            if self.redirects.is_empty() && self.page_redirects.is_empty() {
+                return Ok(None);
+            }
+            let output = Path::new("_redirects");
+            if !self.claim_output(output, Path::new(CONFIG_FILE))? {
+                return Ok(None);
+            }
+            let mut text = String::new();
+            for redirect in self.redirects.iter().chain(&self.page_redirects) {
+                text.push_str(&format!("{} {}", redirect.from, redirect.to));
+                if let Some(status) = redirect.status {
+                    text.push_str(&format!(" {}", status));
+                }
+                text.push('\n');
+            }
+            self.write_file(output, text.into_bytes())?;
+            Ok(Some(self.dest_dir.join(output)))
+        }
+
+        fn count_pruned(&mut self, asset: &Path) {
+            if let Ok(size) = self.source_fs.file_size(&self.src_dir.join(asset)) {
+                self.bytes_saved += size as i64;
+            }
+        }
+
+        fn copy_referenced_assets(&mut self, report: &mut BuildReport) -> Result<(), BuildError> {
+            for asset in std::mem::take(&mut self.deferred_assets) {
+                if !self.referenced_assets.contains(&self.asset_output(&asset)) {
+                    self.count_pruned(&asset);
+                    continue;
+                }
+                match self.copy_to_output(&asset) {
+                    Ok(Some(dest_path)) => {
+                        report.written.push(dest_path);
+                        self.emit(BuildEvent::FileProcessed { path: asset });
+                    }
+                    Ok(None) => {}
+                    Err(e) => self.report_error(&format!("Failed to process file [{}]", asset.display()), e)?,
+                }
+            }
+            Ok(())
+        }
+
+        // A _layout.html marker holds an <el-layout> tag naming the default layout for its
+        // directory and everything below it, until a deeper marker overrides it.
+        fn read_layout_marker(&self, dir: &Path) -> Option<String> {
+            let marker_path = dir.join(LAYOUT_MARKER);
+            if !self.source_fs.is_file(&marker_path) {
+                return None;
+            }
+            match self.source_fs.read_to_string(&marker_path) {
+                Ok(content) => self.directives.layout().captures(&content).map(|captures| directives::name(&captures).to_string()).filter(|name| !name.is_empty()),
+                Err(e) => {
+                    log::error(&format!("Failed to read file [{}]: {}", marker_path.display(), e));
+                    None
+                }
+            }
+        }
+
+        fn process_pages(&mut self, report: &mut BuildReport) -> Result<(), BuildError> {
+            for page in self.pages.clone() {
+                let default_layout = self.inherited_layout(&page);
+                match self.process_file(&page, default_layout.as_deref()) {
+                    Ok(Some(dest_path)) => {
+                        report.written.push(dest_path);
+                        self.emit(BuildEvent::FileProcessed { path: page });
+                    }
+                    Ok(None) => {}
+                    Err(e) => self.report_error(&format!("Failed to process file [{}]", page.display()), e)?,
+                }
+            }
+            Ok(())
+        }
+
+        // The default layout a page would get during a full walk, from the deepest marker
+        // between src_dir and the page's directory.
+        fn inherited_layout(&self, page: &Path) -> Option<String> {
+            let mut dir = self.src_dir.clone();
+            let mut layout = self.read_layout_marker(&dir);
+            for part in page.parent().into_iter().flat_map(Path::components) {
+                dir.push(part);
+                layout = self.read_layout_marker(&dir).or(layout);
+            }
+            layout
+        }
+
+        fn process_files(&mut self, path: &Path, default_layout: Option<String>, report: &mut BuildReport) -> Result<(), BuildError> {
+            let default_layout = self.read_layout_marker(path).or(default_layout);
+            let entries = match self.source_fs.read_dir(path) {
+                Ok(entries) => entries,
+                Err(e) => return self.report_error(&format!("Failed to read directory [{}]", path.display()), BuildError::IoError(e)),
+            };
+            for entry_path in entries {
+                let entry_path_relative = entry_path.strip_prefix(&self.src_dir).unwrap();
+                if self.source_fs.is_dir(&entry_path) {
+                    if self.directory_to_ignore(entry_path_relative) {
+                        continue;
+                    }
+                    self.process_files(&entry_path, default_layout.clone(), report)?;
+                } else {
+                    match self.process_file(entry_path_relative, default_layout.as_deref()) {
+                        Ok(Some(dest_path)) => {
+                            report.written.push(dest_path);
+                            self.emit(BuildEvent::FileProcessed { path: entry_path_relative.to_path_buf() });
+                        }
+                        Ok(None) => {}
+                        Err(e) => self.report_error(&format!("Failed to process file [{}]", entry_path_relative.display()), e)?,
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        // Conditional comments and anything inside <pre> or <el-raw> are kept as-is.
+        fn remove_comments(&self, content: &str) -> String {
+            self.comment_regex.replace_all(content, |captures: &regex::Captures| {
+                let matched = &captures[0];
+                if !matched.starts_with("<!--") || matched.starts_with("<!--[if") || matched.starts_with("<!--<![endif]") {
+                    matched.to_string()
+                } else {
+                    String::new()
+                }
+            }).to_string()
+        }
+
+        // Apache-style <!--#include virtual="..." --> directives, resolved against src_dir.
+        fn expand_includes(&mut self, file: &Path, text: &str, stack: &mut Vec<PathBuf>) -> Result<String, BuildError> {
+            let mut result = String::with_capacity(text.len());
+            let mut last = 0;
+            let all_captures: Vec<_> = self.directives.include().captures_iter(text).collect();
+            for captures in all_captures {
+                let whole = captures.get(0).unwrap();
+                result.push_str(&text[last..whole.start()]);
+                last = whole.end();
+
+                let include_path = match safe_relative_path(&captures[2]) {
+                    Some(include_path) => include_path,
+                    None => {
+                        self.report_error(&format!("Failed to include file in [{}]", file.display()), BuildError::PathTraversal(captures[2].to_string()))?;
+                        continue;
+                    }
+                };
+                if stack.contains(&include_path) {
+                    log::error_at(&format!("Circular include detected for [{}]", include_path.display()), Some(&self.source_location(file)), None);
+                    continue;
+                }
+                let src_path = self.src_dir.join(&include_path);
+                match self.source_fs.read_to_string(&src_path) {
+                    Ok(included) => {
+                        self.included_files.insert(include_path.clone());
+                        self.dependencies.insert(include_path.clone());
+                        stack.push(include_path.clone());
+                        let included = self.expand_includes(&include_path, &self.escape_markers(&included), stack)?;
+                        stack.pop();
+                        result.push_str(&included);
+                    }
+                    Err(e) => self.report_error(&format!("Failed to include file [{}]", src_path.display()), BuildError::IoError(e))?,
+                }
+            }
+            result.push_str(&text[last..]);
+            Ok(result)
+        }
+
+        // <el-include-if src="banner.html" var="promo" /> inlines src, resolved against src_dir
+        // like an include, when the page's promo variable is set to anything but "", "false"
+        // or "0", and otherwise leaves nothing. The file's own {{ }} placeholders are filled too.
+        fn expand_conditional_includes(&mut self, file: &Path, text: &str, variables: &BTreeMap<String, String>) -> Result<String, BuildError> {
+            let tags: Vec<_> = self.directives.include_if().captures_iter(text).collect();
+            let mut replacements = Vec::new();
+            for captures in &tags {
+                let whole = captures.get(0).unwrap().range();
+                let attributes = directives::parse_attributes(captures.get(1).map_or("", |attributes| attributes.as_str()));
+                let find = |wanted: &str| attributes.iter().find(|(key, _)| key.eq_ignore_ascii_case(wanted)).map(|(_, value)| *value);
+                let (src, var) = match (find("src"), find("var")) {
+                    (Some(src), Some(var)) => (src, var),
+                    _ => {
+                        let error = BuildError::Malformed { file: file.to_path_buf(), message: format!("<{}> needs src and var", directives::INCLUDE_IF_TAG) };
+                        self.report_error("Failed to include file", error)?;
+                        replacements.push((whole, String::new()));
+                        continue;
+                    }
+                };
+                let truthy = variables.get(var).is_some_and(|value| !matches!(value.trim(), "" | "false" | "0"));
+                if !truthy {
+                    replacements.push((whole, String::new()));
+                    continue;
+                }
+                let include_path = match safe_relative_path(src) {
+                    Some(include_path) => include_path,
+                    None => {
+                        self.report_error(&format!("Failed to include file in [{}]", file.display()), BuildError::PathTraversal(src.to_string()))?;
+                        replacements.push((whole, String::new()));
+                        continue;
+                    }
+                };
+                let src_path = self.src_dir.join(&include_path);
+                match self.source_fs.read_to_string(&src_path) {
+                    Ok(included) => {
+                        self.included_files.insert(include_path.clone());
+                        self.dependencies.insert(include_path);
+                        replacements.push((whole, self.escape_markers(&included).into_owned()));
+                    }
+                    Err(e) => {
+                        self.report_error(&format!("Failed to include file [{}]", src_path.display()), BuildError::IoError(e))?;
+                        replacements.push((whole, String::new()));
+                    }
+                }
+            }
+            Ok(splice(text, replacements.iter().map(|(range, replacement)| (range.clone(), replacement.as_str())), false))
+        }
+
+        fn resolve_component_name<'a>(&'a self, name: &'a str) -> &'a str {
+            self.aliases.get(name)
+                .or_else(|| self.global_components.get(name.trim_end_matches(".html")))
+                .map(String::as_str)
+                .unwrap_or(name)
+        }
+
+        // The source path a component name refers to, relative to src_dir. A bare name with
+        // no file directly in components_dir falls back to the one component anywhere below
+        // it with that file name, and is an error when there are several. An @root/ prefix
+        // looks in that component root instead.
+        fn component_path(&self, file: &Path, name: &str) -> Result<String, BuildError> {
+            let name = self.resolve_component_name(name).trim_end_matches(".html");
+            if let Some(rooted) = name.strip_prefix('@') {
+                let (root, rest) = rooted.split_once('/').unwrap_or((rooted, ""));
+                return match self.component_roots.get(root) {
+                    _ if rest.is_empty() => Err(BuildError::InvalidName { file: file.to_path_buf(), name: name.to_string() }),
+                    Some(dir) => Ok(format!("{}/{}.html", dir, rest)),
+                    None => Err(BuildError::UnknownComponentRoot { file: file.to_path_buf(), name: name.to_string() }),
+                };
+            }
+            let component_path = format!("{}/{}.html", self.components_dir, name);
+            if name.contains('/') || self.source_fs.is_file(&self.src_dir.join(&component_path)) {
+                return Ok(component_path);
+            }
+            match self.component_index().get(name).map(Vec::as_slice) {
+                Some([found]) => Ok(format!("{}/{}.html", self.components_dir, found)),
+                Some(candidates) => Err(BuildError::AmbiguousComponent {
+                    file: file.to_path_buf(),
+                    name: name.to_string(),
+                    candidates: candidates.iter().map(|found| Path::new(&self.components_dir).join(format!("{}.html", found))).collect(),
+                }),
+                None => Ok(component_path),
+            }
+        }
+
+        // Every component below components_dir by file name, built on first use in a build.
+        fn component_index(&self) -> &HashMap<String, Vec<String>> {
+            self.component_index.get_or_init(|| {
+                let mut index: HashMap<String, Vec<String>> = HashMap::new();
+                for file in self.source_files().unwrap_or_default() {
+                    let component = match file.strip_prefix(&self.components_dir) {
+                        Ok(component) if component.extension().and_then(|s| s.to_str()) == Some("html") => component.with_extension(""),
+                        _ => continue,
+                    };
+                    if let Some(stem) = component.file_name().and_then(|s| s.to_str()) {
+                        index.entry(stem.to_string()).or_default().push(component.to_string_lossy().replace('\\', "/"));
+                    }
+                }
+                for candidates in index.values_mut() {
+                    candidates.sort();
+                }
+                index
+            })
+        }
+
+        fn replace_components(&mut self, path: &Path, processing: &mut HashSet<String>) -> Result<String, BuildError> {
+            if self.progress {
+                log::file(path);
+            }
+            let src_path = self.src_dir.join(path);
+            let cache_key = self.source_fs.canonicalize(&src_path);
+
+            self.dependencies.insert(path.to_path_buf());
+            self.invocations.push(path.to_path_buf());
+            let cached = if self.use_cache { self.cache.lock().unwrap().get(&cache_key).cloned() } else { None };
+            // Pure components are reused within a build even when the cache is off.
+            if let Some(file_contents) = cached.or_else(|| self.pure_components.get(path).cloned()) {
+                if let Some(nested) = self.component_dependencies.get(path) {
+                    self.dependencies.extend(nested.iter().cloned());
+                }
+                if let Some(nested) = self.component_invocations.get(path) {
+                    self.invocations.extend(nested.iter().cloned());
+                }
+                return Ok(file_contents);
+            }
+
+            // Collect this file's own dependencies separately so cache hits can replay them.
+            let outer = std::mem::take(&mut self.dependencies);
+            let outer_invocations = std::mem::take(&mut self.invocations);
+            let result = self.load_components(path, &cache_key, processing);
+            let nested = std::mem::replace(&mut self.dependencies, outer);
+            let nested_invocations = std::mem::replace(&mut self.invocations, outer_invocations);
+            self.dependencies.extend(nested.iter().cloned());
+            self.invocations.extend(nested_invocations.iter().cloned());
+            self.component_dependencies.insert(path.to_path_buf(), nested);
+            self.component_invocations.insert(path.to_path_buf(), nested_invocations);
+            result
+        }
+
+        // A repeated tag reuses the first expansion, so its invocations are counted again here.
+        fn replay_invocations(&mut self, file: &Path, name: &str) {
+            if name.is_empty() {
+                return;
+            }
+            if let Ok(component_path) = self.component_path(file, name) {
+                let component_path = PathBuf::from(component_path);
+                let nested = self.component_invocations.get(&component_path).cloned().unwrap_or_default();
+                self.invocations.push(component_path);
+                self.invocations.extend(nested);
+            }
+        }
+
+        // Without text, the read failed and the file is checked for where it stops being UTF-8.
+        fn lint_encoding(&self, src_path: &Path, text: Option<&str>) {
+            if !self.encoding_lint {
+                return;
+            }
+            let text = match text {
+                Some(text) => text,
+                None => {
+                    let bytes = self.source_fs.read(src_path).unwrap_or_default();
+                    if let Err(e) = std::str::from_utf8(&bytes) {
+                        let line = bytes[..e.valid_up_to()].iter().filter(|&&byte| byte == b'\n').count() + 1;
+                        self.warn_in(src_path, Some(line), &format!("[{}] is not valid UTF-8 at byte {}", src_path.display(), e.valid_up_to()));
+                    }
+                    return;
+                }
+            };
+            let mut controls = text.char_indices().filter(|(_, c)| c.is_control() && !matches!(c, '\t' | '\n' | '\r'));
+            if let Some((offset, c)) = controls.next() {
+                let more = match controls.count() {
+                    0 => String::new(),
+                    count => format!(" and {} more", count),
+                };
+                self.warn_in(src_path, Some(text[..offset].matches('\n').count() + 1), &format!("[{}] has control character U+{:04X} at byte {}{}", src_path.display(), c as u32, offset, more));
+            }
+        }
+
+        fn load_components(&mut self, path: &Path, cache_key: &Path, processing: &mut HashSet<String>) -> Result<String, BuildError> {
+            let src_path = self.src_dir.join(path);
+            // Nested components are read while this one is expanded, so each level takes the
+            // spare buffer if it's free and hands its own back when done.
+            let mut text = std::mem::take(&mut self.read_buffer);
+            text.clear();
+            let read = self.source_fs.read_into(&src_path, &mut text);
+            self.lint_encoding(&src_path, read.as_ref().ok().map(|_| text.as_str()));
+            if let Err(e) = read {
+                let error = match e.kind() {
+                    std::io::ErrorKind::NotFound => BuildError::NotFound(src_path.clone()),
+                    _ => BuildError::IoError(e),
+                };
+                self.read_buffer = text;
+                self.report_error(&format!("Failed to read file [{}]", src_path.display()), error)?;
+                return Ok(String::new());
+            }
+            // An empty component or layout is usually a file that was never filled in. Pages are
+            // read with nothing in processing, layouts are recognised by their directory.
+            let included = !processing.is_empty() || path.starts_with(&self.layout_dir);
+            if self.warn_empty && text.trim().is_empty() && included {
+                self.warn_in(&src_path, None, &format!("[{}] is empty", src_path.display()));
+            }
+            let (front_matter, body) = frontmatter::split(&text);
+            self.front_matter.insert(path.to_path_buf(), front_matter);
+            let body = self.escape_markers(body);
+            let body = body.as_ref();
+            let top_level = processing.is_empty();
+            let result = self.expand_components(path, body, processing)?;
+            // A component without variables or a slot, whose own components are pure too,
+            // expands to the same bytes wherever it is used. Explain traces every expansion.
+            let pure = !top_level && self.trace.is_none()
+                && !body.contains("{{")
+                && !self.directives.slot().is_match(body)
+                && self.dependencies.iter().all(|dependency| self.pure_components.contains_key(dependency));
+            if pure {
+                self.pure_components.insert(path.to_path_buf(), result.clone());
+            }
+            self.read_buffer = text;
+
+            // Deduplicated expansions refer to templates that are only emitted with this page.
+            if self.use_cache && !(top_level && self.shared_components.is_some()) {
+                self.cache.lock().unwrap().insert(cache_key.to_path_buf(), result.clone());
+            }
+            Ok(result)
+        }
+
+        fn expand_components(&mut self, path: &Path, text: &str, processing: &mut HashSet<String>) -> Result<String, BuildError> {
+            let text = if self.enable_ssi { self.expand_includes(path, text, &mut Vec::new())? } else { text.to_string() };
+            let text = self.expand_snippets(path, &text)?;
+
+            let (components, unterminated) = self.directives.components(&text);
+            let opening_tags = self.directives.component_open().find_iter(&text).count();
+            if unterminated || opening_tags > self.directives.component().find_iter(&text).count() {
+                let error = BuildError::Malformed { file: path.to_path_buf(), message: "unterminated component tag".to_string() };
+                self.report_error("Failed to resolve component", error)?;
+            }
+            // Expansions are spliced in one pass at the end. Repeats of the same tag reuse
+            // the first expansion.
+            let mut repeats: HashMap<&str, usize> = HashMap::new();
+            for tag in &components {
+                *repeats.entry(tag.as_str()).or_default() += 1;
+            }
+            let mut expansions: Vec<String> = Vec::new();
+            let mut expanded: HashMap<&str, usize> = HashMap::new();
+            let mut replacements = Vec::new();
+            for tag in &components {
+                let attributes = tag.attributes();
+                let bypass_cache = directives::attribute(attributes, "cache") == Some("false");
+                if let (false, Some(&index)) = (bypass_cache, expanded.get(tag.as_str())) {
+                    replacements.push((tag.range(), index));
+                    if self.usage.is_some() {
+                        self.replay_invocations(path, tag.name());
+                    }
+                    continue;
+                }
+                let src = tag.name();
+                let url = directives::attribute(attributes, "src");
+                if let (true, Some(url)) = (src.is_empty(), url) {
+                    let content = self.bypassing_cache(bypass_cache, |builder| builder.fetch_remote(path, url))?.unwrap_or_default();
+                    expanded.insert(tag.as_str(), expansions.len());
+                    replacements.push((tag.range(), expansions.len()));
+                    expansions.push(content);
+                    continue;
+                }
+                if src.trim().is_empty() {
+                    self.report_error("Failed to resolve component", BuildError::EmptyDirectiveName(path.to_path_buf()))?;
+                    continue;
+                }
+                if !is_valid_name(src.strip_prefix('@').unwrap_or(src)) {
+                    let error = BuildError::InvalidName { file: path.to_path_buf(), name: src.to_string() };
+                    self.report_error("Failed to resolve component", error)?;
+                    continue;
+                }
+                let component_path = match self.component_path(path, src) {
+                    Ok(component_path) => component_path,
+                    Err(e) => {
+                        self.report_error("Failed to resolve component", e)?;
+                        continue;
+                    }
+                };
+
+                if processing.contains(&component_path) {
+                    self.report_error("Failed to resolve component", BuildError::Circular(PathBuf::from(&component_path)))?;
+                    continue;
+                }
+                let src_path = self.src_dir.join(&component_path);
+                let exists = self.source_fs.is_file(&src_path);
+                self.trace(|| {
+                    let indent = "  ".repeat(processing.len());
+                    if exists {
+                        format!("{}component [{}] in [{}] resolved to [{}]", indent, src, path.display(), component_path)
+                    } else {
+                        format!("{}component [{}] in [{}] is missing, looked for [{}]", indent, src, path.display(), component_path)
+                    }
+                });
+                if let Some(placeholder) = self.missing_component.clone().filter(|_| !exists) {
+                    self.report_error(&format!("Failed to read file [{}]", src_path.display()), BuildError::NotFound(src_path))?;
+                    let variables = BTreeMap::from([("name".to_string(), src.to_string())]);
+                    expanded.insert(tag.as_str(), expansions.len());
+                    replacements.push((tag.range(), expansions.len()));
+                    expansions.push(variables::substitute(&placeholder, &variables));
+                    continue;
+                }
+
+                processing.insert(component_path.clone());
+                let mut file_contents = self.bypassing_cache(bypass_cache, |builder| builder.replace_components(Path::new(&component_path), processing))?;
+                processing.remove(&component_path);
+                if self.scoped_styles {
+                    file_contents = html::scope_component(&file_contents, &css::scope_attribute(&component_path));
+                }
+                let file_contents = self.mark_source(Path::new(&component_path), file_contents);
+                let file_contents = self.fill_slot(path, &component_path, &file_contents, tag.content(), processing)?;
+
+                let expansion = match self.shared_components.as_mut() {
+                    Some(shared) if processing.is_empty() && repeats[tag.as_str()] > 1 && file_contents.len() >= DEDUPE_MIN_LEN => {
+                        let placeholder = format!(r#"<template data-el-use="el-shared-{}"></template>"#, shared.len());
+                        shared.push(file_contents);
+                        placeholder
+                    }
+                    _ => file_contents,
+                };
+                expanded.insert(tag.as_str(), expansions.len());
+                replacements.push((tag.range(), expansions.len()));
+                expansions.push(expansion);
+            }
+            let replacements = replacements.into_iter().map(|(range, index)| (range, expansions[index].as_str()));
+            let result = splice(&text, replacements, self.trim_directives);
+            self.expand_wrappers(path, result)
+        }
+
+        // <el-slot /> in a component takes the content its invocation encloses, expanded where
+        // the invocation is. <el-slot>fallback</el-slot> keeps the fallback when the invocation
+        // closes itself or encloses only whitespace.
+        fn fill_slot(&mut self, path: &Path, component_path: &str, component: &str, content: Option<&str>, processing: &mut HashSet<String>) -> Result<String, BuildError> {
+            if !self.directives.slot().is_match(component) {
+                return Ok(component.to_string());
+            }
+            let content = match content.filter(|content| !content.trim().is_empty()) {
+                Some(content) => Some(self.expand_components(path, content, processing)?),
+                None => None,
+            };
+            self.trace(|| match &content {
+                Some(content) => format!("{}slot in [{}] filled with {} bytes from [{}]", "  ".repeat(processing.len()), component_path, content.len(), path.display()),
+                None => format!("{}slot in [{}] kept its fallback", "  ".repeat(processing.len()), component_path),
+            });
+            Ok(self.directives.slot().replace_all(component, |captures: &regex::Captures| {
+                match &content {
+                    Some(content) => content.clone(),
+                    None => captures.get(1).map_or("", |fallback| fallback.as_str()).to_string(),
+                }
+            }).to_string())
+        }
+
+        // cache="false" on a component tag resolves that one invocation, and everything it
+        // pulls in, from source even when caching is on. Nothing it reads is cached, and pure
+        // components expanded before are expanded again.
+        fn bypassing_cache<T>(&mut self, bypass: bool, resolve: impl FnOnce(&mut Self) -> T) -> T {
+            if !bypass {
+                return resolve(self);
+            }
+            let use_cache = std::mem::replace(&mut self.use_cache, false);
+            let pure_components = std::mem::take(&mut self.pure_components);
+            let result = resolve(self);
+            self.use_cache = use_cache;
+            self.pure_components = pure_components;
+            result
+        }
+
+        // Remote content is inlined as-is, without expanding any directives it contains. Failed
+        // fetches only abort strict builds; otherwise the tag is dropped with a warning.
+        fn fetch_remote(&mut self, path: &Path, url: &str) -> Result<Option<String>, BuildError> {
+            if !remote::is_remote(url) {
+                let error = BuildError::Malformed { file: path.to_path_buf(), message: format!("component src [{}] is not an http or https URL", url) };
+                self.report_error("Failed to resolve component", error)?;
+                return Ok(None);
+            }
+            if !self.allow_remote {
+                let error = BuildError::Malformed { file: path.to_path_buf(), message: format!("remote component [{}] is not allowed", url) };
+                self.report_error("Failed to resolve component", error)?;
+                return Ok(None);
+            }
+            let cache_dir = self.base_dir.join(".elss-cache").join("remote");
+            match remote::fetch(url, self.remote_timeout, &cache_dir, !self.use_cache) {
+                Ok(content) => Ok(Some(content)),
+                Err(message) if self.strict => Err(BuildError::RemoteFetch { url: url.to_string(), message }),
+                Err(message) => {
+                    self.warn_in(path, None, &BuildError::RemoteFetch { url: url.to_string(), message }.to_string());
+                    Ok(None)
+                }
+            }
+        }
+
+        // <el-wrapper name="..."> applies a layout to just its inner content. Wrappers are
+        // expanded innermost first so they can be nested.
+        fn expand_wrappers(&mut self, path: &Path, mut text: String) -> Result<String, BuildError> {
+            while let Some(close) = text.find(self.directives.wrapper_close()) {
+                let (open, name) = match self.directives.wrapper_open().captures_iter(&text[..close]).last() {
+                    Some(captures) => (captures.get(0).unwrap().range(), directives::name(&captures).to_string()),
+                    None => {
+                        let error = BuildError::Malformed { file: path.to_path_buf(), message: "closing wrapper tag without an opening tag".to_string() };
+                        self.report_error("Failed to resolve wrapper", error)?;
+                        break;
+                    }
+                };
+                let (whole, inner) = self.trim_region(&text, open.start..close + self.directives.wrapper_close().len(), open.end..close);
+                let inner = text[inner].to_string();
+                let mut wrapped = if name.trim().is_empty() {
+                    self.report_error("Failed to resolve wrapper", BuildError::EmptyDirectiveName(path.to_path_buf()))?;
+                    inner
+                } else if !is_valid_name(&name) {
+                    self.report_error("Failed to resolve wrapper", BuildError::InvalidName { file: path.to_path_buf(), name })?;
+                    inner
+                } else {
+                    self.apply_layout(&name, &inner)?
+                };
+                if whole.end > close + self.directives.wrapper_close().len() && !wrapped.ends_with('\n') {
+                    wrapped.push('\n');
+                }
+                text.replace_range(whole, &wrapped);
+            }
+            Ok(text)
+        }
+        
+        // For a directive with an opening and a closing tag: with trim_directives, each tag
+        // alone on its line takes that whole line, growing whole and shrinking inner.
+        fn trim_region(&self, text: &str, whole: Range<usize>, inner: Range<usize>) -> (Range<usize>, Range<usize>) {
+            if !self.trim_directives {
+                return (whole, inner);
+            }
+            let (mut whole, mut inner) = (whole, inner);
+            if let Some(line) = directive_line(text, whole.start..inner.start).filter(|line| line.end <= inner.end) {
+                (whole.start, inner.start) = (line.start, line.end);
+            }
+            if let Some(line) = directive_line(text, inner.end..whole.end).filter(|line| line.start >= inner.start) {
+                (inner.end, whole.end) = (line.start, line.end);
+            }
+            (whole, inner)
+        }
+
+        // An empty <el-layout name="..."></el-layout> wraps the rest of the page; one with
+        // inner content wraps just that region. Either way the tag itself is dropped and the
+        // page content around it is kept.
+        fn replace_layout(&mut self, file: &Path, content: &str, default_layout: Option<&str>) -> Result<String, BuildError> {
+            if self.legacy_layout {
+                return self.replace_layout_legacy(file, content);
+            }
+            self.layout_variables.remove(file);
+            let mut close_trimmed = false;
+            let (name, before, region, after) = match self.directives.layout().captures(content) {
+                Some(captures) => {
+                    let whole = captures.get(0).unwrap().range();
+                    let inner = captures.get(2).map_or(whole.end..whole.end, |inner| inner.range());
+                    let (trimmed, inner) = if content[inner.clone()].trim().is_empty() {
+                        let line = directive_line(content, whole.clone()).filter(|_| self.trim_directives);
+                        (line.unwrap_or(whole.clone()), inner.start..inner.start)
+                    } else {
+                        self.trim_region(content, whole.clone(), inner)
+                    };
+                    close_trimmed = trimmed.end > whole.end;
+                    let (before, inner, after) = (&content[..trimmed.start], &content[inner], &content[trimmed.end..]);
+                    let name = directives::name(&captures).to_string();
+                    let attributes = captures.get(1).map_or("", |attributes| attributes.as_str());
+                    let variables = directives::parse_attributes(attributes).into_iter()
+                        .filter(|(key, _)| !key.eq_ignore_ascii_case("name"))
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .collect();
+                    self.layout_variables.insert(file.to_path_buf(), variables);
+                    if inner.trim().is_empty() {
+                        (name, "", format!("{}{}", before, after), "")
+                    } else {
+                        (name, before, inner.to_string(), after)
+                    }
+                }
+                // A front matter `layout` wins over the directory default, and `layout: false`
+                // turns the default off.
+                None => match self.front_matter.get(file).and_then(|front_matter| front_matter.get("layout")).or(default_layout) {
+                    Some("false") | None => return Ok(content.to_string()),
+                    Some(name) => (name.to_string(), "", content.to_string(), ""),
+                },
+            };
+            if name == NO_LAYOUT {
+                self.trace(|| format!("layout turned off for [{}]", file.display()));
+                return Ok(format!("{}{}{}", before, region, after));
+            }
+            if name.trim().is_empty() {
+                self.report_error("Failed to resolve layout", BuildError::EmptyDirectiveName(file.to_path_buf()))?;
+                return Ok(content.to_string());
+            }
+            if !is_valid_name(&name) {
+                self.report_error("Failed to resolve layout", BuildError::InvalidName { file: file.to_path_buf(), name })?;
+                return Ok(content.to_string());
+            }
+            let mut wrapped = self.apply_layout(&name, &region)?;
+            if close_trimmed && !after.is_empty() && !wrapped.ends_with('\n') {
+                wrapped.push('\n');
+            }
+            Ok(format!("{}{}{}", before, wrapped, after))
+        }
+
+        // A single pass with the original tag pattern, see Directives::legacy_layout: only the
+        // first layout tag counts, the layout is not itself laid out, and every <el-content />
+        // receives the whole page, layout tag included. Front matter and directory defaults
+        // are ignored. Two things differ from the original: `$1` or `$name` in the page is
+        // inserted literally rather than expanded as a capture group, and a layout name that
+        // could leave el-layouts is rejected like in the current pass.
+        fn replace_layout_legacy(&mut self, file: &Path, content: &str) -> Result<String, BuildError> {
+            let name = match self.directives.legacy_layout().captures(content) {
+                Some(captures) => captures[1].to_string(),
+                None => return Ok(content.to_string()),
+            };
+            if !is_valid_name(&name) {
+                self.report_error("Failed to resolve layout", BuildError::InvalidName { file: file.to_path_buf(), name })?;
+                return Ok(content.to_string());
+            }
+            let file_path = format!("{}/{}.html", self.layout_dir, name.trim_end_matches(".html"));
+            self.trace(|| format!("layout [{}] applied from [{}] in legacy mode", name, file_path));
+            let mut processing = HashSet::new();
+            let file_contents = self.replace_components(Path::new(&file_path), &mut processing)?;
+            let file_contents = self.mark_source(Path::new(&file_path), file_contents);
+            Ok(self.directives.layout_content().replace_all(&file_contents, regex::NoExpand(content)).to_string())
+        }
+
+        fn apply_layout(&mut self, name: &str, content: &str) -> Result<String, BuildError> {
+            let file_path = format!("{}/{}", self.layout_dir, name.trim_end_matches(".html").to_string() + ".html");
+            if self.layout_chain.contains(&file_path) {
+                self.report_error("Failed to apply layout", BuildError::Circular(PathBuf::from(&file_path)))?;
+                return Ok(content.to_string());
+            }
+            self.trace(|| format!("layout [{}] applied from [{}]", name, file_path));
+            let filled = self.fill_layout(&file_path, content)?;
+            // A layout may itself use a layout, e.g. post inside base. The filled result goes
+            // through the same layout pass as a page, so the body ends up inside both.
+            self.layout_chain.push(file_path.clone());
+            let result = self.replace_layout(Path::new(&file_path), &filled, None);
+            self.layout_chain.pop();
+            result
+        }
+
+        fn fill_layout(&mut self, file_path: &str, content: &str) -> Result<String, BuildError> {
+            let mut processing = HashSet::new();
+            let file_contents = self.replace_components(Path::new(file_path), &mut processing)?;
+            let file_contents = self.mark_source(Path::new(file_path), file_contents);
+            let placeholders = self.directives.layout_content().find_iter(&file_contents).count();
+            if placeholders == 0 {
+                return match self.missing_content {
+                    MissingContent::Drop => Ok(file_contents),
+                    MissingContent::Append => Ok(file_contents + content),
+                    MissingContent::Error => {
+                        self.report_error("Failed to apply layout", BuildError::MissingContent(PathBuf::from(file_path)))?;
+                        Ok(file_contents)
+                    }
+                };
+            }
+            // Every <el-content /> in a layout receives the full page body. This is intended,
+            // e.g. for a layout that repeats the body in a print-only section.
+            if self.warn_duplicate_content && placeholders > 1 {
+                self.warn_in(Path::new(file_path), None, &format!("layout [{}] inserts the page body {} times", file_path, placeholders));
+            }
+            let placeholders = self.directives.layout_content().find_iter(&file_contents).map(|placeholder| (placeholder.range(), content));
+            Ok(splice(&file_contents, placeholders, self.trim_directives))
+        }
+    }
+
+    fn output_extension(output: &Path) -> String {
+        output.extension().map_or(String::new(), |extension| extension.to_string_lossy().to_ascii_lowercase())
+    }
+
+    // The short hash of HEAD, or None outside a git checkout or without git installed.
+    fn git_revision(dir: &Path) -> Option<String> {
+        let output = Command::new("git").arg("-C").arg(dir).args(["rev-parse", "--short", "HEAD"]).output().ok()?;
+        let revision = String::from_utf8(output.stdout).ok()?.trim().to_string();
+        (output.status.success() && !revision.is_empty()).then_some(revision)
+    }
+
+    fn copy_asset(source_fs: &dyn SourceFs, copy: &PendingCopy, skip_unchanged: bool, fsync: bool) -> std::io::Result<()> {
+        if skip_unchanged && copy.write_path != copy.previous && is_unchanged(source_fs, &copy.src_path, &copy.previous) {
+            // The staging directory replaces dest_dir, so the previous copy is linked over
+            // rather than duplicated.
+            if fs::hard_link(&copy.previous, &copy.write_path).is_ok() {
+                return Ok(());
+            }
+        }
+        source_fs.copy(&copy.src_path, &copy.write_path)?;
+        if skip_unchanged {
+            // Matching mtimes are what marks the copy as unchanged on the next build.
+            if let Some(modified) = source_fs.modified(&copy.src_path) {
+                fs::File::options().write(true).open(&copy.write_path)?.set_modified(modified)?;
+            }
+        }
+        if fsync {
+            fs::File::options().write(true).open(&copy.write_path)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    // Size and modification time are enough to treat an asset as unchanged.
+    fn is_unchanged(source_fs: &dyn SourceFs, src_path: &Path, previous: &Path) -> bool {
+        match (source_fs.file_size(src_path), source_fs.modified(src_path), fs::metadata(previous)) {
+            (Ok(size), Some(modified), Ok(previous)) => size == previous.len() && previous.modified().ok() == Some(modified),
+            _ => false,
+        }
+    }
+
+    // Puts each replacement at its range in one pass; ranges must be in order and must not
+    // overlap. With trim, a range alone on its line takes that whole line instead.
+    fn splice<'r>(text: &str, replacements: impl IntoIterator<Item = (Range<usize>, &'r str)>, trim: bool) -> String {
+        let mut result = String::with_capacity(text.len());
+        let mut last = 0;
+        for (range, replacement) in replacements {
+            match directive_line(text, range.clone()).filter(|line| trim && line.start >= last) {
+                Some(line) => {
+                    result.push_str(&text[last..line.start]);
+                    let replacement = replacement.trim_end_matches(['\r', '\n']);
+                    if !replacement.trim().is_empty() {
+                        result.push_str(replacement);
+                        if text[..line.end].ends_with('\n') {
+                            result.push('\n');
+                        }
+                    }
+                    last = line.end;
+                }
+                None => {
+                    result.push_str(&text[last..range.start]);
+                    result.push_str(replacement);
+                    last = range.end;
+                }
+            }
+        }
+        result.push_str(&text[last..]);
+        result
+    }
+
+    // The whole line around range, line break included, when nothing but whitespace shares it.
+    fn directive_line(text: &str, range: Range<usize>) -> Option<Range<usize>> {
+        let line_start = text[..range.start].rfind('\n').map_or(0, |newline| newline + 1);
+        let line_end = text[range.end..].find('\n').map_or(text.len(), |newline| range.end + newline + 1);
+        let blank = text[line_start..range.start].trim().is_empty() && text[range.end..line_end].trim().is_empty();
+        blank.then_some(line_start..line_end)
+    }
+
+    // Directive names become file paths, so anything that could be read as a glob, query
+    // or fragment is rejected up front, as is anything that could leave the source tree.
+    fn is_valid_name(name: &str) -> bool {
+        name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'))
+            && !name.starts_with('/')
+            && safe_relative_path(name).is_some()
+    }
+
+    // Resolves a directive path against a source root, refusing anything that could escape it.
+    fn safe_relative_path(name: &str) -> Option<PathBuf> {
+        let mut path = PathBuf::new();
+        for component in Path::new(name.trim_start_matches('/')).components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::CurDir => {}
+                _ => return None,
+            }
+        }
+        if path.as_os_str().is_empty() { None } else { Some(path) }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::sourcefs::MemoryFs;
+        use std::io;
+
+        // A builder over an in-memory site, with sources given relative to src/.
+        fn site(files: &[(&str, &str)]) -> SiteBuilder {
+            let fs: MemoryFs = files.iter().map(|(path, contents)| (Path::new("site/src").join(path), *contents)).collect();
+            SiteBuilder::new(PathBuf::from("site")).with_source_fs(fs).with_progress(false)
+        }
+
+        // Every output as text, keyed by its path in dest_dir with forward slashes.
+        fn build(mut builder: SiteBuilder) -> BTreeMap<String, String> {
+            builder.build_to_memory().unwrap().into_iter()
+                .map(|(path, bytes)| (path.to_string_lossy().replace('\\', "/"), String::from_utf8_lossy(&bytes).into_owned()))
+                .collect()
+        }
+
+        // A site written to a fresh directory under the system temp dir, for tests of what a
+        // build does on disk.
+        fn disk_site(name: &str, files: &[(&str, &str)]) -> PathBuf {
+            let base_dir = std::env::temp_dir().join(format!("elss-test-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&base_dir);
+            for (path, contents) in files {
+                let path = base_dir.join("src").join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, contents).unwrap();
+            }
+            base_dir
+        }
+
+        // The warnings a dry run of the build reports, in order.
+        fn warnings(builder: SiteBuilder) -> Vec<String> {
+            let warnings = std::rc::Rc::new(RefCell::new(Vec::new()));
+            let collected = warnings.clone();
+            builder.with_dry_run(true).build_with_progress(move |event| {
+                if let BuildEvent::Warning { message } = event {
+                    collected.borrow_mut().push(message);
+                }
+            }).unwrap();
+            let warnings = warnings.borrow().clone();
+            warnings
+        }
+
+        #[test]
+        fn comments_are_kept_by_default() {
+            let outputs = build(site(&[("index.html", "<p>a</p><!-- note --><p>b</p>")]));
+            assert_eq!(outputs["index.html"], "<p>a</p><!-- note --><p>b</p>");
+        }
+
+        #[test]
+        fn strip_comments_removes_comments_but_not_conditional_or_preformatted_ones() {
+            let page = "<!--[if IE]><p>ie</p><![endif]--><p>a</p><!-- note -->\n<pre><!-- kept --></pre>";
+            let outputs = build(site(&[("index.html", page)]).with_strip_comments(true));
+            assert_eq!(outputs["index.html"], "<!--[if IE]><p>ie</p><![endif]--><p>a</p>\n<pre><!-- kept --></pre>");
+        }
+
+        #[test]
+        fn pages_use_the_closest_directory_layout_and_an_explicit_layout_wins() {
+            let outputs = build(site(&[
+                ("_layout.html", r#"<el-layout name="base" />"#),
+                ("blog/_layout.html", r#"<el-layout name="post" />"#),
+                ("el-layouts/base.html", "<base><el-content /></base>"),
+                ("el-layouts/post.html", "<post><el-content /></post>"),
+                ("index.html", "home"),
+                ("blog/2024/entry.html", "entry"),
+                ("blog/own.html", r#"<el-layout name="base"></el-layout>own"#),
+            ]));
+            assert_eq!(outputs["index.html"], "<base>home</base>");
+            assert_eq!(outputs["blog/2024/entry.html"], "<post>entry</post>");
+            assert_eq!(outputs["blog/own.html"], "<base>own</base>");
+            assert!(!outputs.contains_key("blog/_layout.html"));
+        }
+
+        #[test]
+        fn the_report_lists_every_written_page_and_asset() {
+            let base_dir = disk_site("written", &[("index.html", "<p>x</p>"), ("a/b.html", "b"), ("style.css", "p{}")]);
+            let report = SiteBuilder::new(base_dir.clone()).with_progress(false).build().unwrap();
+            let mut written: Vec<PathBuf> = report.written.iter()
+                .map(|path| path.strip_prefix(base_dir.join("build")).unwrap().to_path_buf())
+                .collect();
+            written.sort();
+            assert_eq!(written, [PathBuf::from("a/b.html"), PathBuf::from("index.html"), PathBuf::from("style.css")]);
+            assert!(written.iter().all(|path| base_dir.join("build").join(path).is_file()));
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn aliases_from_config_name_components_and_unknown_names_resolve_normally() {
+            let config = Config::parse("[aliases]\nbtn = \"ui/forms/button\"\n").unwrap();
+            let fs: MemoryFs = [
+                ("site/src/el-components/ui/forms/button.html", "<button>ok</button>"),
+                ("site/src/el-components/card.html", "<div>card</div>"),
+                ("site/src/index.html", r#"<el-component name="btn" /><el-component name="card" />"#),
+            ].into_iter().collect();
+            let builder = SiteBuilder::from_config(PathBuf::from("site"), &config).with_source_fs(fs).with_progress(false);
+            assert_eq!(build(builder)["index.html"], "<button>ok</button><div>card</div>");
+        }
+
+        // A source whose read_dir fails for one directory, as an unreadable one would on disk.
+        struct UnreadableDir {
+            fs: MemoryFs,
+            dir: PathBuf,
+        }
+
+        impl SourceFs for UnreadableDir {
+            fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+                self.fs.read(path)
+            }
+
+            fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+                if path == self.dir {
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, "permission denied"));
+                }
+                self.fs.read_dir(path)
+            }
+
+            fn is_file(&self, path: &Path) -> bool {
+                self.fs.is_file(path)
+            }
+
+            fn is_dir(&self, path: &Path) -> bool {
+                self.fs.is_dir(path)
+            }
+        }
+
+        fn unreadable_site() -> UnreadableDir {
+            let fs: MemoryFs = [("site/src/index.html", "<p>home</p>"), ("site/src/private/page.html", "<p>hidden</p>")].into_iter().collect();
+            UnreadableDir { fs, dir: PathBuf::from("site/src/private") }
+        }
+
+        #[test]
+        fn an_unreadable_directory_fails_a_strict_build() {
+            let mut builder = SiteBuilder::new(PathBuf::from("site")).with_source_fs(unreadable_site()).with_progress(false).with_strict(true);
+            match builder.build_to_memory() {
+                Err(BuildError::IoError(e)) => assert_eq!(e.kind(), io::ErrorKind::PermissionDenied),
+                other => panic!("expected a read_dir error, got {:?}", other.map(|outputs| outputs.len())),
+            }
+        }
+
+        #[test]
+        fn an_unreadable_directory_is_skipped_outside_strict_mode() {
+            let builder = SiteBuilder::new(PathBuf::from("site")).with_source_fs(unreadable_site()).with_progress(false);
+            let outputs = build(builder);
+            assert_eq!(outputs["index.html"], "<p>home</p>");
+            assert!(!outputs.contains_key("private/page.html"));
+        }
+
+        #[test]
+        fn an_empty_or_blank_component_name_is_reported_as_such() {
+            for page in [r#"<el-component name="" />"#, r#"<el-component name="  " />"#] {
+                match site(&[("index.html", page)]).with_strict(true).build_to_memory() {
+                    Err(BuildError::EmptyDirectiveName(file)) => assert!(file.ends_with("index.html"), "{}", file.display()),
+                    other => panic!("expected an empty name error for {}, got {:?}", page, other.map(|outputs| outputs.len())),
+                }
+            }
+        }
+
+        #[test]
+        fn a_build_without_the_cache_matches_a_cached_build() {
+            let files = [
+                ("el-components/card.html", r#"<div><el-component name="icon" /></div>"#),
+                ("el-components/icon.html", "<i>*</i>"),
+                ("index.html", r#"<el-component name="card" /><el-component name="card" />"#),
+                ("about.html", r#"<el-component name="icon" /><el-component name="card" />"#),
+            ];
+            let cached = build(site(&files));
+            assert_eq!(cached["index.html"], "<div><i>*</i></div><div><i>*</i></div>");
+            assert_eq!(build(site(&files).with_cache(false)), cached);
+        }
+
+        #[test]
+        fn configured_error_pages_are_built_with_the_default_layout() {
+            let builder = site(&[
+                ("_layout.html", r#"<el-layout name="base" />"#),
+                ("el-layouts/base.html", "<base><el-content /></base>"),
+                ("el-components/errors/404.html", "not found"),
+                ("index.html", "home"),
+            ]).with_error_pages(vec![PathBuf::from("el-components/errors/404.html")]);
+            let outputs = build(builder);
+            assert_eq!(outputs["404.html"], "<base>not found</base>");
+            assert_eq!(outputs["index.html"], "<base>home</base>");
+        }
+
+        #[test]
+        fn a_failed_build_leaves_the_previous_output_intact() {
+            let base_dir = disk_site("atomic", &[("index.html", "<p>old</p>")]);
+            SiteBuilder::new(base_dir.clone()).with_progress(false).build().unwrap();
+            fs::write(base_dir.join("src/index.html"), "<p>new</p>").unwrap();
+            fs::write(base_dir.join("src/broken.html"), r#"<el-component name="missing" />"#).unwrap();
+            assert!(SiteBuilder::new(base_dir.clone()).with_progress(false).with_strict(true).build().is_err());
+            assert_eq!(fs::read_to_string(base_dir.join("build/index.html")).unwrap(), "<p>old</p>");
+            assert!(!base_dir.join("build/broken.html").exists());
+            assert!(!base_dir.join(".build.elss-tmp").exists());
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn two_sources_writing_the_same_output_are_reported() {
+            let mut builder = site(&[("about.html", "a"), ("about/index.html", "b")])
+                .with_path_mapper(|path| if path == Path::new("about.html") { PathBuf::from("about/index.html") } else { path.to_path_buf() })
+                .with_strict(true);
+            match builder.build_to_memory() {
+                Err(BuildError::DuplicateOutput { output, first, second }) => {
+                    assert_eq!(output, Path::new("about/index.html"));
+                    let mut sources = [first, second];
+                    sources.sort();
+                    assert_eq!(sources, [PathBuf::from("about/index.html"), PathBuf::from("about.html")]);
+                }
+                other => panic!("expected a conflict, got {:?}", other.map(|outputs| outputs.len())),
+            }
+        }
+
+        #[test]
+        fn an_explicit_dest_dir_overrides_the_configured_one() {
+            let base_dir = disk_site("out", &[("index.html", "<p>x</p>")]);
+            let config = Config::parse("dest_dir = \"public\"\n").unwrap();
+            let out = base_dir.join("elsewhere");
+            SiteBuilder::from_config(base_dir.clone(), &config).with_dest_dir(out.clone()).with_progress(false).build().unwrap();
+            assert_eq!(fs::read_to_string(out.join("index.html")).unwrap(), "<p>x</p>");
+            assert!(!base_dir.join("public").exists());
+            assert!(!base_dir.join("build").exists());
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn junk_files_are_skipped_by_default() {
+            let outputs = build(site(&[("index.html", "x"), (".DS_Store", "junk"), ("img/Thumbs.db", "junk"), ("notes.bak", "b")]));
+            assert_eq!(outputs.keys().collect::<Vec<_>>(), ["index.html", "notes.bak"]);
+        }
+
+        #[test]
+        fn configured_files_and_extensions_are_skipped() {
+            let builder = site(&[("index.html", "x"), ("notes.bak", "b"), ("draft.psd", "p"), ("TODO.txt", "t"), ("style.css", "p{}")])
+                .with_ignored(&["TODO.txt".to_string()], &[".bak".to_string(), "psd".to_string()]);
+            assert_eq!(build(builder).keys().collect::<Vec<_>>(), ["index.html", "style.css"]);
+        }
+
+        #[test]
+        fn ssi_includes_inline_files_from_src_dir_when_enabled() {
+            let files = [("partials/header.html", "<header>h</header>"), ("blog/index.html", "<!--#include virtual=\"partials/header.html\" --><p>x</p>")];
+            assert_eq!(build(site(&files).with_ssi(true))["blog/index.html"], "<header>h</header><p>x</p>");
+            assert_eq!(build(site(&files))["blog/index.html"], files[1].1);
+        }
+
+        #[test]
+        fn ssi_includes_outside_src_dir_are_rejected() {
+            let page = "<!--#include virtual=\"../secret.html\" -->";
+            match site(&[("index.html", page)]).with_ssi(true).with_strict(true).build_to_memory() {
+                Err(BuildError::PathTraversal(path)) => assert_eq!(path, "../secret.html"),
+                other => panic!("expected a traversal error, got {:?}", other.map(|outputs| outputs.len())),
+            }
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn the_post_build_command_runs_after_a_build_and_its_failure_fails_it() {
+            let base_dir = disk_site("post-build", &[("index.html", "<p>x</p>")]);
+            let command = r#"test -f "$ELSS_DEST_DIR/index.html" && printf ran > ran.txt"#;
+            SiteBuilder::new(base_dir.clone()).with_progress(false).with_post_build(Some(command.to_string())).build().unwrap();
+            assert_eq!(fs::read_to_string(base_dir.join("ran.txt")).unwrap(), "ran");
+            let result = SiteBuilder::new(base_dir.clone()).with_progress(false).with_post_build(Some("exit 3".to_string())).build();
+            assert!(matches!(result, Err(BuildError::PostBuildFailed(_, Some(3)))));
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn render_component_expands_nested_components_without_writing() {
+            let mut builder = site(&[
+                ("el-components/card.html", r#"<div class="card"><el-component name="icon" /></div>"#),
+                ("el-components/icon.html", "<i>*</i>"),
+            ]);
+            let rendered = builder.render_component(Path::new("el-components/card.html")).unwrap();
+            assert_eq!(rendered, r#"<div class="card"><i>*</i></div>"#);
+        }
+
+        #[test]
+        fn warn_fragments_flags_pages_without_an_html_root_but_not_components() {
+            let files = [
+                ("el-components/card.html", "<div>card</div>"),
+                ("el-layouts/base.html", "<!doctype html><html><body><el-content /></body></html>"),
+                ("fragment.html", r#"<el-component name="card" />"#),
+                ("page.html", r#"<el-layout name="base"></el-layout><el-component name="card" />"#),
+            ];
+            let reported = warnings(site(&files).with_warn_fragments(true));
+            assert_eq!(reported.len(), 1, "{:?}", reported);
+            assert!(reported[0].contains("fragment.html"), "{}", reported[0]);
+            assert!(warnings(site(&files)).is_empty());
+        }
+
+        #[test]
+        fn critical_css_is_inlined_into_built_pages_and_pages_without_a_head_are_warned_about() {
+            let files = [
+                ("css/critical.css", "h1{color:red}"),
+                ("index.html", r#"<html><head><link rel="stylesheet" href="css/critical.css"></head><body>x</body></html>"#),
+                ("bare.html", "<p>x</p>"),
+            ];
+            let outputs = build(site(&files).with_critical_css(Some(PathBuf::from("css/critical.css"))));
+            assert_eq!(outputs["index.html"], r#"<html><head><link rel="preload" href="css/critical.css" as="style"><style>h1{color:red}</style></head><body>x</body></html>"#);
+            assert_eq!(outputs["bare.html"], "<p>x</p>");
+            let reported = warnings(site(&files).with_critical_css(Some(PathBuf::from("css/critical.css"))));
+            assert!(reported.len() == 1 && reported[0].contains("bare.html"), "{:?}", reported);
+        }
+
+        #[test]
+        fn a_missing_component_fails_a_strict_build_with_not_found() {
+            match site(&[("index.html", r#"<el-component name="nope" />"#)]).with_strict(true).build_to_memory() {
+                Err(BuildError::NotFound(path)) => assert_eq!(path, Path::new("site/src/el-components/nope.html")),
+                other => panic!("expected a missing component, got {:?}", other.map(|outputs| outputs.len())),
+            }
+        }
+
+        #[test]
+        fn a_permalink_sends_a_page_to_its_own_output_path() {
+            let outputs = build(site(&[("pages/about-us.html", "---\npermalink: /about/index.html\n---\n<p>about</p>")]));
+            assert_eq!(outputs.keys().collect::<Vec<_>>(), ["about/index.html"]);
+            assert_eq!(outputs["about/index.html"], "<p>about</p>");
+        }
+
+        #[test]
+        fn a_permalink_outside_dest_dir_is_rejected() {
+            let page = "---\npermalink: ../../escape.html\n---\nx";
+            match site(&[("index.html", page)]).with_strict(true).build_to_memory() {
+                Err(BuildError::PathTraversal(permalink)) => assert_eq!(permalink, "../../escape.html"),
+                other => panic!("expected a traversal error, got {:?}", other.map(|outputs| outputs.len())),
+            }
+        }
+
+        #[test]
+        fn a_permalink_onto_another_pages_output_is_a_conflict() {
+            let files = [("about.html", "---\npermalink: /about/index.html\n---\na"), ("about/index.html", "b")];
+            let result = site(&files).with_strict(true).build_to_memory();
+            assert!(matches!(result, Err(BuildError::DuplicateOutput { output, .. }) if output == Path::new("about/index.html")));
+        }
+
+        #[test]
+        fn a_check_build_fails_on_problems_and_writes_nothing() {
+            let check = |base_dir: &Path| SiteBuilder::new(base_dir.to_path_buf()).with_progress(false)
+                .with_dry_run(true)
+                .with_strict(true)
+                .with_unknown_directive_check(true)
+                .build();
+            let healthy = disk_site("check-healthy", &[("el-components/card.html", "<div>c</div>"), ("index.html", r#"<el-component name="card" />"#)]);
+            assert!(check(&healthy).is_ok());
+            assert!(!healthy.join("build").exists());
+            let broken = disk_site("check-broken", &[("index.html", r#"<el-component name="card" />"#)]);
+            assert!(check(&broken).is_err());
+            assert!(!broken.join("build").exists());
+            fs::remove_dir_all(&healthy).unwrap();
+            fs::remove_dir_all(&broken).unwrap();
+        }
+
+        #[test]
+        fn global_components_resolve_by_bare_name_from_any_page() {
+            let builder = site(&[
+                ("el-components/icons/star.html", "<i>*</i>"),
+                ("index.html", r#"<el-component name="star" />"#),
+                ("blog/deep/post.html", r#"<el-component name="star" />"#),
+            ]).with_global_components(&["icons/star".to_string()]);
+            let outputs = build(builder);
+            assert_eq!(outputs["index.html"], "<i>*</i>");
+            assert_eq!(outputs["blog/deep/post.html"], "<i>*</i>");
+        }
+
+        #[test]
+        fn reference_validation_reports_only_missing_names() {
+            let builder = site(&[
+                ("el-components/card.html", "<div>c</div>"),
+                ("el-layouts/base.html", "<el-content />"),
+                ("index.html", r#"<el-layout name="base" /><el-component name="card" />"#),
+                ("about.html", r#"<el-component name="card" /><el-component name="missing" />"#),
+            ]);
+            let missing = builder.find_missing_references().unwrap();
+            assert_eq!(missing.len(), 1, "{:?}", missing);
+            assert_eq!(missing[0].file, Path::new("about.html"));
+            assert_eq!(missing[0].name, "missing");
+            let result = site(&[("index.html", r#"<el-component name="missing" />"#)]).with_reference_validation(true).with_strict(true).build_to_memory();
+            assert!(matches!(result, Err(BuildError::MissingReferences(1))));
+        }
+
+        #[test]
+        fn every_content_placeholder_in_a_layout_receives_the_page() {
+            let files = [
+                ("el-layouts/base.html", "<main><el-content /></main><div class=\"print\"><el-content /></div>"),
+                ("index.html", r#"<el-layout name="base"></el-layout><p>x</p>"#),
+            ];
+            assert_eq!(build(site(&files))["index.html"], r#"<main><p>x</p></main><div class="print"><p>x</p></div>"#);
+            assert!(warnings(site(&files)).is_empty());
+            let reported = warnings(site(&files).with_warn_duplicate_content(true));
+            assert!(reported.len() == 1 && reported[0].contains("base"), "{:?}", reported);
+        }
+
+        #[test]
+        fn render_string_expands_a_document_against_the_site_on_disk() {
+            let base_dir = disk_site("stdin", &[
+                ("el-components/card.html", "<div>card</div>"),
+                ("el-layouts/base.html", "<body><el-content /></body>"),
+            ]);
+            let mut builder = SiteBuilder::new(base_dir.clone()).with_progress(false);
+            let rendered = builder.render_string(r#"<el-layout name="base"></el-layout><el-component name="card" />"#).unwrap();
+            assert_eq!(rendered, "<body><div>card</div></body>");
+            assert!(!base_dir.join("build").exists());
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn pages_builds_only_the_listed_pages() {
+            let builder = site(&[
+                ("el-components/card.html", "<div>card</div>"),
+                ("a.html", r#"<el-component name="card" />"#),
+                ("b/c.html", "c"),
+                ("other.html", "other"),
+                ("style.css", "p{}"),
+            ]).with_pages(vec![PathBuf::from("a.html"), PathBuf::from("b/c.html")]);
+            let outputs = build(builder);
+            assert_eq!(outputs.keys().collect::<Vec<_>>(), ["a.html", "b/c.html"]);
+            assert_eq!(outputs["a.html"], "<div>card</div>");
+        }
+
+        #[test]
+        fn component_and_layout_names_outside_the_safe_set_are_rejected() {
+            for name in ["card?", "ca*d", "../../secret/x", "/etc/passwd", "a/../../b"] {
+                for page in [format!(r#"<el-component name="{}" />"#, name), format!(r#"<el-layout name="{}" />x"#, name)] {
+                    match site(&[("index.html", page.as_str())]).with_strict(true).build_to_memory() {
+                        Err(BuildError::InvalidName { file, name: rejected }) => {
+                            assert_eq!(rejected, name);
+                            assert!(file.ends_with("index.html"), "{}", file.display());
+                        }
+                        other => panic!("expected {} to be rejected, got {:?}", page, other.map(|outputs| outputs.len())),
+                    }
+                }
+            }
+        }
+
+        fn site_with_a_layout_without_content() -> SiteBuilder {
+            site(&[("el-layouts/bare.html", "<header>h</header>"), ("index.html", r#"<el-layout name="bare"></el-layout><p>x</p>"#)])
+        }
+
+        #[test]
+        fn missing_content_drop_keeps_only_the_layout() {
+            let outputs = build(site_with_a_layout_without_content().with_missing_content(MissingContent::Drop));
+            assert_eq!(outputs["index.html"], "<header>h</header>");
+        }
+
+        #[test]
+        fn missing_content_append_puts_the_page_after_the_layout() {
+            let outputs = build(site_with_a_layout_without_content().with_missing_content(MissingContent::Append));
+            assert_eq!(outputs["index.html"], "<header>h</header><p>x</p>");
+        }
+
+        #[test]
+        fn missing_content_error_is_the_default_and_names_the_layout() {
+            match site_with_a_layout_without_content().with_strict(true).build_to_memory() {
+                Err(BuildError::MissingContent(layout)) => assert!(layout.ends_with("el-layouts/bare.html"), "{}", layout.display()),
+                other => panic!("expected a missing content error, got {:?}", other.map(|outputs| outputs.len())),
+            }
+        }
+
+        #[test]
+        fn transforms_run_on_flattened_pages_in_registration_order() {
+            let builder = site(&[("el-layouts/base.html", "<body><el-content /></body>"), ("index.html", r#"<el-layout name="base"></el-layout>[marker]"#)])
+                .with_transform(|path, page| {
+                    assert_eq!(path, Path::new("index.html"));
+                    page.replace("[marker]", "[MARKER]")
+                })
+                .with_transform(|_, page| page.replace("[MARKER]", "[MARKER]!"));
+            assert_eq!(build(builder)["index.html"], "<body>[MARKER]!</body>");
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn unchanged_assets_are_not_copied_again() {
+            use std::os::unix::fs::MetadataExt;
+            let base_dir = disk_site("skip-assets", &[("index.html", "x"), ("img/logo.png", "png"), ("img/photo.png", "old")]);
+            let build_site = || SiteBuilder::new(base_dir.clone()).with_progress(false).with_skip_unchanged_assets(true).build().unwrap();
+            let inode = |path: &str| fs::metadata(base_dir.join("build").join(path)).unwrap().ino();
+            build_site();
+            let logo = inode("img/logo.png");
+            fs::write(base_dir.join("src/img/photo.png"), "newer").unwrap();
+            build_site();
+            assert_eq!(inode("img/logo.png"), logo);
+            assert_eq!(fs::read_to_string(base_dir.join("build/img/photo.png")).unwrap(), "newer");
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn a_front_matter_layout_wraps_the_page_and_an_explicit_tag_still_works() {
+            let outputs = build(site(&[
+                ("el-layouts/post.html", "<article><el-content /></article>"),
+                ("el-layouts/base.html", "<main><el-content /></main>"),
+                ("post.html", "---\nlayout: post\n---\n<p>x</p>"),
+                ("page.html", r#"<el-layout name="base"></el-layout><p>y</p>"#),
+            ]));
+            assert_eq!(outputs["post.html"], "<article><p>x</p></article>");
+            assert_eq!(outputs["page.html"], "<main><p>y</p></main>");
+        }
+
+        #[test]
+        fn a_mount_moves_the_output_and_its_root_relative_links() {
+            let base_dir = disk_site("mount", &[("blog/post.html", r#"<a href="/about.html">a</a><a href="//cdn.example.com/x.js">c</a><img src="logo.png">"#)]);
+            SiteBuilder::new(base_dir.clone()).with_progress(false).with_mount(Some("/pr-123".to_string())).build().unwrap();
+            let page = fs::read_to_string(base_dir.join("build/pr-123/blog/post.html")).unwrap();
+            assert_eq!(page, r#"<a href="/pr-123/about.html">a</a><a href="//cdn.example.com/x.js">c</a><img src="logo.png">"#);
+            assert!(!base_dir.join("build/blog").exists());
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn deduped_components_are_smaller_and_keep_every_use() {
+            let card = format!("<div class=\"card\">{}</div>", "lorem ipsum ".repeat(50));
+            let page = format!("<html><body>{}</body></html>", r#"<el-component name="card" />"#.repeat(10));
+            let files = [("el-components/card.html", card.as_str()), ("index.html", page.as_str())];
+            let plain = build(site(&files)).remove("index.html").unwrap();
+            let deduped = build(site(&files).with_dedupe_components(true)).remove("index.html").unwrap();
+            assert_eq!(plain.matches(&card).count(), 10);
+            assert!(deduped.len() < plain.len());
+            assert_eq!(deduped.matches(&card).count(), 1);
+            assert_eq!(deduped.matches(r#"<template data-el-use="el-shared-0"></template>"#).count(), 10);
+            assert!(deduped.contains(DEDUPE_SCRIPT));
+        }
+
+        #[test]
+        fn a_wrapper_applies_a_layout_to_part_of_a_page() {
+            let outputs = build(site(&[
+                ("el-layouts/section.html", "<section><el-content /></section>"),
+                ("el-components/a.html", "<p>a</p>"),
+                ("el-components/b.html", "<p>b</p>"),
+                ("index.html", r#"<h1>t</h1><el-wrapper name="section"><el-component name="a" /><el-component name="b" /></el-wrapper><footer></footer>"#),
+            ]));
+            assert_eq!(outputs["index.html"], "<h1>t</h1><section><p>a</p><p>b</p></section><footer></footer>");
+        }
+
+        #[test]
+        fn with_output_name_renames_the_output_directory_inside_base_dir() {
+            let base_dir = disk_site("output-name", &[("index.html", "<p>x</p>")]);
+            SiteBuilder::new(base_dir.clone()).with_progress(false).with_output_name("dist").build().unwrap();
+            assert_eq!(fs::read_to_string(base_dir.join("dist/index.html")).unwrap(), "<p>x</p>");
+            assert!(!base_dir.join("build").exists());
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn warn_empty_flags_empty_components_which_still_expand_to_nothing() {
+            let files = [("el-components/todo.html", ""), ("el-components/card.html", "<div>c</div>"), ("index.html", r#"<p><el-component name="todo" /><el-component name="card" /></p>"#)];
+            assert_eq!(build(site(&files).with_warn_empty(true))["index.html"], "<p><div>c</div></p>");
+            let reported = warnings(site(&files).with_warn_empty(true));
+            assert!(reported.len() == 1 && reported[0].contains("todo"), "{:?}", reported);
+            assert!(warnings(site(&files)).is_empty());
+        }
+
+        #[test]
+        fn build_variables_render_in_pages_and_layouts() {
+            let files = [("el-layouts/base.html", "<el-content /><footer>{{ build.year }}</footer>"), ("index.html", r#"<el-layout name="base"></el-layout><p>{{ build.time }}</p>"#)];
+            let mut builder = site(&files).with_build_time_format(Some("%Y".to_string()));
+            let outputs = builder.build_to_memory().unwrap();
+            let year = builder.variables["build.year"].clone();
+            assert_eq!(year.len(), 4);
+            assert_eq!(String::from_utf8_lossy(&outputs[Path::new("index.html")]), format!("<p>{0}</p><footer>{0}</footer>", year));
+        }
+
+        #[test]
+        fn html_validation_reports_an_unbalanced_component_without_changing_output() {
+            let files = [("el-components/broken.html", "<div><span>x</div>"), ("index.html", r#"<main><el-component name="broken" /></main>"#)];
+            match site(&files).with_html_validation(true).with_strict(true).build_to_memory() {
+                Err(BuildError::InvalidHtml { file, message }) => {
+                    assert_eq!(file, Path::new("index.html"));
+                    assert_eq!(message, "<span> on line 1 is closed by </div> on line 1");
+                }
+                other => panic!("expected a validation error, got {:?}", other.map(|outputs| outputs.len())),
+            }
+            assert_eq!(build(site(&files).with_html_validation(true))["index.html"], "<main><div><span>x</div></main>");
+        }
+
+        #[test]
+        fn a_page_moved_into_a_subdirectory_keeps_its_relative_links_working() {
+            let outputs = build(site(&[("about.html", "---\npermalink: /about/index.html\n---\n<link href=\"./style.css\"><img src=\"img/a.png\">")]));
+            assert_eq!(outputs["about/index.html"], r#"<link href="../style.css"><img src="../img/a.png">"#);
+        }
+
+        #[test]
+        fn head_blocks_from_components_are_hoisted_into_the_page_head_once() {
+            let files = [
+                ("el-components/chart.html", "<el-head><script src=\"chart.js\"></script></el-head><canvas></canvas>"),
+                ("index.html", r#"<html><head><title>t</title></head><body><el-component name="chart" /><el-component name="chart" /></body></html>"#),
+                ("bare.html", r#"<el-component name="chart" />"#),
+            ];
+            let outputs = build(site(&files));
+            assert_eq!(outputs["index.html"], r#"<html><head><title>t</title><script src="chart.js"></script></head><body><canvas></canvas><canvas></canvas></body></html>"#);
+            let reported = warnings(site(&files));
+            assert!(reported.len() == 1 && reported[0].contains("bare.html"), "{:?}", reported);
+        }
+
+        #[test]
+        fn build_to_memory_returns_pages_and_assets_without_writing() {
+            let base_dir = disk_site("memory", &[("el-components/card.html", "<div>c</div>"), ("index.html", r#"<el-component name="card" />"#)]);
+            fs::write(base_dir.join("src/logo.png"), [0x89, b'P', b'N', b'G', 0x00, 0xff]).unwrap();
+            let outputs = SiteBuilder::new(base_dir.clone()).with_progress(false).build_to_memory().unwrap();
+            assert_eq!(outputs.len(), 2);
+            assert_eq!(outputs[Path::new("index.html")], b"<div>c</div>");
+            assert_eq!(outputs[Path::new("logo.png")], [0x89, b'P', b'N', b'G', 0x00, 0xff]);
+            assert!(!base_dir.join("build").exists());
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn scoped_styles_mark_the_selector_and_the_components_root_elements() {
+            let files = [("el-components/card.html", "<style>.card { color: red }</style><div class=\"card\"><p>x</p></div>"), ("index.html", r#"<el-component name="card" />"#)];
+            let page = build(site(&files).with_scoped_styles(true)).remove("index.html").unwrap();
+            let attribute = css::scope_attribute("el-components/card.html");
+            assert_eq!(page, format!("<style>.card[{0}] {{ color: red }}</style><div class=\"card\" {0}><p>x</p></div>", attribute));
+            assert_eq!(build(site(&files))["index.html"], files[0].1);
+        }
+
+        #[test]
+        fn rebuild_writes_only_the_pages_that_depend_on_a_changed_component() {
+            let base_dir = disk_site("rebuild", &[
+                ("el-components/card.html", "<div>old</div>"),
+                ("el-components/other.html", "<p>o</p>"),
+                ("a.html", r#"<el-component name="card" />"#),
+                ("b/c.html", r#"<section><el-component name="card" /></section>"#),
+                ("d.html", r#"<el-component name="other" />"#),
+                ("style.css", "p{}"),
+            ]);
+            let mut builder = SiteBuilder::new(base_dir.clone()).with_progress(false);
+            builder.build().unwrap();
+            fs::write(base_dir.join("src/el-components/card.html"), "<div>new</div>").unwrap();
+            let report = builder.rebuild(&[base_dir.join("src/el-components/card.html")]).unwrap();
+            let mut written: Vec<PathBuf> = report.written.iter().map(|path| path.strip_prefix(base_dir.join("build")).unwrap().to_path_buf()).collect();
+            written.sort();
+            assert_eq!(written, [PathBuf::from("a.html"), PathBuf::from("b/c.html")]);
+            assert_eq!(fs::read_to_string(base_dir.join("build/b/c.html")).unwrap(), "<section><div>new</div></section>");
+            assert_eq!(fs::read_to_string(base_dir.join("build/d.html")).unwrap(), "<p>o</p>");
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn a_configured_index_file_names_directory_permalinks_and_resolves_directory_links() {
+            let files = [("about.html", "---\npermalink: /about/\n---\nabout"), ("index.html", r#"<el-link to="about/">about</el-link>"#)];
+            let outputs = build(site(&files).with_index_file(Some("default.html".to_string())));
+            assert_eq!(outputs.keys().collect::<Vec<_>>(), ["about/default.html", "index.html"]);
+            assert!(site(&files).with_index_file(Some("default.html".to_string())).with_link_checking(true).with_strict(true).build_to_memory().is_ok());
+            let dead = site(&[("index.html", r#"<el-link to="about/">about</el-link>"#), ("about/index.html", "x")]).with_index_file(Some("default.html".to_string()));
+            assert!(matches!(dead.with_link_checking(true).with_strict(true).build_to_memory(), Err(BuildError::DeadLink { .. })));
+            assert_eq!(build(site(&files)).keys().collect::<Vec<_>>(), ["about/index.html", "index.html"]);
+        }
+
+        #[test]
+        fn the_lint_flags_a_misspelled_directive_once_per_page() {
+            let files = [("el-components/card.html", "<div>c</div>"), ("index.html", r#"<el-compoent name="card" /><el-compoent name="card" /><el-component name="card" />"#)];
+            let reported = warnings(site(&files).with_lint(true));
+            assert_eq!(reported, ["page [index.html] contains unknown directive <el-compoent>, did you mean <el-component>?"]);
+            assert!(warnings(site(&files)).is_empty());
+        }
+
+        #[test]
+        fn later_defines_win_and_front_matter_wins_over_defines() {
+            let defines = vec![
+                ("site".to_string(), "first".to_string()),
+                ("site".to_string(), "second".to_string()),
+                ("query".to_string(), "a=b".to_string()),
+                ("title".to_string(), "Default".to_string()),
+            ];
+            let outputs = build(site(&[
+                ("index.html", "{{ site }} {{ query }} {{ title }}"),
+                ("about.html", "---\ntitle: About\n---\n{{ site }} {{ title }}"),
+            ]).with_defines(defines));
+            assert_eq!(outputs["index.html"], "second a=b Default");
+            assert_eq!(outputs["about.html"], "second About");
+        }
+
+        #[test]
+        fn directive_usage_counts_tags_and_names_across_the_site() {
+            let builder = site(&[
+                ("el-components/card.html", r#"<div><el-component name="icon" /></div>"#),
+                ("el-components/icon.html", "<i></i>"),
+                ("el-layouts/base.html", "<el-content />"),
+                ("index.html", r#"<el-layout name="base" /><el-component name="card" /><el-component name="card" />"#),
+                ("about.html", r#"<el-wrapper name="base"><el-component name="icon" /></el-wrapper>"#),
+                ("style.css", "el-component { }"),
+            ]);
+            let usage = builder.directive_usage().unwrap();
+            let tags: Vec<(&str, usize)> = usage.tags.iter().map(|(tag, count)| (tag.as_str(), *count)).collect();
+            assert_eq!(tags, [("el-component", 4), ("el-content", 1), ("el-layout", 1), ("el-wrapper", 1)]);
+            assert_eq!(usage.components.into_iter().collect::<Vec<_>>(), ["card", "icon"]);
+            assert_eq!(usage.layouts.into_iter().collect::<Vec<_>>(), ["base"]);
+        }
+
+        #[test]
+        fn the_size_limit_names_an_oversized_output() {
+            let files = [("el-components/big.html", "0123456789"), ("index.html", r#"<el-component name="big" /><el-component name="big" />"#), ("small.html", "ok")];
+            match site(&files).with_max_file_size(Some(16)).with_strict(true).build_to_memory() {
+                Err(BuildError::FileTooLarge { file, size, limit }) => {
+                    assert!(file.ends_with("index.html"), "{}", file.display());
+                    assert_eq!((size, limit), (20, 16));
+                }
+                other => panic!("expected the size guard to fire, got {:?}", other.map(|outputs| outputs.len())),
+            }
+            assert_eq!(build(site(&files).with_max_file_size(Some(16))).keys().collect::<Vec<_>>(), ["small.html"]);
+        }
+
+        #[test]
+        fn content_around_a_layout_tag_is_kept_and_an_empty_tag_wraps_the_page() {
+            let outputs = build(site(&[
+                ("el-layouts/base.html", "<main><el-content /></main>"),
+                ("index.html", r#"<p>before</p><el-layout name="base"><p>inside</p></el-layout><p>after</p>"#),
+                ("empty.html", r#"<p>before</p><el-layout name="base"></el-layout><p>after</p>"#),
+            ]));
+            assert_eq!(outputs["index.html"], "<p>before</p><main><p>inside</p></main><p>after</p>");
+            assert_eq!(outputs["empty.html"], "<main><p>before</p><p>after</p></main>");
+        }
+
+        fn switch_page(status: &str, default: &str) -> String {
+            let page = format!("---\nstatus: {}\n---\n<el-switch var=\"status\"><el-case value=\"draft\">D</el-case><el-case value=\"published\">P</el-case>{}</el-switch>", status, default);
+            build(site(&[("index.html", page.as_str())])).remove("index.html").unwrap()
+        }
+
+        #[test]
+        fn a_switch_renders_the_matching_case() {
+            assert_eq!(switch_page("published", "<el-default>?</el-default>"), "P");
+        }
+
+        #[test]
+        fn a_switch_without_a_match_renders_the_default() {
+            assert_eq!(switch_page("archived", "<el-default>?</el-default>"), "?");
+        }
+
+        #[test]
+        fn a_switch_without_a_match_or_default_renders_nothing() {
+            assert_eq!(switch_page("archived", ""), "");
+        }
+
+        #[test]
+        fn the_source_map_records_the_range_of_an_inserted_component() {
+            let outputs = build(site(&[("el-components/card.html", "<div>c</div>"), ("index.html", r#"<p>x</p><el-component name="card" /><p>y</p>"#)]).with_source_map(true));
+            assert_eq!(outputs["index.html"], "<p>x</p><div>c</div><p>y</p>");
+            assert_eq!(outputs["index.html.map"], concat!(
+                "{\"file\":\"index.html\",\"ranges\":[\n",
+                "{\"start\":0,\"end\":8,\"source\":\"index.html\"},\n",
+                "{\"start\":8,\"end\":20,\"source\":\"el-components/card.html\"},\n",
+                "{\"start\":20,\"end\":28,\"source\":\"index.html\"}\n",
+                "]}\n",
+            ));
+            assert!(!build(site(&[("index.html", "x")])).contains_key("index.html.map"));
+        }
+
+        #[test]
+        fn bundle_inlines_local_stylesheets_scripts_and_images() {
+            let files = [
+                ("css/site.css", "p{color:red}"),
+                ("app.js", "go()"),
+                ("img/dot.png", "abc"),
+                ("index.html", r#"<html><head><link rel="stylesheet" href="css/site.css"><script src="app.js" defer></script></head><body><img src="img/dot.png" alt=""><img src="https://x.org/a.png"></body></html>"#),
+            ];
+            let outputs = build(site(&files).with_bundle(true));
+            assert_eq!(outputs["index.html"], r#"<html><head><style>p{color:red}</style><script defer>go()</script></head><body><img src="data:image/png;base64,YWJj" alt=""><img src="https://x.org/a.png"></body></html>"#);
+            let reported = warnings(site(&[("index.html", r#"<img src="missing.png">"#)]).with_bundle(true));
+            assert!(reported.len() == 1 && reported[0].contains("missing.png"), "{:?}", reported);
+        }
+
+        // Reads from disk, counting reads of files with the given name.
+        struct CountingFs {
+            name: &'static str,
+            reads: Arc<Mutex<usize>>,
+        }
+
+        impl SourceFs for CountingFs {
+            fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+                if path.file_name().is_some_and(|name| name == self.name) {
+                    *self.reads.lock().unwrap() += 1;
+                }
+                DiskFs.read(path)
+            }
+
+            fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+                DiskFs.read_dir(path)
+            }
+
+            fn is_file(&self, path: &Path) -> bool {
+                DiskFs.is_file(path)
+            }
+
+            fn is_dir(&self, path: &Path) -> bool {
+                DiskFs.is_dir(path)
+            }
+
+            fn canonicalize(&self, path: &Path) -> PathBuf {
+                DiskFs.canonicalize(path)
+            }
+        }
+
+        #[test]
+        fn a_shared_cache_expands_a_shared_component_once_across_sites() {
+            let root = disk_site("shared-cache", &[]);
+            let write = |path: &str, contents: &str| {
+                fs::create_dir_all(root.join(path).parent().unwrap()).unwrap();
+                fs::write(root.join(path), contents).unwrap();
+            };
+            write("shared/ui/button.html", "<button>ok</button>");
+            write("a/src/index.html", r#"<el-component name="@ui/button" />"#);
+            write("b/src/index.html", r#"<p><el-component name="@ui/button" /></p>"#);
+            let cache = SharedCache::default();
+            let reads = Arc::new(Mutex::new(0));
+            for site_name in ["a", "b"] {
+                let roots = HashMap::from([("ui".to_string(), "../shared/ui".to_string())]);
+                let mut builder = SiteBuilder::new(root.join(site_name))
+                    .with_source_fs(CountingFs { name: "button.html", reads: reads.clone() })
+                    .with_component_roots(roots)
+                    .with_shared_cache(cache.clone())
+                    .with_progress(false);
+                builder.build_to_memory().unwrap();
+            }
+            assert_eq!(*reads.lock().unwrap(), 1);
+            fs::remove_dir_all(&root).unwrap();
+        }
+
+        #[test]
+        fn sites_sharing_a_cache_keep_their_own_same_named_components() {
+            let cache = SharedCache::default();
+            let a = disk_site("shared-cache-a", &[("el-components/card.html", "a"), ("index.html", r#"<el-component name="card" />"#)]);
+            let b = disk_site("shared-cache-b", &[("el-components/card.html", "b"), ("index.html", r#"<el-component name="card" />"#)]);
+            for (base_dir, expected) in [(&a, "a"), (&b, "b")] {
+                let outputs = SiteBuilder::new(base_dir.clone()).with_shared_cache(cache.clone()).with_progress(false).build_to_memory().unwrap();
+                assert_eq!(outputs[Path::new("index.html")], expected.as_bytes());
+            }
+            fs::remove_dir_all(&a).unwrap();
+            fs::remove_dir_all(&b).unwrap();
+        }
+
+        #[test]
+        fn components_resolve_whatever_the_position_of_their_name() {
+            let outputs = build(site(&[
+                ("el-components/card.html", "<div>c</div>"),
+                ("index.html", r#"<el-component name="card" label="x" /><el-component label="x"  name="card"   id="y"/><el-component id="y" name="card"></el-component>"#),
+            ]));
+            assert_eq!(outputs["index.html"], "<div>c</div><div>c</div><div>c</div>");
+        }
+
+        #[test]
+        fn the_report_times_every_page() {
+            let base_dir = disk_site("timing", &[("el-layouts/base.html", "<el-content />"), ("a.html", r#"<el-layout name="base" />a"#), ("b/c.html", "c"), ("style.css", "p{}")]);
+            let report = SiteBuilder::new(base_dir.clone()).with_progress(false).build().unwrap();
+            let mut pages: Vec<&Path> = report.timing.pages.iter().map(|(page, _)| page.as_path()).collect();
+            pages.sort();
+            assert_eq!(pages, [Path::new("a.html"), Path::new("b/c.html")]);
+            assert_eq!(report.timing.slowest(1).len(), 1);
+            assert!(report.timing.slowest(1)[0].1 >= report.timing.pages.iter().map(|(_, time)| *time).min().unwrap());
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn slowest_sorts_pages_by_time_and_takes_the_first_ones() {
+            let timing = Timing {
+                pages: vec![(PathBuf::from("a"), Duration::from_millis(2)), (PathBuf::from("b"), Duration::from_millis(5)), (PathBuf::from("c"), Duration::from_millis(1))],
+                ..Timing::default()
+            };
+            let slowest: Vec<&Path> = timing.slowest(2).into_iter().map(|(page, _)| page.as_path()).collect();
+            assert_eq!(slowest, [Path::new("b"), Path::new("a")]);
+        }
+
+        #[test]
+        fn pruning_copies_only_referenced_assets() {
+            let files = [
+                ("images/used.png", "u"),
+                ("images/orphan.png", "o"),
+                ("css/site.css", "p{}"),
+                ("index.html", r#"<link rel="stylesheet" href="css/site.css"><img src="images/used.png">"#),
+            ];
+            let outputs = build(site(&files).with_prune_unused_assets(true));
+            assert_eq!(outputs.keys().collect::<Vec<_>>(), ["css/site.css", "images/used.png", "index.html"]);
+            assert!(build(site(&files)).contains_key("images/orphan.png"));
+        }
+
+        // Serves body over HTTP to one request and returns the URL to fetch it from.
+        fn serve_once(body: &'static str) -> (String, std::thread::JoinHandle<()>) {
+            use std::io::{Read, Write};
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/footer.html", listener.local_addr().unwrap());
+            let server = std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer).unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body).unwrap();
+            });
+            (url, server)
+        }
+
+        #[test]
+        fn remote_components_are_fetched_once_and_then_served_from_the_cache() {
+            let (url, server) = serve_once("<footer>legal</footer>");
+            let page = format!(r#"<main></main><el-component src="{}" />"#, url);
+            let base_dir = disk_site("remote", &[("index.html", page.as_str())]);
+            let build_site = || SiteBuilder::new(base_dir.clone()).with_progress(false).with_allow_remote(true).with_strict(true).build_to_memory();
+            assert_eq!(build_site().unwrap()[Path::new("index.html")], b"<main></main><footer>legal</footer>");
+            server.join().unwrap();
+            assert_eq!(build_site().unwrap()[Path::new("index.html")], b"<main></main><footer>legal</footer>");
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn remote_components_need_allow_remote_and_a_reachable_server() {
+            let page = r#"<el-component src="http://127.0.0.1:9/footer.html" />"#;
+            let result = site(&[("index.html", page)]).with_strict(true).build_to_memory();
+            assert!(matches!(result, Err(BuildError::Malformed { message, .. }) if message.contains("is not allowed")));
+            let result = site(&[("index.html", page)]).with_allow_remote(true).with_strict(true).build_to_memory();
+            assert!(matches!(result, Err(BuildError::RemoteFetch { url, .. }) if url == "http://127.0.0.1:9/footer.html"));
+        }
+
+        #[test]
+        fn no_clean_keeps_unrelated_files_in_the_output_directory() {
+            let base_dir = disk_site("no-clean", &[("index.html", "<p>new</p>")]);
+            fs::create_dir_all(base_dir.join("build/vendor")).unwrap();
+            fs::write(base_dir.join("build/vendor/tool.js"), "kept").unwrap();
+            fs::write(base_dir.join("build/index.html"), "<p>old</p>").unwrap();
+            SiteBuilder::new(base_dir.clone()).with_progress(false).with_no_clean(true).build().unwrap();
+            assert_eq!(fs::read_to_string(base_dir.join("build/vendor/tool.js")).unwrap(), "kept");
+            assert_eq!(fs::read_to_string(base_dir.join("build/index.html")).unwrap(), "<p>new</p>");
+            SiteBuilder::new(base_dir.clone()).with_progress(false).build().unwrap();
+            assert!(!base_dir.join("build/vendor").exists());
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn the_catalog_renders_each_component_with_its_example_variables() {
+            let mut builder = site(&[
+                ("el-components/button.html", "<button>{{ label }}</button>"),
+                ("el-components/button.toml", "description = \"A <primary> button\"\n[example]\nlabel = \"Save\"\n"),
+                ("el-components/icon.html", "<i></i>"),
+                ("index.html", "not a component"),
+            ]);
+            let catalog = builder.catalog().unwrap();
+            assert!(catalog.contains("<section><h2>button</h2><p>A &lt;primary&gt; button</p><div class=\"el-catalog-example\"><button>Save</button></div>"), "{}", catalog);
+            assert!(catalog.contains("<h2>icon</h2><div class=\"el-catalog-example\"><i></i></div>"), "{}", catalog);
+            assert!(!catalog.contains("not a component"));
+        }
+
+        #[test]
+        fn extra_layout_tag_attributes_are_variables_in_the_layout() {
+            let outputs = build(site(&[
+                ("el-layouts/base.html", "<title>{{ title }}</title><el-content />"),
+                ("index.html", "---\ntitle: Front\n---\n<el-layout name=\"base\" title=\"Home\"></el-layout><p>x</p>"),
+            ]));
+            assert_eq!(outputs["index.html"], "<title>Home</title><p>x</p>");
+        }
+
+        #[test]
+        fn layouts_chain_with_the_page_body_nested_inside_both() {
+            let outputs = build(site(&[
+                ("el-layouts/base.html", "<html><body><el-content /></body></html>"),
+                ("el-layouts/post.html", r#"<el-layout name="base"></el-layout><article><el-content /></article>"#),
+                ("index.html", r#"<el-layout name="post"></el-layout><p>x</p>"#),
+            ]));
+            assert_eq!(outputs["index.html"], "<html><body><article><p>x</p></article></body></html>");
+        }
+
+        #[test]
+        fn a_layout_chain_that_loops_is_circular() {
+            let files = [
+                ("el-layouts/a.html", r#"<el-layout name="b"></el-layout><el-content />"#),
+                ("el-layouts/b.html", r#"<el-layout name="a"></el-layout><el-content />"#),
+                ("index.html", r#"<el-layout name="a"></el-layout>x"#),
+            ];
+            assert!(matches!(site(&files).with_strict(true).build_to_memory(), Err(BuildError::Circular(_))));
+        }
+
+        #[test]
+        fn trim_directives_leaves_no_blank_line_for_a_tag_on_its_own_line() {
+            let files = [("el-components/nav.html", "<nav></nav>\n"), ("el-components/empty.html", ""), ("index.html", "<body>\n    <el-component name=\"nav\" />\n    <el-component name=\"empty\" />\n<p>x</p>\n</body>")];
+            assert_eq!(build(site(&files).with_trim_directives(true))["index.html"], "<body>\n<nav></nav>\n<p>x</p>\n</body>");
+            assert_eq!(build(site(&files))["index.html"], "<body>\n    <nav></nav>\n\n    \n<p>x</p>\n</body>");
+        }
+
+        #[test]
+        fn resolve_dependencies_lists_nested_components_and_layouts() {
+            let mut builder = site(&[
+                ("el-components/card.html", r#"<div><el-component name="icon" /></div>"#),
+                ("el-components/icon.html", "<i></i>"),
+                ("el-components/unused.html", "u"),
+                ("el-layouts/base.html", "<el-content />"),
+                ("index.html", r#"<el-layout name="base"></el-layout><el-component name="card" />"#),
+            ]);
+            let dependencies = builder.resolve_dependencies(Path::new("index.html")).unwrap();
+            assert_eq!(dependencies, [
+                PathBuf::from("el-components/card.html"),
+                PathBuf::from("el-components/icon.html"),
+                PathBuf::from("el-layouts/base.html"),
+            ]);
+        }
+
+        #[test]
+        fn robots_txt_includes_the_sitemap_line() {
+            let robots = Robots {
+                rules: vec!["User-agent: *".to_string(), "Disallow: /drafts/".to_string()],
+                sitemap: Some("https://example.com/sitemap.xml".to_string()),
+            };
+            let outputs = build(site(&[("index.html", "home")]).with_robots(Some(robots)));
+            assert_eq!(
+                outputs["robots.txt"],
+                "User-agent: *\nDisallow: /drafts/\n\nSitemap: https://example.com/sitemap.xml\n"
+            );
+        }
+
+        #[test]
+        fn robots_txt_is_not_written_without_config() {
+            let outputs = build(site(&[("index.html", "home")]));
+            assert!(!outputs.contains_key("robots.txt"));
+        }
+
+        #[test]
+        fn expands_many_components_in_order() {
+            let mut files = vec![("el-components/a.html", "A"), ("el-components/b.html", "B")];
+            let page = r#"<el-component name="a" />-<el-component name="b" />|"#.repeat(2000);
+            files.push(("index.html", &page));
+            let outputs = build(site(&files));
+            assert_eq!(outputs["index.html"], "A-B|".repeat(2000));
+        }
+
+        #[test]
+        fn only_builds_the_given_subtree() {
+            let base_dir = disk_site("only", &[
+                ("el-components/byline.html", "by me"),
+                ("index.html", "<p>new home</p>"),
+                ("blog/post.html", r#"<el-component name="byline" />"#),
+            ]);
+            fs::create_dir_all(base_dir.join("build")).unwrap();
+            fs::write(base_dir.join("build/index.html"), "<p>old home</p>").unwrap();
+            SiteBuilder::new(base_dir.clone()).with_progress(false).with_only(Some(PathBuf::from("blog"))).build().unwrap();
+            assert_eq!(fs::read_to_string(base_dir.join("build/blog/post.html")).unwrap(), "by me");
+            assert_eq!(fs::read_to_string(base_dir.join("build/index.html")).unwrap(), "<p>old home</p>");
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn path_mapper_prefixes_every_output() {
+            let builder = site(&[("index.html", "home"), ("blog/post.html", "post"), ("style.css", "p {}")])
+                .with_path_mapper(|path| Path::new("en").join(path));
+            let outputs = build(builder);
+            let paths: Vec<&str> = outputs.keys().map(String::as_str).collect();
+            assert_eq!(paths, ["en/blog/post.html", "en/index.html", "en/style.css"]);
+        }
+
+        #[test]
+        fn path_mapper_cannot_escape_the_output_directory() {
+            let mut builder = site(&[("index.html", "home")]).with_strict(true).with_path_mapper(|path| Path::new("..").join(path));
+            match builder.build_to_memory() {
+                Err(BuildError::PathTraversal(_)) => {}
+                other => panic!("expected a path traversal error, got {:?}", other.map(|outputs| outputs.len())),
+            }
+        }
+
+        #[test]
+        fn pass_order_decides_whether_components_can_pick_the_layout() {
+            let files = [
+                ("el-components/layout-picker.html", r#"<el-layout name="base" />"#),
+                ("el-layouts/base.html", "<main><el-content /></main>"),
+                ("index.html", r#"<el-component name="layout-picker" /><p>hi</p>"#),
+            ];
+            let components_first = build(site(&files));
+            assert_eq!(components_first["index.html"], "<main><p>hi</p></main>");
+            let layout_first = build(site(&files).with_pass_order(PassOrder::LayoutFirst));
+            assert_eq!(layout_first["index.html"], r#"<el-layout name="base" /><p>hi</p>"#);
+        }
+
+        #[test]
+        fn builds_each_locale_with_its_translations() {
+            let base_dir = disk_site("locales", &[("index.html", "<h1>{{ t.greeting }}</h1><p>{{ t.footer }}</p>")]);
+            fs::create_dir_all(base_dir.join("_i18n")).unwrap();
+            fs::write(base_dir.join("_i18n/en.yaml"), "greeting: Hello\nfooter: Thanks").unwrap();
+            fs::write(base_dir.join("_i18n/fr.yaml"), "greeting: Bonjour").unwrap();
+            let builder = SiteBuilder::new(base_dir.clone())
+                .with_progress(false)
+                .with_locales(vec!["en".to_string(), "fr".to_string()], Some("en".to_string()));
+            let outputs = build(builder);
+            assert_eq!(outputs["en/index.html"], "<h1>Hello</h1><p>Thanks</p>");
+            assert_eq!(outputs["fr/index.html"], "<h1>Bonjour</h1><p>Thanks</p>");
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn keep_going_fails_after_reporting_every_page() {
+            let files = [
+                ("a.html", r#"<el-component name="missing-a" />"#),
+                ("b.html", r#"<el-component name="missing-b" />"#),
+                ("c.html", "fine"),
+            ];
+            assert_eq!(build(site(&files)).len(), 3);
+            match site(&files).with_keep_going(true).build_to_memory() {
+                Err(BuildError::ErrorsReported(2)) => {}
+                other => panic!("expected two reported errors, got {:?}", other.map(|outputs| outputs.len())),
+            }
+        }
+
+        #[test]
+        fn builds_entirely_from_memory_fs() {
+            let outputs = build(site(&[
+                ("el-components/nav.html", "<nav></nav>"),
+                ("el-layouts/base.html", "<body><el-component name=\"nav\" /><el-content /></body>"),
+                ("index.html", r#"<el-layout name="base" /><p>home</p>"#),
+                ("style.css", "p {}"),
+            ]));
+            assert_eq!(outputs["index.html"], "<body><nav></nav><p>home</p></body>");
+            assert_eq!(outputs["style.css"], "p {}");
+            assert!(!Path::new("site").exists());
+        }
+
+        #[test]
+        fn cache_false_rereads_a_component_on_every_use() {
+            let base_dir = disk_site("cache-false", &[
+                ("el-components/clock.html", "{{ time }}"),
+                ("el-components/card.html", "card"),
+                ("a.html", "---\ntime: 9:00\n---\n<el-component name=\"clock\" cache=\"false\" /><el-component name=\"card\" />"),
+                ("b.html", "---\ntime: 10:00\n---\n<el-component name=\"clock\" cache=\"false\" /><el-component name=\"card\" />"),
+            ]);
+            for (name, expected_reads) in [("clock.html", 2), ("card.html", 1)] {
+                let reads = Arc::new(Mutex::new(0));
+                let outputs = SiteBuilder::new(base_dir.clone())
+                    .with_source_fs(CountingFs { name, reads: reads.clone() })
+                    .with_progress(false)
+                    .build_to_memory()
+                    .unwrap();
+                assert_eq!(outputs[Path::new("a.html")], b"9:00card");
+                assert_eq!(outputs[Path::new("b.html")], b"10:00card");
+                assert_eq!(*reads.lock().unwrap(), expected_reads, "reads of {}", name);
+            }
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn redirects_file_combines_config_and_front_matter() {
+            let redirects = vec![Redirect { from: "/old".to_string(), to: "/new".to_string(), status: Some(302) }];
+            let builder = site(&[("index.html", "home"), ("blog/post.html", "---\nredirect_from: [/2019/post]\n---\npost")])
+                .with_redirects(redirects);
+            let outputs = build(builder);
+            assert_eq!(outputs["_redirects"], "/old /new 302\n/2019/post /blog/post.html 301\n");
+        }
+
+        #[test]
+        fn parallel_asset_copies_match_serial_ones() {
+            let assets: Vec<(String, String)> = (0..40)
+                .map(|i| (format!("assets/{}/file-{}.css", i % 4, i), format!("p.n{} {{ width: {}px }}", i, i * 7).repeat(i + 1)))
+                .collect();
+            let mut files: Vec<(&str, &str)> = assets.iter().map(|(path, contents)| (path.as_str(), contents.as_str())).collect();
+            files.push(("index.html", "home"));
+            let base_dir = disk_site("copy-jobs", &files);
+            let read_outputs = |dest_dir: &Path| -> Vec<Vec<u8>> {
+                assets.iter().map(|(path, _)| fs::read(dest_dir.join(path)).unwrap()).collect()
+            };
+            SiteBuilder::new(base_dir.clone()).with_progress(false).with_dest_dir(base_dir.join("serial")).build().unwrap();
+            SiteBuilder::new(base_dir.clone()).with_progress(false).with_dest_dir(base_dir.join("parallel")).with_copy_jobs(8).build().unwrap();
+            let serial = read_outputs(&base_dir.join("serial"));
+            assert_eq!(serial, read_outputs(&base_dir.join("parallel")));
+            assert_eq!(serial[5], assets[5].1.as_bytes());
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn bare_component_names_resolve_to_a_unique_deep_component() {
+            let outputs = build(site(&[
+                ("el-components/ui/forms/button.html", "<button></button>"),
+                ("index.html", r#"<el-component name="button" />"#),
+            ]));
+            assert_eq!(outputs["index.html"], "<button></button>");
+        }
+
+        #[test]
+        fn ambiguous_bare_component_names_are_an_error() {
+            let mut builder = site(&[
+                ("el-components/ui/button.html", "a"),
+                ("el-components/forms/button.html", "b"),
+                ("index.html", r#"<el-component name="button" />"#),
+            ]).with_strict(true);
+            match builder.build_to_memory() {
+                Err(BuildError::AmbiguousComponent { name, candidates, .. }) => {
+                    assert_eq!(name, "button");
+                    assert_eq!(candidates, [PathBuf::from("el-components/forms/button.html"), PathBuf::from("el-components/ui/button.html")]);
+                }
+                other => panic!("expected an ambiguous component error, got {:?}", other.map(|outputs| outputs.len())),
+            }
+        }
+
+        #[test]
+        fn integrity_hashes_local_scripts() {
+            let builder = site(&[
+                ("js/app.js", "console.log(1);"),
+                ("index.html", r#"<script src="/js/app.js"></script><script src="https://cdn.example.com/lib.js"></script>"#),
+            ]).with_integrity(true);
+            let outputs = build(builder);
+            assert_eq!(
+                outputs["index.html"],
+                concat!(
+                    r#"<script src="/js/app.js" integrity="sha384-JawyHuhqEMFMvdtX+VHylbI0hfJp2F7nvwFVRqqfuOoK5oW7TG/7V11Zs7zeFWIE" crossorigin="anonymous"></script>"#,
+                    r#"<script src="https://cdn.example.com/lib.js"></script>"#,
+                )
+            );
+        }
+
+        #[test]
+        fn verbatim_html_is_copied_unchanged() {
+            let vendored = r#"<div><el-component name="widget" />{{ not_a_variable }}</div>"#;
+            let builder = site(&[
+                ("el-components/widget.html", "widget"),
+                ("vendor/docs/index.html", vendored),
+                ("index.html", r#"<el-component name="widget" />"#),
+            ]).with_verbatim_dirs(vec![PathBuf::from("vendor")]);
+            let outputs = build(builder);
+            assert_eq!(outputs["vendor/docs/index.html"], vendored);
+            assert_eq!(outputs["index.html"], "widget");
+        }
+
+        #[test]
+        fn a_second_build_of_the_same_output_sees_the_lock() {
+            let base_dir = disk_site("build-lock", &[("index.html", "home")]);
+            let second = std::rc::Rc::new(RefCell::new(None));
+            let (seen, dir) = (second.clone(), base_dir.clone());
+            SiteBuilder::new(base_dir.clone())
+                .with_progress(false)
+                .with_transform(move |_, page| {
+                    *seen.borrow_mut() = Some(SiteBuilder::new(dir.clone()).with_progress(false).build());
+                    page
+                })
+                .build()
+                .unwrap();
+            match second.borrow_mut().take() {
+                Some(Err(BuildError::BuildLocked(lock))) => assert_eq!(lock, base_dir.join(".build.elss-lock")),
+                other => panic!("expected the second build to be locked out, got {:?}", other.map(|result| result.is_ok())),
+            }
+            SiteBuilder::new(base_dir.clone()).with_progress(false).build().unwrap();
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn each_file_renders_a_block_per_matching_file() {
+            let page = r#"<ul><el-each-file dir="images" ext="jpg"><li><img src="{{ file.path }}" alt="{{ file.stem }}"></li></el-each-file></ul><el-each-file dir="empty" ext="jpg">x</el-each-file>"#;
+            let outputs = build(site(&[
+                ("images/b.jpg", "b"),
+                ("images/a.jpg", "a"),
+                ("images/c.jpg", "c"),
+                ("images/notes.txt", "skip"),
+                ("empty/readme.txt", "none"),
+                ("index.html", page),
+            ]));
+            assert_eq!(
+                outputs["index.html"],
+                concat!(
+                    r#"<ul><li><img src="/images/a.jpg" alt="a"></li>"#,
+                    r#"<li><img src="/images/b.jpg" alt="b"></li>"#,
+                    r#"<li><img src="/images/c.jpg" alt="c"></li></ul>"#,
+                )
+            );
+        }
+
+        #[test]
+        fn nested_paired_components_get_their_own_content() {
+            let outputs = build(site(&[
+                ("el-components/a.html", "<div><el-slot /></div>"),
+                ("el-components/b.html", "<b><el-slot /></b>"),
+                ("index.html", r#"<el-component name="a"><el-component name="b">x</el-component></el-component>|<el-component name="a"><el-component name="a">y</el-component></el-component>"#),
+            ]));
+            assert_eq!(outputs["index.html"], "<div><b>x</b></div>|<div><div>y</div></div>");
+        }
+
+        #[test]
+        fn pages_can_opt_out_of_the_default_layout() {
+            let outputs = build(site(&[
+                ("_layout.html", r#"<el-layout name="base" />"#),
+                ("el-layouts/base.html", "<html><el-content /></html>"),
+                ("index.html", "<p>home</p>"),
+                ("partials/row.html", r#"<el-layout name="none" /><tr></tr>"#),
+                ("partials/cell.html", "---\nlayout: false\n---\n<td></td>"),
+            ]));
+            assert_eq!(outputs["index.html"], "<html><p>home</p></html>");
+            assert_eq!(outputs["partials/row.html"], "<tr></tr>");
+            assert_eq!(outputs["partials/cell.html"], "<td></td>");
+        }
+
+        #[test]
+        fn report_counts_bytes_written_and_saved() {
+            let base_dir = disk_site("bytes-saved", &[("index.html", "<p>hi</p><!-- a note -->"), ("style.css", "p {}")]);
+            let report = SiteBuilder::new(base_dir.clone()).with_progress(false).with_strip_comments(true).build().unwrap();
+            assert_eq!(report.bytes_written, ("<p>hi</p>".len() + "p {}".len()) as u64);
+            assert_eq!(report.bytes_saved, "<!-- a note -->".len() as i64);
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn auto_index_lists_the_pages_of_a_directory_without_an_index() {
+            let auto_index = AutoIndex { dirs: vec!["notes".to_string()], template: "el-layouts/dir-index.html".to_string() };
+            let outputs = build(site(&[
+                ("el-layouts/dir-index.html", "<h1>{{ index.dir }} ({{ index.count }})</h1>{{ index.entries }}"),
+                ("notes/first.html", "---\ntitle: First note\n---\none"),
+                ("notes/second.html", "<title>Second note</title>two"),
+            ]).with_auto_index(Some(auto_index)));
+            assert_eq!(
+                outputs["notes/index.html"],
+                "<h1>notes (2)</h1><ul>\n<li><a href=\"/notes/first.html\">First note</a></li>\n<li><a href=\"/notes/second.html\">Second note</a></li>\n</ul>"
+            );
+        }
+
+        #[test]
+        fn csp_nonce_is_shared_within_a_page() {
+            let page = r#"<meta name="csp-nonce" content="{{ nonce }}"><script>run()</script><style nonce="kept">p {}</style>"#;
+            let outputs = build(site(&[("a.html", page), ("b.html", page)]).with_csp_nonce(true));
+            let nonce_of = |page: &str| {
+                let nonce = html::attribute(page, "content").unwrap().to_string();
+                assert_eq!(page, format!(r#"<meta name="csp-nonce" content="{0}"><script nonce="{0}">run()</script><style nonce="kept">p {{}}</style>"#, nonce));
+                nonce
+            };
+            assert_ne!(nonce_of(&outputs["a.html"]), nonce_of(&outputs["b.html"]));
+        }
+
+        #[test]
+        fn incremental_builds_rebuild_pages_whose_dependencies_changed_by_content() {
+            let base_dir = disk_site("incremental", &[
+                ("el-components/card.html", "<div>card</div>"),
+                ("a.html", r#"<el-component name="card" />"#),
+                ("b.html", "<p>b</p>"),
+            ]);
+            let incremental_build = || SiteBuilder::new(base_dir.clone()).with_progress(false).with_incremental(true).build().unwrap();
+            let reused = |report: &BuildReport| -> Vec<PathBuf> {
+                let mut reused: Vec<PathBuf> = report.reused.iter().map(|path| path.strip_prefix(base_dir.join("build")).unwrap().to_path_buf()).collect();
+                reused.sort();
+                reused
+            };
+            assert!(incremental_build().reused.is_empty());
+            assert_eq!(reused(&incremental_build()), [PathBuf::from("a.html"), PathBuf::from("b.html")]);
+            fs::write(base_dir.join("src/el-components/card.html"), "<div>card</div>\n").unwrap();
+            assert_eq!(reused(&incremental_build()), [PathBuf::from("b.html")]);
+            assert_eq!(fs::read_to_string(base_dir.join("build/a.html")).unwrap(), "<div>card</div>\n");
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn check_links_reports_only_dead_internal_links() {
+            let files = [
+                ("about.html", "about"),
+                ("index.html", r#"<el-link to="/about.html">About</el-link> <el-link to="/missing.html" />"#),
+            ];
+            let outputs = build(site(&files).with_link_checking(true));
+            assert_eq!(outputs["index.html"], r#"<a href="/about.html">About</a> <a href="/missing.html">/missing.html</a>"#);
+            match site(&files).with_link_checking(true).with_keep_going(true).build_to_memory() {
+                Err(BuildError::ErrorsReported(1)) => {}
+                other => panic!("expected one dead link, got {:?}", other.map(|outputs| outputs.len())),
+            }
+            match site(&files).with_link_checking(true).with_strict(true).build_to_memory() {
+                Err(BuildError::DeadLink { file, link }) => assert_eq!((file, link.as_str()), (PathBuf::from("index.html"), "/missing.html")),
+                other => panic!("expected a dead link error, got {:?}", other.map(|outputs| outputs.len())),
+            }
+        }
+
+        #[test]
+        fn default_slot_uses_children_or_its_fallback() {
+            let outputs = build(site(&[
+                ("el-components/card.html", "<div><el-slot>Default card body</el-slot></div>"),
+                ("index.html", r#"<el-component name="card">actual</el-component>|<el-component name="card"></el-component>|<el-component name="card" />"#),
+            ]));
+            assert_eq!(outputs["index.html"], "<div>actual</div>|<div>Default card body</div>|<div>Default card body</div>");
+        }
+
+        #[test]
+        fn check_deterministic_finds_outputs_that_differ_between_builds() {
+            let files = [("index.html", "<p>home</p>"), ("script.html", "<script>run()</script>"), ("style.css", "p {}")];
+            assert!(site(&files).check_deterministic().unwrap().is_empty());
+            let differing = site(&files).with_csp_nonce(true).check_deterministic().unwrap();
+            assert_eq!(differing, [PathBuf::from("script.html")]);
+        }
+
+        #[test]
+        fn asset_inline_inlines_files_up_to_the_threshold() {
+            let large = format!("<svg>{}</svg>", "<g></g>".repeat(20));
+            let outputs = build(site(&[
+                ("icons/small.svg", "<svg><g></g></svg>"),
+                ("icons/large.svg", &large),
+                ("icons/dot.png", "PNG"),
+                ("index.html", r#"<el-asset-inline src="icons/small.svg" max="64" class="icon" /><el-asset-inline src="icons/large.svg" max="64" alt="" /><el-asset-inline src="icons/dot.png" max="64" alt="dot" />"#),
+            ]));
+            assert_eq!(
+                outputs["index.html"],
+                concat!(
+                    r#"<svg class="icon"><g></g></svg>"#,
+                    r#"<img src="icons/large.svg" alt="">"#,
+                    r#"<img src="data:image/png;base64,UE5H" alt="dot">"#,
+                )
+            );
+            assert!(outputs.contains_key("icons/large.svg"));
+        }
+
+        #[test]
+        fn custom_directives_render_from_their_attributes() {
+            let builder = site(&[("posts/hello.html", "---\nlang: en\n---\n<p>Posted <el-date value=\"2024-03-09\" /></p>")])
+                .with_directive("el-date", |attributes, context| {
+                    let value = attributes.iter().find(|(key, _)| *key == "value").map_or("", |(_, value)| *value);
+                    let parts: Vec<&str> = value.split('-').collect();
+                    let months = ["January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December"];
+                    let month = months[parts[1].parse::<usize>().unwrap() - 1];
+                    format!("{} {}, {} ({}, {})", month, parts[2].trim_start_matches('0'), parts[0], context.page.display(), context.variables["lang"])
+                });
+            let outputs = build(builder);
+            assert_eq!(outputs["posts/hello.html"], "<p>Posted March 9, 2024 (posts/hello.html, en)</p>");
+        }
+
+        #[test]
+        fn bundle_docs_joins_pages_in_order_with_unique_anchors() {
+            let mut builder = site(&[
+                ("intro.html", r#"<html><head><title>Docs</title></head><body><h1 id="top">Intro</h1><a href="guide/install.html#steps">install</a></body></html>"#),
+                ("guide/install.html", r#"<h1>Install</h1><h2 id="steps">Steps</h2>"#),
+                ("guide-install.html", "<h1>More</h1>"),
+            ]);
+            let pages = [PathBuf::from("intro.html"), PathBuf::from("guide/install.html"), PathBuf::from("guide-install.html")];
+            let combined = builder.bundle_docs(&pages).unwrap();
+            assert_eq!(combined, concat!(
+                "<!DOCTYPE html>\n<html><head><title>Docs</title></head>\n<body>\n",
+                "<section id=\"intro\">\n<h2 id=\"intro-top\">Intro</h2><a href=\"#guide-install-steps\">install</a>\n</section>\n",
+                "<section id=\"guide-install\">\n<h2>Install</h2><h3 id=\"guide-install-steps\">Steps</h3>\n</section>\n",
+                "<section id=\"guide-install-2\">\n<h2>More</h2>\n</section>\n",
+                "</body>\n</html>\n",
+            ));
+        }
+
+        #[test]
+        fn drafts_are_only_built_with_drafts_on() {
+            let auto_index = || Some(AutoIndex { dirs: vec!["posts".to_string()], template: "el-layouts/list.html".to_string() });
+            let files = [
+                ("el-layouts/list.html", "{{ index.count }}"),
+                ("posts/done.html", "---\ntitle: Done\n---\ndone"),
+                ("posts/wip.html", "---\ntitle: WIP\ndraft: true\nredirect_from: [/wip]\n---\nwip"),
+            ];
+            let outputs = build(site(&files).with_auto_index(auto_index()));
+            assert!(!outputs.contains_key("posts/wip.html"));
+            assert!(!outputs.contains_key("_redirects"));
+            assert_eq!(outputs["posts/index.html"], "1");
+            let outputs = build(site(&files).with_auto_index(auto_index()).with_drafts(true));
+            assert_eq!(outputs["posts/wip.html"], "wip");
+            assert_eq!(outputs["_redirects"], "/wip /posts/wip.html 301\n");
+            assert_eq!(outputs["posts/index.html"], "2");
+        }
+
+        #[test]
+        fn missing_components_are_replaced_with_the_configured_placeholder() {
+            let placeholder = r#"<div class="missing">missing: {{ name }}</div>"#.to_string();
+            let page = r#"<el-component name="hero" /><el-component name="card" />"#;
+            let outputs = build(site(&[("el-components/card.html", "card"), ("index.html", page)]).with_missing_component(Some(placeholder)));
+            assert_eq!(outputs["index.html"], r#"<div class="missing">missing: hero</div>card"#);
+            assert_eq!(build(site(&[("el-components/card.html", "card"), ("index.html", page)]))["index.html"], "card");
+        }
+
+        #[test]
+        fn handlers_choose_between_flattening_and_copying_by_extension() {
+            let handlers = HashMap::from([
+                ("htm".to_string(), FileHandler::Flatten),
+                ("txt".to_string(), FileHandler::Copy),
+                ("html".to_string(), FileHandler::Copy),
+            ]);
+            let component = r#"<el-component name="sig" />"#;
+            let outputs = build(site(&[
+                ("el-components/sig.html", "signed"),
+                ("page.htm", component),
+                ("notes.txt", component),
+                ("raw.html", component),
+                ("data.unknown", component),
+            ]).with_handlers(handlers));
+            assert_eq!(outputs["page.htm"], "signed");
+            assert_eq!(outputs["notes.txt"], component);
+            assert_eq!(outputs["raw.html"], component);
+            assert_eq!(outputs["data.unknown"], component);
+        }
+
+        #[test]
+        fn explain_traces_the_layout_and_each_component() {
+            let mut builder = site(&[
+                ("el-components/nav.html", "<nav></nav>"),
+                ("el-components/card.html", "<div><el-slot /></div>"),
+                ("el-layouts/base.html", "<body><el-component name=\"nav\" /><el-content /></body>"),
+                ("index.html", r#"<el-layout name="base" /><el-component name="card">hi</el-component>"#),
+            ]);
+            let trace = builder.explain(Path::new("index.html")).unwrap();
+            assert_eq!(trace, [
+                "component [card] in [index.html] resolved to [el-components/card.html]",
+                "slot in [el-components/card.html] filled with 2 bytes from [index.html]",
+                "layout [base] applied from [el-layouts/base.html]",
+                "component [nav] in [el-layouts/base.html] resolved to [el-components/nav.html]",
+                "variables substituted in [index.html]: none",
+                "output [index.html]: 37 bytes",
+            ]);
+        }
+
+        #[test]
+        fn build_with_progress_reports_events_in_order() {
+            let events = std::rc::Rc::new(RefCell::new(Vec::new()));
+            let collected = events.clone();
+            let files = [("el-components/todo.html", ""), ("a.html", "a"), ("b.html", r#"<el-component name="todo" />"#)];
+            let mut builder = site(&files).with_warn_empty(true).with_dry_run(true);
+            builder.build_with_progress(move |event| {
+                collected.borrow_mut().push(match event {
+                    BuildEvent::Started => "started".to_string(),
+                    BuildEvent::FileProcessed { path } => format!("processed {}", path.display()),
+                    BuildEvent::Warning { message } => format!("warning {}", message),
+                    BuildEvent::Finished { report } => format!("finished {}", report.written.len()),
+                });
+            }).unwrap();
+            assert_eq!(*events.borrow(), [
+                "started",
+                "processed a.html",
+                "warning [site/src/el-components/todo.html] is empty",
+                "processed b.html",
+                "finished 2",
+            ]);
+        }
+
+        #[test]
+        fn version_meta_stamps_pages_with_a_head() {
+            let files = [("index.html", "<html><head><title>t</title></head><body></body></html>"), ("fragment.html", "<p>x</p>")];
+            let builder = || site(&files).with_version_meta(true).with_build_id(Some("abc123".to_string()));
+            let outputs = build(builder());
+            assert_eq!(
+                outputs["index.html"],
+                format!(r#"<html><head><title>t</title><meta name="generator" content="elss {}"><meta name="build-id" content="abc123"></head><body></body></html>"#, env!("CARGO_PKG_VERSION"))
+            );
+            assert_eq!(outputs["fragment.html"], "<p>x</p>");
+            let reported = warnings(builder());
+            assert_eq!(reported.len(), 1, "{:?}", reported);
+            assert!(reported[0].contains("fragment.html"), "{:?}", reported);
+        }
+
+        #[test]
+        fn component_roots_resolve_prefixed_names() {
+            let files = [
+                ("../vendor/ui/button.html", "<button>ok</button>"),
+                ("index.html", r#"<el-component name="@ui/button" />"#),
+                ("other.html", r#"<el-component name="@forms/input" />"#),
+            ];
+            let roots = || HashMap::from([("ui".to_string(), "vendor/ui/".to_string())]);
+            let outputs = build(site(&files).with_component_roots(roots()));
+            assert_eq!(outputs["index.html"], "<button>ok</button>");
+            match site(&files).with_component_roots(roots()).with_strict(true).build_to_memory() {
+                Err(BuildError::UnknownComponentRoot { file, name }) => assert_eq!((file, name.as_str()), (PathBuf::from("other.html"), "@forms/input")),
+                other => panic!("expected an unknown component root error, got {:?}", other.map(|outputs| outputs.len())),
+            }
+        }
+
+        #[test]
+        fn builds_write_the_same_output_with_and_without_fsync() {
+            for fsync in [false, true] {
+                let base_dir = disk_site(&format!("fsync-{}", fsync), &[("index.html", "<p>home</p>"), ("css/site.css", "p {}")]);
+                SiteBuilder::new(base_dir.clone()).with_progress(false).with_fsync(fsync).build().unwrap();
+                assert_eq!(fs::read_to_string(base_dir.join("build/index.html")).unwrap(), "<p>home</p>");
+                assert_eq!(fs::read_to_string(base_dir.join("build/css/site.css")).unwrap(), "p {}");
+                fs::remove_dir_all(&base_dir).unwrap();
+            }
+        }
+
+        #[test]
+        fn a11y_lint_warns_about_an_image_without_alt() {
+            let files = [("index.html", r#"<p><img src="cat.jpg"></p>"#)];
+            assert!(warnings(site(&files)).is_empty());
+            assert_eq!(warnings(site(&files).with_a11y_lint(true)), [r#"page [index.html]: <img src="cat.jpg"> on line 1 has no alt attribute"#]);
+        }
+
+        #[test]
+        fn content_tag_names_the_layout_content_placeholder() {
+            let files = [
+                ("el-layouts/base.html", "<main><page-body /></main><el-content />"),
+                ("index.html", r#"<el-layout name="base" /><p>x</p>"#),
+            ];
+            let outputs = build(site(&files).with_content_tag(Some("page-body".to_string())));
+            assert_eq!(outputs["index.html"], "<main><p>x</p></main><el-content />");
+        }
+
+        #[test]
+        fn usage_report_counts_invocations_per_page() {
+            let base_dir = disk_site("usage-report", &[
+                ("el-components/card.html", "<div><el-component name=\"icon\" /></div>"),
+                ("el-components/icon.html", "<i></i>"),
+                ("el-layouts/base.html", "<el-content />"),
+                ("a.html", r#"<el-layout name="base" /><el-component name="card" /><el-component name="card" />"#),
+                ("b.html", r#"<el-component name="icon" />"#),
+            ]);
+            SiteBuilder::new(base_dir.clone()).with_progress(false).with_usage_report(true).build().unwrap();
+            assert_eq!(fs::read_to_string(base_dir.join("usage.json")).unwrap(), concat!(
+                "{\n",
+                "  \"el-components/card.html\": {\"count\": 2, \"pages\": [\"a.html\"]},\n",
+                "  \"el-components/icon.html\": {\"count\": 3, \"pages\": [\"a.html\", \"b.html\"]},\n",
+                "  \"el-layouts/base.html\": {\"count\": 1, \"pages\": [\"a.html\"]}\n",
+                "}\n",
+            ));
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn a_permalink_with_a_disallowed_extension_fails_the_build() {
+            let allowed = ["html".to_string(), ".CSS".to_string()];
+            let files = [("index.html", "home"), ("style.css", "p {}"), ("CNAME", "example.com")];
+            assert_eq!(build(site(&files).with_allowed_output_extensions(&allowed)).len(), 3);
+            let mut builder = site(&[("index.html", "home"), ("contact.html", "---\npermalink: /contact.php\n---\nform")])
+                .with_allowed_output_extensions(&allowed);
+            match builder.build_to_memory() {
+                Err(BuildError::DisallowedExtension(output)) => assert_eq!(output, PathBuf::from("contact.php")),
+                other => panic!("expected a disallowed extension error, got {:?}", other.map(|outputs| outputs.len())),
+            }
+        }
+
+        #[test]
+        fn reused_read_buffers_leave_no_trace_of_earlier_components() {
+            let big = format!("<section>{}<el-component name=\"small\" /></section>", "<p>filler</p>".repeat(2000));
+            let base_dir = disk_site("read-buffer", &[
+                ("el-components/big.html", &big),
+                ("el-components/small.html", "---\nkind: tiny\n---\n<b>tiny</b>"),
+                ("index.html", r#"<el-component name="big" /><el-component name="small" /><el-component name="big" />"#),
+            ]);
+            let expected_big = big.replace(r#"<el-component name="small" />"#, "<b>tiny</b>");
+            for use_cache in [false, true] {
+                let outputs = SiteBuilder::new(base_dir.clone()).with_progress(false).with_cache(use_cache).build_to_memory().unwrap();
+                assert_eq!(String::from_utf8_lossy(&outputs[Path::new("index.html")]), format!("{0}<b>tiny</b>{0}", expected_big));
+            }
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn env_blocks_are_kept_only_for_their_environment() {
+            let files = [("index.html", r#"<p>home</p><el-env name="prod"><script src="analytics.js"></script></el-env><el-env name="dev"><p>debug</p></el-env>"#)];
+            let build_for = |env: Option<&str>| build(site(&files).with_env(env.map(str::to_string)))["index.html"].clone();
+            assert_eq!(build_for(Some("prod")), r#"<p>home</p><script src="analytics.js"></script>"#);
+            assert_eq!(build_for(Some("dev")), "<p>home</p><p>debug</p>");
+            assert_eq!(build_for(None), "<p>home</p>");
+        }
+
+        #[test]
+        fn postprocessors_only_touch_outputs_with_their_extension() {
+            let builder = site(&[("index.html", "<p>home</p>"), ("css/site.css", "p {}"), ("js/app.js", "run()")])
+                .with_postprocessor("css", |output, css| format!("{}\n/* {} */", css, output.display()));
+            let outputs = build(builder);
+            assert_eq!(outputs["css/site.css"], "p {}\n/* css/site.css */");
+            assert_eq!(outputs["index.html"], "<p>home</p>");
+            assert_eq!(outputs["js/app.js"], "run()");
+        }
+
+        #[test]
+        fn pure_components_are_expanded_once_per_build() {
+            let base_dir = disk_site("pure-components", &[
+                ("el-components/icon.html", "<svg></svg>"),
+                ("el-components/greeting.html", "<p>{{ name }}</p>"),
+                ("a.html", "---\nname: A\n---\n<el-component name=\"icon\" /><el-component name=\"greeting\" />"),
+                ("b.html", "---\nname: B\n---\n<el-component name=\"icon\" /><el-component name=\"greeting\" /><el-component name=\"icon\" cache=\"false\" />"),
+            ]);
+            for (name, expected_reads) in [("icon.html", 2), ("greeting.html", 2)] {
+                let reads = Arc::new(Mutex::new(0));
+                let outputs = SiteBuilder::new(base_dir.clone())
+                    .with_source_fs(CountingFs { name, reads: reads.clone() })
+                    .with_cache(false)
+                    .with_progress(false)
+                    .build_to_memory()
+                    .unwrap();
+                assert_eq!(outputs[Path::new("a.html")], b"<svg></svg><p>A</p>");
+                assert_eq!(outputs[Path::new("b.html")], b"<svg></svg><p>B</p><svg></svg>");
+                assert_eq!(*reads.lock().unwrap(), expected_reads, "reads of {}", name);
+            }
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn static_files_are_copied_to_the_output_root() {
+            let files = [
+                ("static/robots.txt", "User-agent: *"),
+                ("static/about.html", "static about"),
+                ("about.html", "<p>about</p>"),
+            ];
+            let builder = || site(&files).with_static_dir(Some(PathBuf::from("static")));
+            let outputs = build(builder());
+            assert_eq!(outputs["robots.txt"], "User-agent: *");
+            assert_eq!(outputs["about.html"], "<p>about</p>");
+            assert!(!outputs.keys().any(|output| output.starts_with("static/")));
+            let reported = warnings(builder());
+            assert_eq!(reported.len(), 1, "{:?}", reported);
+            assert!(reported[0].contains("is shadowed by"), "{:?}", reported);
+        }
+
+        #[test]
+        fn reading_time_counts_the_page_text_at_200_words_a_minute() {
+            let long = format!("<el-layout name=\"post\" /><p>{}</p>", "<b>word</b> ".repeat(401));
+            let outputs = build(site(&[
+                ("el-layouts/post.html", "<span>{{ page.reading_time }} min read</span><el-content />"),
+                ("long.html", &long),
+                ("short.html", r#"<el-layout name="post" /><p>Just a few words.</p>"#),
+            ]));
+            assert!(outputs["long.html"].starts_with("<span>3 min read</span>"));
+            assert_eq!(outputs["short.html"], "<span>1 min read</span><p>Just a few words.</p>");
+        }
+
+        #[test]
+        fn relative_links_rewrite_root_relative_urls_by_page_depth() {
+            let page = r#"<link href="/style.css"><img src="/img/a.png"><a href="https://example.com/x">x</a>"#;
+            let outputs = build(site(&[("index.html", page), ("blog/2024/post.html", page)]).with_relative_links(true));
+            assert_eq!(outputs["blog/2024/post.html"], r#"<link href="../../style.css"><img src="../../img/a.png"><a href="https://example.com/x">x</a>"#);
+            assert_eq!(outputs["index.html"], r#"<link href="./style.css"><img src="./img/a.png"><a href="https://example.com/x">x</a>"#);
+        }
+
+        #[test]
+        fn defined_snippets_expand_where_used_and_are_removed() {
+            let outputs = build(site(&[
+                ("a.html", r#"<el-define name="badge"><span class="badge">new</span></el-define><h2>One <el-use name="badge" /></h2><h2>Two <el-use name="badge"></el-use></h2>"#),
+                ("b.html", r#"<p><el-use name="badge" /></p>"#),
+            ]));
+            assert_eq!(outputs["a.html"], r#"<h2>One <span class="badge">new</span></h2><h2>Two <span class="badge">new</span></h2>"#);
+            assert_eq!(outputs["b.html"], r#"<p><el-use name="badge" /></p>"#);
+        }
+
+        #[test]
+        fn encoding_lint_reports_control_characters_with_their_offset() {
+            let files = [("el-components/card.html", "<div>a\u{7}b</div>\t\r\n"), ("index.html", r#"<el-component name="card" />"#)];
+            assert!(warnings(site(&files)).is_empty());
+            let reported = warnings(site(&files).with_encoding_lint(true));
+            assert_eq!(reported, ["[site/src/el-components/card.html] has control character U+0007 at byte 6"]);
+        }
+
+        #[test]
+        fn include_if_inlines_the_file_only_when_the_variable_is_truthy() {
+            let page = r#"<el-include-if src="partials/banner.html" var="promo" /><p>body</p>"#;
+            let outputs = build(site(&[
+                ("partials/banner.html", "<div>{{ promo }} sale</div>"),
+                ("on.html", &format!("---\npromo: Spring\n---\n{}", page)),
+                ("off.html", &format!("---\npromo: false\n---\n{}", page)),
+                ("unset.html", page),
+            ]));
+            assert_eq!(outputs["on.html"], "<div>Spring sale</div><p>body</p>");
+            assert_eq!(outputs["off.html"], "<p>body</p>");
+            assert_eq!(outputs["unset.html"], "<p>body</p>");
+        }
+
+        #[test]
+        fn include_if_cannot_reach_outside_the_source_directory() {
+            let mut builder = site(&[("index.html", "---\npromo: yes\n---\n<el-include-if src=\"../secret.html\" var=\"promo\" />")]).with_strict(true);
+            match builder.build_to_memory() {
+                Err(BuildError::PathTraversal(_)) => {}
+                other => panic!("expected a path traversal error, got {:?}", other.map(|outputs| outputs.len())),
+            }
+        }
+
+        #[test]
+        fn todo_markers_are_listed_by_file_and_line() {
+            let builder = site(&[
+                ("el-components/card.html", "<div>\n<!-- FIXME: escape the\n   title -->\n</div>"),
+                ("index.html", "---\ntitle: Home\n---\n<p>hi</p>\n<!-- TODO: add a hero -->"),
+                ("style.css", "/* TODO: not html */"),
+            ]);
+            let markers: Vec<(PathBuf, usize, String, String)> = builder.todo_markers().unwrap().into_iter()
+                .map(|marker| (marker.file, marker.line, marker.marker, marker.note))
+                .collect();
+            assert_eq!(markers, [
+                (PathBuf::from("el-components/card.html"), 2, "FIXME".to_string(), "escape the title".to_string()),
+                (PathBuf::from("index.html"), 5, "TODO".to_string(), "add a hero".to_string()),
+            ]);
+        }
+
+        #[test]
+        fn render_for_print_points_links_at_the_sources() {
+            let base_dir = disk_site("print", &[
+                ("el-layouts/base.html", r#"<html><head><link rel="stylesheet" href="/css/print.css"></head><body><el-content /></body></html>"#),
+                ("docs/report.html", r#"<el-layout name="base" /><img src="chart.png"><a href="/docs/other.html">other</a>"#),
+            ]);
+            let printed = SiteBuilder::new(base_dir.clone()).with_progress(false).render_for_print(Path::new("docs/report.html")).unwrap();
+            let base = html::file_url(&fs::canonicalize(base_dir.join("src")).unwrap().join("docs"));
+            assert_eq!(printed, format!(
+                r#"<html><head><base href="{}/"><link rel="stylesheet" href="../css/print.css"></head><body><img src="chart.png"><a href="../docs/other.html">other</a></body></html>"#,
+                base.trim_end_matches('/')
+            ));
+            assert!(!base_dir.join("build").exists());
+            fs::remove_dir_all(&base_dir).unwrap();
+        }
+
+        #[test]
+        fn legacy_layout_processes_only_the_first_tag_in_one_pass() {
+            let files = [
+                ("el-layouts/base.html", "<main><el-content /></main>"),
+                ("el-layouts/other.html", "<aside><el-content /></aside>"),
+                ("index.html", r#"<el-layout name="base"></el-layout><el-layout name="other"></el-layout><p>x</p>"#),
+                ("closed.html", r#"<el-layout name="base" /><p>y</p>"#),
+                ("price.html", r#"<el-layout name="base"></el-layout><p>$5, $1 or $name</p>"#),
+            ];
+            let outputs = build(site(&files).with_legacy_layout(true));
+            assert_eq!(outputs["price.html"], r#"<main><el-layout name="base"></el-layout><p>$5, $1 or $name</p></main>"#);
+            assert_eq!(outputs["index.html"], r#"<main><el-layout name="base"></el-layout><el-layout name="other"></el-layout><p>x</p></main>"#);
+            assert_eq!(outputs["closed.html"], r#"<el-layout name="base" /><p>y</p>"#);
+            assert_eq!(build(site(&files))["index.html"], "<aside><main><p>x</p></main></aside>");
+            match site(&[("index.html", r#"<el-layout name="../base"></el-layout>"#)]).with_legacy_layout(true).with_strict(true).build_to_memory() {
+                Err(BuildError::InvalidName { name, .. }) => assert_eq!(name, "../base"),
+                other => panic!("expected an invalid name error, got {:?}", other.map(|outputs| outputs.len())),
+            }
+        }
+
+        #[test]
+        fn warn_empty_covers_root_components_and_layouts_but_not_pages() {
+            let files = [
+                ("../vendor/ui/empty.html", " "),
+                ("el-layouts/blank.html", ""),
+                ("index.html", r#"<el-component name="@ui/empty" /><p>x</p>"#),
+                ("post.html", r#"<el-layout name="blank"></el-layout><p>y</p>"#),
+                ("nothing.html", ""),
+            ];
+            let roots = HashMap::from([("ui".to_string(), "vendor/ui/".to_string())]);
+            let mut reported = warnings(site(&files).with_component_roots(roots).with_warn_empty(true));
+            reported.retain(|message| message.ends_with("is empty"));
+            reported.sort();
+            assert_eq!(reported.len(), 2, "{:?}", reported);
+            assert!(reported[0].contains("vendor/ui/empty.html") && reported[1].contains("blank.html"), "{:?}", reported);
+        }
+
+        #[test]
+        fn private_use_characters_in_sources_are_kept_with_the_source_map_on() {
+            let files = [
+                ("el-components/icon.html", "<i>\u{E001}</i>"),
+                ("index.html", "<p>\u{E000}a</p><el-component name=\"icon\" /><p>\u{E002}b</p>"),
+            ];
+            let outputs = build(site(&files).with_source_map(true));
+            assert_eq!(outputs["index.html"], "<p>\u{E000}a</p><i>\u{E001}</i><p>\u{E002}b</p>");
+            assert_eq!(outputs["index.html.map"], concat!(
+                "{\"file\":\"index.html\",\"ranges\":[\n",
+                "{\"start\":0,\"end\":11,\"source\":\"index.html\"},\n",
+                "{\"start\":11,\"end\":21,\"source\":\"el-components/icon.html\"},\n",
+                "{\"start\":21,\"end\":32,\"source\":\"index.html\"}\n",
+                "]}\n",
+            ));
+            assert_eq!(build(site(&files))["index.html"], outputs["index.html"]);
+        }
+
+        #[test]
+        fn a_failed_read_hands_the_read_buffer_back() {
+            let mut builder = site(&[("index.html", &"<p>x</p>".repeat(100))]);
+            builder.render_component(Path::new("index.html")).unwrap();
+            let capacity = builder.read_buffer.capacity();
+            assert!(capacity >= 800);
+            assert_eq!(builder.render_component(Path::new("missing.html")).unwrap(), "");
+            assert_eq!(builder.read_buffer.capacity(), capacity);
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,530 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use crate::error::BuildError;
+
+pub const CONFIG_FILE: &str = "elss.toml";
+
+// Only the subset of TOML that elss.toml needs: tables, strings, integers,
+// booleans and arrays of those.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(BTreeMap<String, Value>),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&BTreeMap<String, Value>> {
+        match self {
+            Value::Table(table) => Some(table),
+            _ => None,
+        }
+    }
+}
+
+// What a layout without an <el-content /> placeholder does with the page body.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MissingContent {
+    Drop,
+    Append,
+    #[default]
+    Error,
+}
+
+// Which of a page's passes runs first. Components-first expands the page's components and
+// then applies its layout, so a component can still emit the page's <el-layout> tag.
+// Layout-first puts the raw page into its layout and then expands the components of the
+// combined document, so page components are expanded in place inside the layout.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PassOrder {
+    #[default]
+    ComponentsFirst,
+    LayoutFirst,
+}
+
+// Settings for a generated robots.txt. Each rule is one line, e.g. "Disallow: /drafts/".
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Robots {
+    pub rules: Vec<String>,
+    pub sitemap: Option<String>,
+}
+
+// How files with an extension listed under [handlers] are processed: flattened as pages or
+// copied as they are. Extensions not listed are copied, except html, which is flattened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileHandler {
+    Flatten,
+    Copy,
+}
+
+// Directories that get a generated index page when they have none. The template, relative
+// to src/, is built once per directory; keep it under el-layouts so it isn't a page itself.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AutoIndex {
+    pub dirs: Vec<String>,
+    pub template: String,
+}
+
+// One line of a generated _redirects file, written in config as "/old -> /new 301". The
+// status is optional; hosts default to 301.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub from: String,
+    pub to: String,
+    pub status: Option<u16>,
+}
+
+impl Redirect {
+    pub fn parse(rule: &str) -> Result<Redirect, String> {
+        let invalid = || format!("redirect [{}] must look like \"/old -> /new 301\"", rule);
+        let (from, rest) = rule.split_once("->").ok_or_else(invalid)?;
+        let mut rest = rest.split_whitespace();
+        let to = rest.next().ok_or_else(invalid)?;
+        let status = match rest.next() {
+            Some(status) => Some(status.parse().map_err(|_| invalid())?),
+            None => None,
+        };
+        let from = from.trim();
+        if from.is_empty() || from.contains(char::is_whitespace) || rest.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Redirect { from: from.to_string(), to: to.to_string(), status })
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    pub dest_dir: Option<String>,
+    pub aliases: HashMap<String, String>,
+    pub component_roots: HashMap<String, String>,
+    pub handlers: HashMap<String, FileHandler>,
+    pub global_components: Vec<String>,
+    pub error_pages: Vec<String>,
+    pub verbatim: Vec<String>,
+    pub static_dir: Option<String>,
+    pub ignore_files: Vec<String>,
+    pub ignore_extensions: Vec<String>,
+    pub allowed_output_extensions: Vec<String>,
+    pub post_build: Option<String>,
+    pub critical_css: Option<String>,
+    pub missing_content: MissingContent,
+    pub missing_component: Option<String>,
+    pub content_tag: Option<String>,
+    pub version_meta: bool,
+    pub fsync: bool,
+    pub build_time_format: Option<String>,
+    pub index_file: Option<String>,
+    pub robots: Option<Robots>,
+    pub redirects: Vec<Redirect>,
+    pub auto_index: Option<AutoIndex>,
+    pub pass_order: PassOrder,
+    pub locales: Vec<String>,
+    pub default_locale: Option<String>,
+}
+
+impl Config {
+    pub fn load(base_dir: &Path) -> Result<Config, BuildError> {
+        Config::load_env(base_dir, None)
+    }
+
+    // With an environment, elss.<env>.toml is deep-merged over elss.toml. Unlike the base
+    // file, a requested overlay has to exist.
+    pub fn load_env(base_dir: &Path, env: Option<&str>) -> Result<Config, BuildError> {
+        let path = base_dir.join(CONFIG_FILE);
+        let mut table = if path.is_file() { read_table(&path)? } else { BTreeMap::new() };
+        if let Some(env) = env {
+            let overlay_path = base_dir.join(format!("elss.{}.toml", env));
+            if !overlay_path.is_file() {
+                return Err(BuildError::ConfigError(format!("{}: overlay for environment [{}] not found", overlay_path.display(), env)));
+            }
+            merge_tables(&mut table, read_table(&overlay_path)?);
+        }
+        Config::from_table(&table).map_err(|e| BuildError::ConfigError(format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let table = parse_table(text)?;
+        Config::from_table(&table)
+    }
+
+    fn from_table(table: &BTreeMap<String, Value>) -> Result<Config, String> {
+        let mut config = Config::default();
+        if let Some(aliases) = table.get("aliases") {
+            let aliases = aliases.as_table().ok_or("[aliases] must be a table")?;
+            for (alias, target) in aliases {
+                let target = target.as_str().ok_or(format!("alias [{}] must be a string", alias))?;
+                config.aliases.insert(alias.clone(), target.to_string());
+            }
+        }
+        if let Some(roots) = table.get("component_roots") {
+            let roots = roots.as_table().ok_or("[component_roots] must be a table")?;
+            for (root, dir) in roots {
+                let dir = dir.as_str().ok_or(format!("component root [{}] must be a string", root))?;
+                config.component_roots.insert(root.trim_start_matches('@').to_string(), dir.trim_end_matches('/').to_string());
+            }
+        }
+        if let Some(handlers) = table.get("handlers") {
+            let handlers = handlers.as_table().ok_or("[handlers] must be a table")?;
+            for (extension, handler) in handlers {
+                let handler = match handler.as_str() {
+                    Some("flatten") => FileHandler::Flatten,
+                    Some("copy") => FileHandler::Copy,
+                    _ => return Err(format!("handler for [{}] must be flatten or copy", extension)),
+                };
+                config.handlers.insert(extension.trim_start_matches('.').to_string(), handler);
+            }
+        }
+        config.dest_dir = string(table, "dest_dir")?;
+        config.global_components = string_list(table, "global_components")?;
+        config.error_pages = string_list(table, "error_pages")?;
+        config.verbatim = string_list(table, "verbatim")?.iter().map(|dir| dir.trim_matches('/').to_string()).collect();
+        config.static_dir = string(table, "static_dir")?.map(|dir| dir.trim_matches('/').to_string());
+        config.ignore_files = string_list(table, "ignore_files")?;
+        config.ignore_extensions = string_list(table, "ignore_extensions")?;
+        config.allowed_output_extensions = string_list(table, "allowed_output_extensions")?;
+        config.post_build = string(table, "post_build")?;
+        config.critical_css = string(table, "critical_css")?;
+        config.build_time_format = string(table, "build_time_format")?;
+        config.index_file = string(table, "index_file")?;
+        if let Some(robots) = table.get("robots") {
+            let robots = robots.as_table().ok_or("[robots] must be a table")?;
+            config.robots = Some(Robots { rules: string_list(robots, "rules")?, sitemap: string(robots, "sitemap")? });
+        }
+        config.redirects = string_list(table, "redirects")?.iter().map(|rule| Redirect::parse(rule)).collect::<Result<_, _>>()?;
+        if let Some(auto_index) = table.get("auto_index") {
+            let auto_index = auto_index.as_table().ok_or("[auto_index] must be a table")?;
+            let template = string(auto_index, "template")?.ok_or("[auto_index] needs a template")?;
+            let dirs = string_list(auto_index, "dirs")?.iter().map(|dir| dir.trim_matches('/').to_string()).collect();
+            config.auto_index = Some(AutoIndex { dirs, template });
+        }
+        config.locales = string_list(table, "locales")?;
+        config.default_locale = string(table, "default_locale")?;
+        if let Some(default_locale) = config.default_locale.as_ref().filter(|locale| !config.locales.contains(locale)) {
+            return Err(format!("[default_locale] [{}] is not one of [locales]", default_locale));
+        }
+        config.pass_order = match string(table, "pass_order")?.as_deref() {
+            None | Some("components-first") => PassOrder::ComponentsFirst,
+            Some("layout-first") => PassOrder::LayoutFirst,
+            Some(other) => return Err(format!("[pass_order] must be components-first or layout-first, not [{}]", other)),
+        };
+        config.missing_component = string(table, "missing_component")?;
+        config.content_tag = string(table, "content_tag")?;
+        if let Some(tag) = config.content_tag.as_ref().filter(|tag| tag.is_empty() || !tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')) {
+            return Err(format!("[content_tag] must be a tag name, not [{}]", tag));
+        }
+        config.version_meta = boolean(table, "version_meta")?.unwrap_or(false);
+        config.fsync = boolean(table, "fsync")?.unwrap_or(false);
+        config.missing_content = match string(table, "missing_content")?.as_deref() {
+            None | Some("error") => MissingContent::Error,
+            Some("drop") => MissingContent::Drop,
+            Some("append") => MissingContent::Append,
+            Some(other) => return Err(format!("[missing_content] must be drop, append or error, not [{}]", other)),
+        };
+        Ok(config)
+    }
+}
+
+fn read_table(path: &Path) -> Result<BTreeMap<String, Value>, BuildError> {
+    let text = fs::read_to_string(path)?;
+    parse_table(&text).map_err(|e| BuildError::ConfigError(format!("{}: {}", path.display(), e)))
+}
+
+// Tables merge key by key; any other overlay value, arrays included, replaces the base value.
+pub fn merge_tables(base: &mut BTreeMap<String, Value>, overlay: BTreeMap<String, Value>) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base_table)), Value::Table(overlay_table)) => merge_tables(base_table, overlay_table),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn boolean(table: &BTreeMap<String, Value>, key: &str) -> Result<Option<bool>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(Value::Boolean(value)) => Ok(Some(*value)),
+        Some(_) => Err(format!("[{}] must be true or false", key)),
+    }
+}
+
+fn string(table: &BTreeMap<String, Value>, key: &str) -> Result<Option<String>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value.clone())),
+        Some(_) => Err(format!("[{}] must be a string", key)),
+    }
+}
+
+fn string_list(table: &BTreeMap<String, Value>, key: &str) -> Result<Vec<String>, String> {
+    match table.get(key) {
+        None => Ok(Vec::new()),
+        Some(Value::Array(items)) => items.iter()
+            .map(|item| item.as_str().map(str::to_string).ok_or(format!("[{}] must only contain strings", key)))
+            .collect(),
+        Some(_) => Err(format!("[{}] must be an array of strings", key)),
+    }
+}
+
+pub fn parse_table(text: &str) -> Result<BTreeMap<String, Value>, String> {
+    let mut root = BTreeMap::new();
+    let mut current: Vec<String> = Vec::new();
+    let mut lines = text.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let mut line = strip_comment(line).trim().to_string();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            let header = line.strip_prefix('[').and_then(|l| l.strip_suffix(']'))
+                .ok_or(format!("line {}: malformed table header", index + 1))?;
+            current = header.split('.').map(|key| unquote_key(key.trim())).collect();
+            table_at(&mut root, &current).map_err(|e| format!("line {}: {}", index + 1, e))?;
+            continue;
+        }
+        // Arrays may span several lines; keep reading until the brackets balance.
+        while bracket_depth(&line) > 0 {
+            match lines.next() {
+                Some((_, next)) => {
+                    line.push(' ');
+                    line.push_str(strip_comment(next).trim());
+                }
+                None => return Err(format!("line {}: unterminated array", index + 1)),
+            }
+        }
+        let (key, value) = line.split_once('=').ok_or(format!("line {}: expected key = value", index + 1))?;
+        let key = unquote_key(key.trim());
+        let mut chars = value.trim().chars().peekable();
+        let value = parse_value(&mut chars).map_err(|e| format!("line {}: {}", index + 1, e))?;
+        if chars.any(|c| !c.is_whitespace()) {
+            return Err(format!("line {}: unexpected trailing characters", index + 1));
+        }
+        let table = table_at(&mut root, &current).map_err(|e| format!("line {}: {}", index + 1, e))?;
+        table.insert(key, value);
+    }
+    Ok(root)
+}
+
+fn table_at<'a>(root: &'a mut BTreeMap<String, Value>, path: &[String]) -> Result<&'a mut BTreeMap<String, Value>, String> {
+    let mut table = root;
+    for key in path {
+        let entry = table.entry(key.clone()).or_insert_with(|| Value::Table(BTreeMap::new()));
+        table = match entry {
+            Value::Table(inner) => inner,
+            _ => return Err(format!("[{}] is not a table", key)),
+        };
+    }
+    Ok(table)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match in_string {
+            Some(quote) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' && quote == '"' {
+                    escaped = true;
+                } else if c == quote {
+                    in_string = None;
+                }
+            }
+            None if c == '"' || c == '\'' => in_string = Some(c),
+            None if c == '#' => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+fn bracket_depth(line: &str) -> i32 {
+    let value = match line.split_once('=') {
+        Some((_, value)) => value,
+        None => return 0,
+    };
+    let mut depth = 0;
+    let mut in_string = None;
+    for c in value.chars() {
+        match in_string {
+            Some(quote) if c == quote => in_string = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => in_string = Some(c),
+            None if c == '[' => depth += 1,
+            None if c == ']' => depth -= 1,
+            None => {}
+        }
+    }
+    depth
+}
+
+fn unquote_key(key: &str) -> String {
+    key.trim_matches(|c| c == '"' || c == '\'').to_string()
+}
+
+fn parse_value(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<Value, String> {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+    match chars.peek() {
+        Some('"') => {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => return Ok(Value::String(s)),
+                    Some('\\') => match chars.next() {
+                        Some('n') => s.push('\n'),
+                        Some('t') => s.push('\t'),
+                        Some('"') => s.push('"'),
+                        Some('\\') => s.push('\\'),
+                        Some(c) => return Err(format!("unsupported escape \\{}", c)),
+                        None => return Err("unterminated string".to_string()),
+                    },
+                    Some(c) => s.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+        }
+        Some('\'') => {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some('\'') => return Ok(Value::String(s)),
+                    Some(c) => s.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+        }
+        Some('[') => {
+            chars.next();
+            let mut items = Vec::new();
+            loop {
+                while chars.peek().is_some_and(|c| c.is_whitespace() || *c == ',') {
+                    chars.next();
+                }
+                if chars.peek() == Some(&']') {
+                    chars.next();
+                    return Ok(Value::Array(items));
+                }
+                if chars.peek().is_none() {
+                    return Err("unterminated array".to_string());
+                }
+                items.push(parse_value(chars)?);
+            }
+        }
+        Some(_) => {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == ',' || c == ']' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            match word.as_str() {
+                "true" => Ok(Value::Boolean(true)),
+                "false" => Ok(Value::Boolean(false)),
+                _ => word.replace('_', "").parse::<i64>().map(Value::Integer)
+                    .map_err(|_| format!("unsupported value [{}]", word)),
+            }
+        }
+        None => Err("missing value".to_string()),
+    }
+}
+
+// Writes a table back out in the subset of TOML that parse_table reads.
+pub fn to_toml(table: &BTreeMap<String, Value>) -> String {
+    let mut text = String::new();
+    write_table(&mut text, table, &[]);
+    text
+}
+
+fn write_table(text: &mut String, table: &BTreeMap<String, Value>, path: &[String]) {
+    for (key, value) in table.iter().filter(|(_, value)| !matches!(value, Value::Table(_))) {
+        text.push_str(&format!("{} = {}\n", format_key(key), format_value(value)));
+    }
+    for (key, value) in table {
+        if let Value::Table(inner) = value {
+            let path = [path, &[format_key(key)]].concat();
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(&format!("[{}]\n", path.join(".")));
+            write_table(text, inner, &path);
+        }
+    }
+}
+
+fn format_key(key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        key.to_string()
+    } else {
+        format!("\"{}\"", key)
+    }
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\t', "\\t")),
+        Value::Integer(i) => i.to_string(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Array(items) => format!("[{}]", items.iter().map(format_value).collect::<Vec<_>>().join(", ")),
+        Value::Table(table) => format!("{{ {} }}", table.iter().map(|(key, value)| format!("{} = {}", format_key(key), format_value(value))).collect::<Vec<_>>().join(", ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_content_parses_each_mode_and_defaults_to_error() {
+        assert_eq!(Config::parse("").unwrap().missing_content, MissingContent::Error);
+        assert_eq!(Config::parse("missing_content = \"drop\"").unwrap().missing_content, MissingContent::Drop);
+        assert_eq!(Config::parse("missing_content = \"append\"").unwrap().missing_content, MissingContent::Append);
+        assert!(Config::parse("missing_content = \"ignore\"").is_err());
+    }
+
+    #[test]
+    fn an_overlay_overrides_its_keys_and_inherits_the_rest() {
+        let mut base = parse_table("dest_dir = \"build\"\npost_build = \"true\"\n[aliases]\nbtn = \"ui/button\"\ncard = \"ui/card\"\n").unwrap();
+        merge_tables(&mut base, parse_table("dest_dir = \"dist\"\n[aliases]\nbtn = \"prod/button\"\n").unwrap());
+        assert_eq!(base, parse_table("dest_dir = \"dist\"\npost_build = \"true\"\n[aliases]\nbtn = \"prod/button\"\ncard = \"ui/card\"\n").unwrap());
+    }
+
+    #[test]
+    fn load_env_merges_the_environment_file_and_requires_it() {
+        let base_dir = std::env::temp_dir().join(format!("elss-test-config-env-{}", std::process::id()));
+        fs::create_dir_all(&base_dir).unwrap();
+        fs::write(base_dir.join(CONFIG_FILE), "dest_dir = \"build\"\nstatic_dir = \"public\"\n").unwrap();
+        fs::write(base_dir.join("elss.prod.toml"), "dest_dir = \"dist\"\n").unwrap();
+        let config = Config::load_env(&base_dir, Some("prod")).unwrap();
+        assert_eq!(config.dest_dir.as_deref(), Some("dist"));
+        assert_eq!(config.static_dir.as_deref(), Some("public"));
+        assert_eq!(Config::load(&base_dir).unwrap().dest_dir.as_deref(), Some("build"));
+        assert!(Config::load_env(&base_dir, Some("staging")).is_err());
+        fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn handlers_map_extensions_to_flatten_or_copy() {
+        let config = Config::parse("[handlers]\n\".htm\" = \"flatten\"\ntxt = \"copy\"\n").unwrap();
+        assert_eq!(config.handlers.get("htm"), Some(&FileHandler::Flatten));
+        assert_eq!(config.handlers.get("txt"), Some(&FileHandler::Copy));
+        assert!(Config::parse("[handlers]\nmd = \"markdown\"\n").is_err());
+    }
+}
@@ -0,0 +1,101 @@
+// Vue-style scoping: every selector in a component's <style> is narrowed to elements that
+// carry the component's scope attribute.
+
+// A short stable attribute name derived from the component path (FNV-1a).
+pub fn scope_attribute(component: &str) -> String {
+    let mut hash: u32 = 0x811c_9dc5;
+    for byte in component.bytes() {
+        hash ^= u32::from(byte);
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    format!("data-el-{:08x}", hash)
+}
+
+pub fn scope_css(css: &str, attribute: &str) -> String {
+    let mut result = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(open) = rest.find(['{', ';']) {
+        let prelude = &rest[..open];
+        if rest[open..].starts_with(';') {
+            // Statement at-rules such as @import.
+            result.push_str(&rest[..=open]);
+            rest = &rest[open + 1..];
+            continue;
+        }
+        let close = matching_brace(rest, open);
+        let block = &rest[open + 1..close];
+        let trimmed = prelude.trim_start();
+        if trimmed.starts_with('@') {
+            result.push_str(prelude);
+            result.push('{');
+            if ["@media", "@supports", "@container", "@layer"].iter().any(|rule| trimmed.starts_with(rule)) {
+                result.push_str(&scope_css(block, attribute));
+            } else {
+                // @keyframes, @font-face and friends do not hold selectors.
+                result.push_str(block);
+            }
+        } else {
+            result.push_str(&scope_selectors(prelude, attribute));
+            result.push('{');
+            result.push_str(block);
+        }
+        if close < rest.len() {
+            result.push('}');
+            rest = &rest[close + 1..];
+        } else {
+            rest = "";
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+fn matching_brace(text: &str, open: usize) -> usize {
+    let mut depth = 0;
+    for (i, c) in text[open..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return open + i;
+                }
+            }
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+// Adds the attribute to the last compound selector, ahead of any pseudo-classes:
+// "ul > li a:hover" becomes "ul > li a[attr]:hover".
+fn scope_selectors(prelude: &str, attribute: &str) -> String {
+    let leading = &prelude[..prelude.len() - prelude.trim_start().len()];
+    let trailing = &prelude[prelude.trim_end().len()..];
+    let selectors: Vec<String> = prelude.trim().split(',').map(|selector| {
+        let selector = selector.trim();
+        let last_start = selector.rfind([' ', '>', '+', '~']).map_or(0, |i| i + 1);
+        let (head, last) = selector.split_at(last_start);
+        let pseudo = last.find(':').unwrap_or(last.len());
+        format!("{}{}[{}]{}", head, &last[..pseudo], attribute, &last[pseudo..])
+    }).collect();
+    format!("{}{}{}", leading, selectors.join(", "), trailing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selectors_are_scoped_on_their_last_compound_and_inside_media_rules() {
+        let css = "ul > li a:hover, .card { color: red } @media (min-width: 1px) { p { margin: 0 } } @keyframes spin { from { opacity: 0 } }";
+        assert_eq!(scope_css(css, "data-el-1"), "ul > li a[data-el-1]:hover, .card[data-el-1] { color: red } @media (min-width: 1px) { p[data-el-1] { margin: 0 } } @keyframes spin { from { opacity: 0 } }");
+    }
+
+    #[test]
+    fn scope_attributes_are_stable_per_component() {
+        assert_eq!(scope_attribute("el-components/card.html"), scope_attribute("el-components/card.html"));
+        assert_ne!(scope_attribute("el-components/card.html"), scope_attribute("el-components/list.html"));
+        assert!(scope_attribute("x").starts_with("data-el-"));
+    }
+}
@@ -0,0 +1,422 @@
+use std::ops::Range;
+use regex::{Captures, Regex};
+
+pub const COMPONENT_TAG: &str = "el-component";
+pub const LAYOUT_TAG: &str = "el-layout";
+pub const CONTENT_TAG: &str = "el-content";
+pub const WRAPPER_TAG: &str = "el-wrapper";
+pub const HEAD_TAG: &str = "el-head";
+pub const RAW_TAG: &str = "el-raw";
+pub const SWITCH_TAG: &str = "el-switch";
+pub const CASE_TAG: &str = "el-case";
+pub const DEFAULT_TAG: &str = "el-default";
+pub const ENV_TAG: &str = "el-env";
+pub const EACH_FILE_TAG: &str = "el-each-file";
+pub const LINK_TAG: &str = "el-link";
+pub const SLOT_TAG: &str = "el-slot";
+pub const ASSET_INLINE_TAG: &str = "el-asset-inline";
+pub const DEFINE_TAG: &str = "el-define";
+pub const USE_TAG: &str = "el-use";
+pub const INCLUDE_IF_TAG: &str = "el-include-if";
+
+// Any run of attributes, in any order, quoted or not. Captured so the parser below can pick
+// out the ones a directive cares about.
+const ATTRIBUTES: &str = r#"((?:\s+[^\s"'=/>]+(?:\s*=\s*(?:"[^"]*"|'[^']*'|[^\s"'=<>`]+))?)*)\s*"#;
+
+pub struct Directives {
+    component: Regex,
+    component_open: Regex,
+    component_token: Regex,
+    layout: Regex,
+    legacy_layout: Regex,
+    layout_content: Regex,
+    wrapper_open: Regex,
+    wrapper_close: String,
+    head: Regex,
+    switch: Regex,
+    case: Regex,
+    default_case: Regex,
+    env: Regex,
+    each_file: Regex,
+    link: Regex,
+    slot: Regex,
+    asset_inline: Regex,
+    define: Regex,
+    snippet_use: Regex,
+    include_if: Regex,
+    include: Regex,
+    any_directive: Regex,
+    tags: Vec<String>,
+}
+
+impl Directives {
+    pub fn new(component_tag: &str, layout_tag: &str, content_tag: &str) -> Self {
+        let tags = [component_tag, layout_tag, content_tag, WRAPPER_TAG, HEAD_TAG, RAW_TAG, SWITCH_TAG, CASE_TAG, DEFAULT_TAG, ENV_TAG, EACH_FILE_TAG, LINK_TAG, SLOT_TAG, ASSET_INLINE_TAG, DEFINE_TAG, USE_TAG, INCLUDE_IF_TAG].iter().map(|tag| tag.to_string()).collect();
+        let component_tag = regex::escape(component_tag);
+        let layout_tag = regex::escape(layout_tag);
+        let content_tag = regex::escape(content_tag);
+        Directives {
+            component: Regex::new(&format!(r#"<{}{}/?>"#, component_tag, ATTRIBUTES)).unwrap(),
+            component_open: Regex::new(&format!(r#"<{}\b"#, component_tag)).unwrap(),
+            component_token: Regex::new(&format!(r#"<{0}{1}(/?)>|</{0}\s*>"#, component_tag, ATTRIBUTES)).unwrap(),
+            layout: Regex::new(&format!(r#"(?s)<{0}{1}(?:/>|>(.*?)</{0}>)"#, layout_tag, ATTRIBUTES)).unwrap(),
+            // The first releases' pattern: name as the only attribute, a closing tag on the same
+            // line, and no self-closing form.
+            legacy_layout: Regex::new(&format!(r#"<{0}\s+name="([^"]*)"\s*>(.*?)</{0}>"#, layout_tag)).unwrap(),
+            layout_content: Regex::new(&format!(r#"<{}\s*/>"#, content_tag)).unwrap(),
+            wrapper_open: Regex::new(&format!(r#"<{}{}>"#, WRAPPER_TAG, ATTRIBUTES)).unwrap(),
+            wrapper_close: format!("</{}>", WRAPPER_TAG),
+            head: Regex::new(&format!(r#"(?s)<{0}\s*>(.*?)</{0}>"#, HEAD_TAG)).unwrap(),
+            switch: Regex::new(&format!(r#"(?s)<{0}\s+var="([^"]*)"\s*>(.*?)</{0}>"#, SWITCH_TAG)).unwrap(),
+            case: Regex::new(&format!(r#"(?s)<{0}\s+value="([^"]*)"\s*>(.*?)</{0}>"#, CASE_TAG)).unwrap(),
+            default_case: Regex::new(&format!(r#"(?s)<{0}\s*>(.*?)</{0}>"#, DEFAULT_TAG)).unwrap(),
+            env: Regex::new(&format!(r#"(?s)<{0}\s+name="([^"]*)"\s*>(.*?)</{0}>"#, ENV_TAG)).unwrap(),
+            each_file: Regex::new(&format!(r#"(?s)<{0}{1}>(.*?)</{0}>"#, EACH_FILE_TAG, ATTRIBUTES)).unwrap(),
+            slot: Regex::new(&format!(r#"(?s)<{0}\s*(?:/>|>(.*?)</{0}>)"#, SLOT_TAG)).unwrap(),
+            asset_inline: Regex::new(&format!(r#"<{}{}/?>"#, ASSET_INLINE_TAG, ATTRIBUTES)).unwrap(),
+            link: Regex::new(&format!(r#"(?s)<{0}{1}(?:/>|>(.*?)</{0}>)"#, LINK_TAG, ATTRIBUTES)).unwrap(),
+            define: Regex::new(&format!(r#"(?s)<{0}\s+name="([^"]*)"\s*>(.*?)</{0}>"#, DEFINE_TAG)).unwrap(),
+            snippet_use: Regex::new(&format!(r#"<{0}\s+name="([^"]*)"\s*(?:/>|></{0}>)"#, USE_TAG)).unwrap(),
+            include_if: Regex::new(&format!(r#"<{}{}/?>"#, INCLUDE_IF_TAG, ATTRIBUTES)).unwrap(),
+            any_directive: Regex::new(r#"<el-[A-Za-z0-9-]+"#).unwrap(),
+            include: Regex::new(r#"<!--#include\s+(virtual|file)="([^"]*)"\s*-->"#).unwrap(),
+            tags,
+        }
+    }
+
+    pub fn component(&self) -> &Regex {
+        &self.component
+    }
+
+    pub fn component_open(&self) -> &Regex {
+        &self.component_open
+    }
+
+    // Pairs component open and close tags by nesting depth, so the close tag of a nested
+    // component is never taken for its parent's. Also says whether some tag was never
+    // closed; matching carries on after it as if it were not there.
+    pub fn components<'t>(&self, text: &'t str) -> (Vec<ComponentTag<'t>>, bool) {
+        let tokens: Vec<Captures<'t>> = self.component_token.captures_iter(text).collect();
+        let is_open = |token: &Captures| token.get(2).is_some_and(|slash| slash.as_str().is_empty());
+        let mut tags = Vec::new();
+        let mut unterminated = false;
+        let mut index = 0;
+        while index < tokens.len() {
+            let token = &tokens[index];
+            index += 1;
+            // A close tag with nothing open is left in place.
+            let attributes = match token.get(1) {
+                Some(attributes) => attributes.as_str(),
+                None => continue,
+            };
+            let start = token.get(0).unwrap().start();
+            if !is_open(token) {
+                tags.push(ComponentTag { text: &text[token.get(0).unwrap().range()], start, attributes, content: None });
+                continue;
+            }
+            let mut depth = 1;
+            let close = tokens[index..].iter().position(|inner| {
+                match inner.get(1) {
+                    None => depth -= 1,
+                    Some(_) if is_open(inner) => depth += 1,
+                    Some(_) => {}
+                }
+                depth == 0
+            });
+            match close {
+                Some(close) => {
+                    let close_tag = tokens[index + close].get(0).unwrap();
+                    let content = &text[token.get(0).unwrap().end()..close_tag.start()];
+                    tags.push(ComponentTag { text: &text[start..close_tag.end()], start, attributes, content: Some(content) });
+                    index += close + 1;
+                }
+                None => unterminated = true,
+            }
+        }
+        (tags, unterminated)
+    }
+
+    pub fn layout(&self) -> &Regex {
+        &self.layout
+    }
+
+    pub fn legacy_layout(&self) -> &Regex {
+        &self.legacy_layout
+    }
+
+    pub fn layout_content(&self) -> &Regex {
+        &self.layout_content
+    }
+
+    pub fn wrapper_open(&self) -> &Regex {
+        &self.wrapper_open
+    }
+
+    pub fn wrapper_close(&self) -> &str {
+        &self.wrapper_close
+    }
+
+    pub fn head(&self) -> &Regex {
+        &self.head
+    }
+
+    pub fn switch(&self) -> &Regex {
+        &self.switch
+    }
+
+    pub fn env(&self) -> &Regex {
+        &self.env
+    }
+
+    pub fn case(&self) -> &Regex {
+        &self.case
+    }
+
+    pub fn default_case(&self) -> &Regex {
+        &self.default_case
+    }
+
+    pub fn each_file(&self) -> &Regex {
+        &self.each_file
+    }
+
+    pub fn link(&self) -> &Regex {
+        &self.link
+    }
+
+    pub fn slot(&self) -> &Regex {
+        &self.slot
+    }
+
+    pub fn asset_inline(&self) -> &Regex {
+        &self.asset_inline
+    }
+
+    pub fn define(&self) -> &Regex {
+        &self.define
+    }
+
+    pub fn snippet_use(&self) -> &Regex {
+        &self.snippet_use
+    }
+
+    pub fn include_if(&self) -> &Regex {
+        &self.include_if
+    }
+
+    pub fn include(&self) -> &Regex {
+        &self.include
+    }
+
+    pub fn any_directive(&self) -> &Regex {
+        &self.any_directive
+    }
+
+    // The closest directive tag to a misspelled one, if any is within two edits.
+    pub fn suggest(&self, tag: &str) -> Option<&str> {
+        self.tags.iter()
+            .map(|known| (edit_distance(tag, known), known))
+            .filter(|(distance, _)| *distance <= 2)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, known)| known.as_str())
+    }
+}
+
+impl Default for Directives {
+    fn default() -> Self {
+        Directives::new(COMPONENT_TAG, LAYOUT_TAG, CONTENT_TAG)
+    }
+}
+
+// One component tag, from its start through its matching close tag, or just the tag when
+// it closes itself. Component tags nested inside belong to its content.
+pub struct ComponentTag<'t> {
+    text: &'t str,
+    start: usize,
+    attributes: &'t str,
+    content: Option<&'t str>,
+}
+
+impl<'t> ComponentTag<'t> {
+    pub fn as_str(&self) -> &'t str {
+        self.text
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.start + self.text.len()
+    }
+
+    pub fn attributes(&self) -> &'t str {
+        self.attributes
+    }
+
+    // What the tag encloses; None when it closes itself.
+    pub fn content(&self) -> Option<&'t str> {
+        self.content
+    }
+
+    pub fn name(&self) -> &'t str {
+        attribute(self.attributes, "name").unwrap_or("")
+    }
+}
+
+// The `name` attribute of a component, layout or wrapper match, wherever it appears among
+// the tag's attributes. A tag without one yields an empty name.
+pub fn name<'t>(captures: &Captures<'t>) -> &'t str {
+    captures.get(1).and_then(|attributes| attribute(attributes.as_str(), "name")).unwrap_or("")
+}
+
+// Looks up one attribute in the text between a tag name and its closing `>`.
+pub fn attribute<'t>(attributes: &'t str, wanted: &str) -> Option<&'t str> {
+    parse_attributes(attributes).into_iter().find(|(key, _)| key.eq_ignore_ascii_case(wanted)).map(|(_, value)| value)
+}
+
+// Matches a tag registered by a library user, self-closing or with content: group 1 holds
+// the attributes and group 2 the content.
+pub fn custom_directive(tag: &str) -> Regex {
+    Regex::new(&format!(r#"(?s)<{0}{1}(?:/>|>(.*?)</{0}\s*>)"#, regex::escape(tag), ATTRIBUTES)).unwrap()
+}
+
+// Every attribute in the text between a tag name and its closing `>`, in order. Quoted
+// values may contain anything but their own quote, so `label="name=x"` is not mistaken for
+// a name. Attributes without a value are given an empty one.
+pub fn parse_attributes(attributes: &str) -> Vec<(&str, &str)> {
+    let mut parsed = Vec::new();
+    let mut rest = attributes.trim_start();
+    while !rest.is_empty() {
+        let key_end = rest.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        rest = rest[key_end..].trim_start();
+        let mut value = "";
+        if let Some(after_equals) = rest.strip_prefix('=') {
+            let after_equals = after_equals.trim_start();
+            let (found, remaining) = match after_equals.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let end = after_equals[1..].find(quote).map_or(after_equals.len(), |end| end + 1);
+                    (&after_equals[1..end], after_equals.get(end + 1..).unwrap_or(""))
+                }
+                _ => {
+                    let end = after_equals.find(char::is_whitespace).unwrap_or(after_equals.len());
+                    (&after_equals[..end], &after_equals[end..])
+                }
+            };
+            value = found;
+            rest = remaining.trim_start();
+        }
+        if key.is_empty() {
+            break;
+        }
+        parsed.push((key, value));
+    }
+    parsed
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn component_matches_self_closing_and_open_tags_with_any_attributes() {
+        let directives = Directives::default();
+        let captures = directives.component().captures(r#"<p><el-component class="x" name="card" /></p>"#).unwrap();
+        assert_eq!(name(&captures), "card");
+        assert_eq!(captures.get(0).unwrap().as_str(), r#"<el-component class="x" name="card" />"#);
+        assert!(directives.component().is_match(r#"<el-component name="card">"#));
+        assert!(!directives.component().is_match(r#"<el-components name="card" />"#));
+    }
+
+    #[test]
+    fn layout_matches_self_closing_and_paired_tags() {
+        let directives = Directives::default();
+        let captures = directives.layout().captures(r#"<el-layout name="base" />"#).unwrap();
+        assert_eq!(name(&captures), "base");
+        assert!(captures.get(2).is_none());
+        let captures = directives.layout().captures("<el-layout name=\"base\">\n<p>x</p>\n</el-layout>").unwrap();
+        assert_eq!(name(&captures), "base");
+        assert_eq!(&captures[2], "\n<p>x</p>\n");
+    }
+
+    #[test]
+    fn layout_content_matches_only_the_self_closing_tag() {
+        let directives = Directives::default();
+        assert!(directives.layout_content().is_match("<main><el-content /></main>"));
+        assert!(directives.layout_content().is_match("<el-content/>"));
+        assert!(!directives.layout_content().is_match("<el-content></el-content>"));
+    }
+
+    #[test]
+    fn patterns_use_the_given_tag_names() {
+        let directives = Directives::new("x-include", "x-layout", "x-body");
+        assert!(directives.component().is_match(r#"<x-include name="card" />"#));
+        assert!(!directives.component().is_match(r#"<el-component name="card" />"#));
+        assert!(directives.layout().is_match(r#"<x-layout name="base" />"#));
+        assert!(directives.layout_content().is_match("<x-body />"));
+        assert!(!directives.layout_content().is_match("<el-content />"));
+    }
+
+    #[test]
+    fn suggest_names_the_closest_known_tag() {
+        let directives = Directives::default();
+        assert_eq!(directives.suggest("el-compoent"), Some(COMPONENT_TAG));
+        assert_eq!(directives.suggest("el-layuot"), Some(LAYOUT_TAG));
+        assert_eq!(directives.suggest("el-something-else"), None);
+    }
+
+    #[test]
+    fn name_is_found_first_middle_or_last_among_attributes() {
+        let directives = Directives::default();
+        for tag in [
+            r#"<el-component name="card" label="x" id='y' />"#,
+            "<el-component\n    label=\"x\"\n    name=\"card\"\n    id='y'\n/>",
+            r#"<el-component label="name=wrong" id=y name='card'>"#,
+        ] {
+            let captures = directives.component().captures(tag).unwrap();
+            assert_eq!(name(&captures), "card", "{}", tag);
+        }
+    }
+
+    #[test]
+    fn attributes_parse_quoted_unquoted_and_bare_values() {
+        assert_eq!(parse_attributes(r#" name="a b" id=x hidden data-v='1' "#), [("name", "a b"), ("id", "x"), ("hidden", ""), ("data-v", "1")]);
+        assert_eq!(attribute(r#"NAME="card""#, "name"), Some("card"));
+        assert_eq!(attribute(r#"label="x""#, "name"), None);
+    }
+
+    #[test]
+    fn components_pair_nested_tags_by_depth() {
+        let directives = Directives::default();
+        let text = r#"<el-component name="a"><el-component name="a">x</el-component><el-component name="b" /></el-component><el-component name="c">"#;
+        let (tags, unterminated) = directives.components(text);
+        assert!(unterminated);
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].text, &text[..text.len() - r#"<el-component name="c">"#.len()]);
+        assert_eq!(tags[0].content, Some(r#"<el-component name="a">x</el-component><el-component name="b" />"#));
+        let (inner, unterminated) = directives.components(tags[0].content.unwrap());
+        assert!(!unterminated);
+        assert_eq!(inner.iter().map(|tag| tag.content).collect::<Vec<_>>(), [Some("x"), None]);
+    }
+
+    #[test]
+    fn legacy_layout_matches_only_the_original_tag_form() {
+        let directives = Directives::default();
+        let captures = directives.legacy_layout().captures(r#"<p>x</p><el-layout name="base"></el-layout>"#).unwrap();
+        assert_eq!(&captures[1], "base");
+        assert!(!directives.legacy_layout().is_match(r#"<el-layout name="base" />"#));
+        assert!(!directives.legacy_layout().is_match(r#"<el-layout name="base" title="Home"></el-layout>"#));
+        assert!(!directives.legacy_layout().is_match("<el-layout name=\"base\">\n</el-layout>"));
+    }
+}
@@ -0,0 +1,112 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum BuildError {
+    IoError(io::Error),
+    ConfigError(String),
+    NotFound(PathBuf),
+    Circular(PathBuf),
+    Malformed { file: PathBuf, message: String },
+    EmptyDirectiveName(PathBuf),
+    InvalidName { file: PathBuf, name: String },
+    AmbiguousComponent { file: PathBuf, name: String, candidates: Vec<PathBuf> },
+    UnknownComponentRoot { file: PathBuf, name: String },
+    UnknownSnippet { file: PathBuf, name: String },
+    MissingContent(PathBuf),
+    InvalidHtml { file: PathBuf, message: String },
+    FileTooLarge { file: PathBuf, size: u64, limit: u64 },
+    DuplicateOutput { output: PathBuf, first: PathBuf, second: PathBuf },
+    PathTraversal(String),
+    DisallowedExtension(PathBuf),
+    PostBuildFailed(String, Option<i32>),
+    MissingReferences(usize),
+    RemoteFetch { url: String, message: String },
+    ErrorsReported(usize),
+    BuildLocked(PathBuf),
+    DeadLink { file: PathBuf, link: String },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::IoError(e) => write!(f, "{}", e),
+            BuildError::ConfigError(message) => write!(f, "Invalid configuration: {}", message),
+            BuildError::NotFound(path) => write!(f, "File not found: [{}]", path.display()),
+            BuildError::Circular(path) => write!(f, "Circular dependency detected for [{}]", path.display()),
+            BuildError::Malformed { file, message } => write!(f, "Malformed directive in [{}]: {}", file.display(), message),
+            BuildError::EmptyDirectiveName(file) => write!(f, "Empty directive name in [{}]", file.display()),
+            BuildError::InvalidName { file, name } => write!(f, "Invalid directive name [{}] in [{}]: only letters, digits, '-', '_', '.' and '/' are allowed, in a relative path without '..'", name, file.display()),
+            BuildError::AmbiguousComponent { file, name, candidates } => {
+                let candidates: Vec<String> = candidates.iter().map(|candidate| format!("[{}]", candidate.display())).collect();
+                write!(f, "Component [{}] in [{}] is ambiguous, it could be any of {}; use its full path", name, file.display(), candidates.join(", "))
+            }
+            BuildError::UnknownComponentRoot { file, name } => write!(f, "Component [{}] in [{}] names a root that is not in [component_roots]", name, file.display()),
+            BuildError::UnknownSnippet { file, name } => write!(f, "Snippet [{}] is used in [{}] but not defined there with <el-define>", name, file.display()),
+            BuildError::MissingContent(layout) => write!(f, "Layout [{}] has no <el-content /> placeholder", layout.display()),
+            BuildError::InvalidHtml { file, message } => write!(f, "Invalid HTML in [{}]: {}", file.display(), message),
+            BuildError::FileTooLarge { file, size, limit } => write!(f, "[{}] is {} bytes, over the {} byte limit", file.display(), size, limit),
+            BuildError::DuplicateOutput { output, first, second } => write!(f, "Both [{}] and [{}] write to [{}]", first.display(), second.display(), output.display()),
+            BuildError::PathTraversal(path) => write!(f, "Path escapes the source directory: [{}]", path),
+            BuildError::DisallowedExtension(output) => write!(f, "Output [{}] has an extension that is not in [allowed_output_extensions]", output.display()),
+            BuildError::PostBuildFailed(command, Some(code)) => write!(f, "Post-build command [{}] exited with status {}", command, code),
+            BuildError::PostBuildFailed(command, None) => write!(f, "Post-build command [{}] was terminated", command),
+            BuildError::MissingReferences(count) => write!(f, "{} missing component or layout references", count),
+            BuildError::RemoteFetch { url, message } => write!(f, "Failed to fetch [{}]: {}", url, message),
+            BuildError::ErrorsReported(count) => write!(f, "{} problems were reported", count),
+            BuildError::BuildLocked(lock) => write!(f, "Another build is writing this output, it holds [{}]", lock.display()),
+            BuildError::DeadLink { file, link } => write!(f, "[{}] links to [{}], which the build did not produce", file.display(), link),
+        }
+    }
+}
+
+impl BuildError {
+    // The source file the error is about, for tools that attach errors to files. Paths are
+    // as the builder had them, so either relative to src_dir or already under it.
+    pub fn file(&self) -> Option<&Path> {
+        match self {
+            BuildError::NotFound(file) | BuildError::Circular(file) | BuildError::EmptyDirectiveName(file) | BuildError::MissingContent(file) => Some(file),
+            BuildError::Malformed { file, .. }
+            | BuildError::InvalidName { file, .. }
+            | BuildError::AmbiguousComponent { file, .. }
+            | BuildError::UnknownComponentRoot { file, .. }
+            | BuildError::UnknownSnippet { file, .. }
+            | BuildError::InvalidHtml { file, .. }
+            | BuildError::FileTooLarge { file, .. }
+            | BuildError::DeadLink { file, .. } => Some(file),
+            BuildError::DuplicateOutput { second, .. } => Some(second),
+            _ => None,
+        }
+    }
+}
+
+impl Error for BuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BuildError::IoError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BuildError {
+    fn from(e: io::Error) -> Self {
+        BuildError::IoError(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_errors_convert_and_stay_the_source() {
+        let error: BuildError = io::Error::new(io::ErrorKind::PermissionDenied, "denied").into();
+        assert!(matches!(error, BuildError::IoError(_)));
+        assert_eq!(error.to_string(), "denied");
+        assert_eq!(error.source().unwrap().to_string(), "denied");
+        assert!(BuildError::NotFound(PathBuf::from("src/a.html")).source().is_none());
+    }
+}
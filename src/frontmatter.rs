@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+
+// A leading block of `key: value` lines fenced by `---`, as used by most static site generators.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FrontMatter {
+    values: BTreeMap<String, String>,
+}
+
+impl FrontMatter {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+// Splits a document into its front matter and body. Documents without a front matter block
+// are returned unchanged.
+pub fn split(text: &str) -> (FrontMatter, &str) {
+    let rest = match text.strip_prefix("---\n").or_else(|| text.strip_prefix("---\r\n")) {
+        Some(rest) => rest,
+        None => return (FrontMatter::default(), text),
+    };
+    let mut values = BTreeMap::new();
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        offset += line.len();
+        let line = line.trim_end();
+        if line == "---" {
+            return (FrontMatter { values }, &rest[offset..]);
+        }
+        parse_line(line, &mut values);
+    }
+    (FrontMatter::default(), text)
+}
+
+// Reads a whole document of `key: value` lines, e.g. a translation file, without fences.
+pub fn parse(text: &str) -> FrontMatter {
+    let mut values = BTreeMap::new();
+    for line in text.lines() {
+        parse_line(line.trim_end(), &mut values);
+    }
+    FrontMatter { values }
+}
+
+// A value written as a list, e.g. `[/old, "/older"]`. A bare value is a list of one.
+pub fn list(value: &str) -> Vec<String> {
+    let value = value.trim();
+    let items = value.strip_prefix('[').and_then(|value| value.strip_suffix(']')).unwrap_or(value);
+    items.split(',').map(unquote).filter(|item| !item.is_empty()).collect()
+}
+
+fn parse_line(line: &str, values: &mut BTreeMap<String, String>) {
+    if line.trim_start().starts_with('#') {
+        return;
+    }
+    if let Some((key, value)) = line.split_once(':') {
+        values.insert(key.trim().to_string(), unquote(value));
+    }
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    let quoted = value.len() >= 2
+        && ((value.starts_with('"') && value.ends_with('"')) || (value.starts_with('\'') && value.ends_with('\'')));
+    if quoted { value[1..value.len() - 1].to_string() } else { value.to_string() }
+}
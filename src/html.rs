@@ -0,0 +1,580 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+use regex::Regex;
+use crate::css;
+use crate::encoding;
+
+fn link_regex() -> &'static Regex {
+    static LINK: OnceLock<Regex> = OnceLock::new();
+    LINK.get_or_init(|| Regex::new(r#"(?is)<link\b[^>]*>"#).unwrap())
+}
+
+fn root_link_regex() -> &'static Regex {
+    static ROOT_LINK: OnceLock<Regex> = OnceLock::new();
+    ROOT_LINK.get_or_init(|| Regex::new(r#"(?i)(\s(?:href|src|action)\s*=\s*["'])/([^/])"#).unwrap())
+}
+
+fn link_attribute_regex() -> &'static Regex {
+    static LINK_ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+    LINK_ATTRIBUTE.get_or_init(|| Regex::new(r#"(?i)(\s(?:href|src|action)\s*=\s*)(?:"([^"]*)"|'([^']*)')"#).unwrap())
+}
+
+fn script_or_style_open_regex() -> &'static Regex {
+    static SCRIPT_OR_STYLE_OPEN: OnceLock<Regex> = OnceLock::new();
+    SCRIPT_OR_STYLE_OPEN.get_or_init(|| Regex::new(r#"(?is)<(?:script|style)\b[^>]*>"#).unwrap())
+}
+
+fn title_regex() -> &'static Regex {
+    static TITLE: OnceLock<Regex> = OnceLock::new();
+    TITLE.get_or_init(|| Regex::new(r#"(?is)<title\b[^>]*>(.*?)</title\s*>"#).unwrap())
+}
+
+fn style_regex() -> &'static Regex {
+    static STYLE: OnceLock<Regex> = OnceLock::new();
+    STYLE.get_or_init(|| Regex::new(r#"(?is)(<style\b[^>]*>)(.*?)(</style\s*>)"#).unwrap())
+}
+
+fn script_src_regex() -> &'static Regex {
+    static SCRIPT_SRC: OnceLock<Regex> = OnceLock::new();
+    SCRIPT_SRC.get_or_init(|| Regex::new(r#"(?is)<script\b([^>]*)>\s*</script\s*>"#).unwrap())
+}
+
+fn script_open_regex() -> &'static Regex {
+    static SCRIPT_OPEN: OnceLock<Regex> = OnceLock::new();
+    SCRIPT_OPEN.get_or_init(|| Regex::new(r#"(?is)<script\b[^>]*>"#).unwrap())
+}
+
+fn img_regex() -> &'static Regex {
+    static IMG: OnceLock<Regex> = OnceLock::new();
+    IMG.get_or_init(|| Regex::new(r#"(?is)<img\b[^>]*>"#).unwrap())
+}
+
+fn src_attribute_regex() -> &'static Regex {
+    static SRC_ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+    SRC_ATTRIBUTE.get_or_init(|| Regex::new(r#"(?i)(\ssrc\s*=\s*)(?:"[^"]*"|'[^']*')"#).unwrap())
+}
+
+fn head_open_regex() -> &'static Regex {
+    static HEAD_OPEN: OnceLock<Regex> = OnceLock::new();
+    HEAD_OPEN.get_or_init(|| Regex::new(r#"(?i)<head\b[^>]*>"#).unwrap())
+}
+
+fn head_close_regex() -> &'static Regex {
+    static HEAD_CLOSE: OnceLock<Regex> = OnceLock::new();
+    HEAD_CLOSE.get_or_init(|| Regex::new(r#"(?i)</head\s*>"#).unwrap())
+}
+
+fn body_close_regex() -> &'static Regex {
+    static BODY_CLOSE: OnceLock<Regex> = OnceLock::new();
+    BODY_CLOSE.get_or_init(|| Regex::new(r#"(?i)</body\s*>"#).unwrap())
+}
+
+fn head_regex() -> &'static Regex {
+    static HEAD: OnceLock<Regex> = OnceLock::new();
+    HEAD.get_or_init(|| Regex::new(r#"(?is)<head\b[^>]*>.*?</head\s*>"#).unwrap())
+}
+
+fn body_regex() -> &'static Regex {
+    static BODY: OnceLock<Regex> = OnceLock::new();
+    BODY.get_or_init(|| Regex::new(r#"(?is)<body\b[^>]*>(.*)</body\s*>"#).unwrap())
+}
+
+fn heading_regex() -> &'static Regex {
+    static HEADING: OnceLock<Regex> = OnceLock::new();
+    HEADING.get_or_init(|| Regex::new(r#"(?i)<(/?)h([1-6])\b"#).unwrap())
+}
+
+fn id_attribute_regex() -> &'static Regex {
+    static ID_ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+    ID_ATTRIBUTE.get_or_init(|| Regex::new(r#"(?i)(\sid\s*=\s*)(?:"([^"]*)"|'([^']*)')"#).unwrap())
+}
+
+fn tag_regex() -> &'static Regex {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    TAG.get_or_init(|| Regex::new(r#"<(/?)([A-Za-z][A-Za-z0-9-]*)(?:\s[^>]*?)?(/?)>"#).unwrap())
+}
+
+fn html_open_regex() -> &'static Regex {
+    static HTML_OPEN: OnceLock<Regex> = OnceLock::new();
+    HTML_OPEN.get_or_init(|| Regex::new(r#"(?is)<html\b[^>]*>"#).unwrap())
+}
+
+fn button_regex() -> &'static Regex {
+    static BUTTON: OnceLock<Regex> = OnceLock::new();
+    BUTTON.get_or_init(|| Regex::new(r#"(?is)<button\b([^>]*)>(.*?)</button\s*>"#).unwrap())
+}
+
+fn opaque_regex() -> &'static Regex {
+    static OPAQUE: OnceLock<Regex> = OnceLock::new();
+    OPAQUE.get_or_init(|| Regex::new(r#"(?is)<!--.*?-->|<script\b.*?</script\s*>|<style\b.*?</style\s*>|<!\[CDATA\[.*?\]\]>"#).unwrap())
+}
+
+fn markup_regex() -> &'static Regex {
+    static MARKUP: OnceLock<Regex> = OnceLock::new();
+    MARKUP.get_or_init(|| Regex::new(r#"(?s)<[^>]*>"#).unwrap())
+}
+
+fn todo_regex() -> &'static Regex {
+    static TODO: OnceLock<Regex> = OnceLock::new();
+    TODO.get_or_init(|| Regex::new(r#"(?s)<!--\s*(TODO|FIXME)\b:?(.*?)-->"#).unwrap())
+}
+
+const VOID_ELEMENTS: &[&str] = &["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr"];
+const OPTIONAL_END_TAGS: &[&str] = &["body", "caption", "colgroup", "dd", "dt", "head", "html", "li", "optgroup", "option", "p", "rp", "rt", "tbody", "td", "tfoot", "th", "thead", "tr"];
+
+pub fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!(r#"(?i)\s{}\s*=\s*(?:"([^"]*)"|'([^']*)')"#, regex::escape(name));
+    let captures = Regex::new(&pattern).ok()?.captures(tag)?;
+    captures.get(1).or_else(|| captures.get(2)).map(|value| value.as_str())
+}
+
+// Inserts markup right before </body>, or appends it to pages without one.
+pub fn append_to_body(page: &str, markup: &str) -> String {
+    let position = body_close_regex().find(page).map_or(page.len(), |body_close| body_close.start());
+    let mut result = String::with_capacity(page.len() + markup.len());
+    result.push_str(&page[..position]);
+    result.push_str(markup);
+    result.push_str(&page[position..]);
+    result
+}
+
+// Inserts markup right before </head>, returning None when the page has no head.
+pub fn insert_into_head(page: &str, markup: &str) -> Option<String> {
+    let head_close = head_close_regex().find(page)?;
+    let mut result = String::with_capacity(page.len() + markup.len());
+    result.push_str(&page[..head_close.start()]);
+    result.push_str(markup);
+    result.push_str(&page[head_close.start()..]);
+    Some(result)
+}
+
+// Inserts markup right after <head>, ahead of anything it should apply to, e.g. a <base>.
+pub fn prepend_to_head(page: &str, markup: &str) -> Option<String> {
+    let head_open = head_open_regex().find(page)?;
+    Some(format!("{}{}{}", &page[..head_open.end()], markup, &page[head_open.end()..]))
+}
+
+// Inlines the critical stylesheet into the head and turns links to it into preloads.
+// css_path is the stylesheet's path relative to the site root.
+pub fn inline_critical_css(page: &str, css: &str, css_path: &str) -> Option<String> {
+    let css_path = css_path.trim_start_matches('/');
+    let rewritten = link_regex().replace_all(page, |captures: &regex::Captures| {
+        let tag = &captures[0];
+        let is_stylesheet = attribute(tag, "rel").is_some_and(|rel| rel.eq_ignore_ascii_case("stylesheet"));
+        match attribute(tag, "href") {
+            Some(href) if is_stylesheet && href.trim_start_matches("./").trim_start_matches('/') == css_path => {
+                format!(r#"<link rel="preload" href="{}" as="style">"#, href)
+            }
+            _ => tag.to_string(),
+        }
+    });
+    insert_into_head(&rewritten, &format!("<style>{}</style>", css.trim()))
+}
+
+// Moves root-relative href, src and action URLs under prefix, leaving protocol-relative
+// "//host" URLs alone.
+pub fn prefix_root_links(page: &str, prefix: &str) -> String {
+    let prefix = prefix.trim_matches('/');
+    root_link_regex().replace_all(page, |captures: &regex::Captures| {
+        format!("{}/{}/{}", &captures[1], prefix, &captures[2])
+    }).to_string()
+}
+
+// Makes root-relative href, src and action URLs relative to a page depth directories below
+// the site root, so the output can be browsed from file://. "//host" URLs are left alone.
+pub fn relativize_root_links(page: &str, depth: usize) -> String {
+    let root = if depth == 0 { "./".to_string() } else { "../".repeat(depth) };
+    root_link_regex().replace_all(page, |captures: &regex::Captures| {
+        format!("{}{}{}", &captures[1], root, &captures[2])
+    }).to_string()
+}
+
+// A lint for mismatched and unclosed tags, not a full HTML parser. Elements whose end tag
+// HTML lets authors omit are closed implicitly.
+pub fn tag_balance_problems(page: &str) -> Vec<String> {
+    // Blank out comments, scripts and styles but keep newlines so line numbers stay right.
+    let page = opaque_regex().replace_all(page, |captures: &regex::Captures| {
+        captures[0].chars().map(|c| if c == '\n' { '\n' } else { ' ' }).collect::<String>()
+    });
+    let line_of = |offset: usize| page[..offset].matches('\n').count() + 1;
+    let mut problems = Vec::new();
+    let mut open: Vec<(String, usize)> = Vec::new();
+    for captures in tag_regex().captures_iter(&page) {
+        let name = captures[2].to_ascii_lowercase();
+        let offset = captures.get(0).unwrap().start();
+        if VOID_ELEMENTS.contains(&name.as_str()) || !captures[3].is_empty() {
+            continue;
+        }
+        if captures[1].is_empty() {
+            open.push((name, offset));
+            continue;
+        }
+        match open.iter().rposition(|(tag, _)| *tag == name) {
+            Some(index) => {
+                for (tag, tag_offset) in open.drain(index..).skip(1) {
+                    if !OPTIONAL_END_TAGS.contains(&tag.as_str()) {
+                        problems.push(format!("<{}> on line {} is closed by </{}> on line {}", tag, line_of(tag_offset), name, line_of(offset)));
+                    }
+                }
+            }
+            None => problems.push(format!("</{}> on line {} has no matching opening tag", name, line_of(offset))),
+        }
+    }
+    for (tag, offset) in open {
+        if !OPTIONAL_END_TAGS.contains(&tag.as_str()) {
+            problems.push(format!("<{}> on line {} is never closed", tag, line_of(offset)));
+        }
+    }
+    problems
+}
+
+// Basic accessibility checks: images without alt, an <html> without lang and buttons with
+// nothing for a screen reader to announce. Pages without an <html> tag are fragments and
+// are not expected to carry lang.
+pub fn accessibility_problems(page: &str) -> Vec<String> {
+    let page = opaque_regex().replace_all(page, |captures: &regex::Captures| {
+        captures[0].chars().map(|c| if c == '\n' { '\n' } else { ' ' }).collect::<String>()
+    });
+    let line_of = |offset: usize| page[..offset].matches('\n').count() + 1;
+    let mut problems = Vec::new();
+    if let Some(html) = html_open_regex().find(&page).filter(|html| !has_attribute(html.as_str(), "lang")) {
+        problems.push(format!("<html> on line {} has no lang attribute", line_of(html.start())));
+    }
+    for img in img_regex().find_iter(&page).filter(|img| !has_attribute(img.as_str(), "alt")) {
+        problems.push(format!("{} on line {} has no alt attribute", img.as_str(), line_of(img.start())));
+    }
+    for button in button_regex().captures_iter(&page) {
+        let labelled = ["aria-label", "aria-labelledby", "title"].iter().any(|name| attribute(&button[1], name).is_some_and(|value| !value.trim().is_empty()));
+        let inner = &button[2];
+        let text = tag_regex().replace_all(inner, "");
+        let image_text = img_regex().find_iter(inner).any(|img| attribute(img.as_str(), "alt").is_some_and(|alt| !alt.trim().is_empty()));
+        if !labelled && !image_text && text.trim().is_empty() {
+            problems.push(format!("<button> on line {} has no accessible text", line_of(button.get(0).unwrap().start())));
+        }
+    }
+    problems
+}
+
+// Also matches attributes written without a value, e.g. <img alt>.
+fn has_attribute(tag: &str, name: &str) -> bool {
+    let pattern = format!(r#"(?i)\s{}(?:\s|=|/|>|$)"#, regex::escape(name));
+    Regex::new(&pattern).is_ok_and(|regex| regex.is_match(tag))
+}
+
+// Rewrites relative href, src and action URLs of a page written to a different directory
+// than its source, so they still point at the same files. Both directories are relative
+// to the site root.
+pub fn rebase_relative_links(page: &str, from_dir: &Path, to_dir: &Path) -> String {
+    link_attribute_regex().replace_all(page, |captures: &regex::Captures| {
+        let (quote, url) = match captures.get(2) {
+            Some(url) => ('"', url.as_str()),
+            None => ('\'', captures.get(3).map_or("", |url| url.as_str())),
+        };
+        match rebase_url(url, from_dir, to_dir) {
+            Some(url) => format!("{}{}{}{}", &captures[1], quote, url, quote),
+            None => captures[0].to_string(),
+        }
+    }).to_string()
+}
+
+fn rebase_url(url: &str, from_dir: &Path, to_dir: &Path) -> Option<String> {
+    let is_relative = is_local_url(url) && !url.starts_with(['/', '?']) && !url.contains("{{");
+    if !is_relative {
+        return None;
+    }
+    let split = url.find(['?', '#']).unwrap_or(url.len());
+    let (path, suffix) = url.split_at(split);
+    let target = normalize(&from_dir.join(path))?;
+    let to_dir = normalize(to_dir)?;
+    let common = target.components().zip(to_dir.components()).take_while(|(a, b)| a == b).count();
+    let mut rebased = PathBuf::new();
+    for _ in to_dir.components().skip(common) {
+        rebased.push("..");
+    }
+    rebased.extend(target.components().skip(common));
+    let mut rebased = rebased.to_string_lossy().replace('\\', "/");
+    if path.ends_with('/') && !rebased.is_empty() {
+        rebased.push('/');
+    }
+    if rebased.is_empty() {
+        rebased.push_str("./");
+    }
+    Some(rebased + suffix)
+}
+
+// Resolves "." and ".." lexically; None when the path climbs above the site root.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(normalized)
+}
+
+// Scopes a component's <style> blocks and marks its top-level elements with the matching
+// attribute. Components without a <style> are returned unchanged.
+pub fn scope_component(component: &str, attribute: &str) -> String {
+    if !style_regex().is_match(component) {
+        return component.to_string();
+    }
+    let styled = style_regex().replace_all(component, |captures: &regex::Captures| {
+        format!("{}{}{}", &captures[1], css::scope_css(&captures[2], attribute), &captures[3])
+    });
+    let blanked = opaque_regex().replace_all(&styled, |captures: &regex::Captures| " ".repeat(captures[0].len()));
+    let mut result = String::with_capacity(styled.len() + attribute.len() * 4);
+    let mut last = 0;
+    let mut depth = 0usize;
+    for captures in tag_regex().captures_iter(&blanked) {
+        let name = captures[2].to_ascii_lowercase();
+        let is_void = VOID_ELEMENTS.contains(&name.as_str()) || !captures[3].is_empty();
+        if !captures[1].is_empty() {
+            depth = depth.saturating_sub(1);
+            continue;
+        }
+        if depth == 0 && !["style", "script", "el-head", "link", "meta"].contains(&name.as_str()) {
+            let tag = captures.get(0).unwrap();
+            let insert_at = tag.start() + tag.as_str()[..tag.len() - 1 - captures[3].len()].trim_end().len();
+            result.push_str(&styled[last..insert_at]);
+            result.push(' ');
+            result.push_str(attribute);
+            last = insert_at;
+        }
+        if !is_void {
+            depth += 1;
+        }
+    }
+    result.push_str(&styled[last..]);
+    result
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// The <!-- TODO: ... --> and <!-- FIXME: ... --> comments in text as (line, marker, note),
+// with lines counted from 1 and the note's whitespace collapsed.
+pub fn todo_comments(text: &str) -> Vec<(usize, &str, String)> {
+    todo_regex().captures_iter(text).map(|captures| {
+        let line = text[..captures.get(0).unwrap().start()].matches('\n').count() + 1;
+        let note = captures[2].split_whitespace().collect::<Vec<_>>().join(" ");
+        (line, captures.get(1).unwrap().as_str(), note)
+    }).collect()
+}
+
+// Whitespace-separated words outside tags, comments, scripts and styles.
+pub fn word_count(page: &str) -> usize {
+    let text = opaque_regex().replace_all(page, " ");
+    markup_regex().split(&text).map(|text| text.split_whitespace().count()).sum()
+}
+
+// The text of a page's <title>, if it has a non-empty one. Entities are left as written.
+pub fn title(page: &str) -> Option<String> {
+    let title = title_regex().captures(page)?.get(1)?.as_str().trim();
+    (!title.is_empty()).then(|| title.to_string())
+}
+
+// Joins flattened pages, given as (anchor, output path, page), into one document: the first
+// page's head, then each page's body in a <section id="anchor"> with its headings one level
+// down. Ids are prefixed with the page's anchor, and links to a bundled page, or to an id
+// on one, become links to the matching anchor in the document.
+pub fn combine_pages(pages: &[(String, PathBuf, String)]) -> String {
+    let anchor_for = |target: &Path| pages.iter().find(|(_, output, _)| output == target).map(|(anchor, _, _)| anchor.as_str());
+    let mut sections = String::new();
+    for (anchor, output, page) in pages {
+        let body = body_regex().captures(page).and_then(|captures| captures.get(1)).map_or(page.as_str(), |body| body.as_str());
+        let body = heading_regex().replace_all(body, |captures: &regex::Captures| {
+            let level: u8 = captures[2].parse().unwrap_or(6);
+            format!("<{}h{}", &captures[1], (level + 1).min(6))
+        });
+        let body = id_attribute_regex().replace_all(&body, |captures: &regex::Captures| {
+            let id = captures.get(2).or_else(|| captures.get(3)).map_or("", |id| id.as_str());
+            format!(r#"{}"{}-{}""#, &captures[1], anchor, id)
+        });
+        let page_dir = output.parent().unwrap_or(Path::new(""));
+        let body = link_attribute_regex().replace_all(&body, |captures: &regex::Captures| {
+            let url = captures.get(2).or_else(|| captures.get(3)).map_or("", |url| url.as_str());
+            let fragment = url.split_once('#').map(|(_, fragment)| fragment).filter(|fragment| !fragment.is_empty());
+            let target = match url.split_once('#') {
+                Some(("", _)) => Some(anchor.as_str()).filter(|_| fragment.is_some()),
+                _ if is_local_url(url) => resolve_url(page_dir, url).and_then(|target| anchor_for(&target)),
+                _ => None,
+            };
+            match (target, fragment) {
+                (Some(target), Some(fragment)) => format!(r##"{}"#{}-{}""##, &captures[1], target, fragment),
+                (Some(target), None) => format!(r##"{}"#{}""##, &captures[1], target),
+                (None, _) => captures[0].to_string(),
+            }
+        });
+        sections.push_str(&format!("<section id=\"{}\">\n{}\n</section>\n", anchor, body.trim()));
+    }
+    let head = pages.first()
+        .and_then(|(_, _, page)| head_regex().find(page))
+        .map_or("<head><meta charset=\"utf-8\"></head>", |head| head.as_str());
+    format!("<!DOCTYPE html>\n<html>{}\n<body>\n{}</body>\n</html>\n", head, sections)
+}
+
+// Every local href, src and action URL on a page. URLs inside CSS are not included.
+pub fn local_references(page: &str) -> Vec<&str> {
+    link_attribute_regex().captures_iter(page)
+        .filter_map(|captures| captures.get(2).or_else(|| captures.get(3)))
+        .map(|url| url.as_str())
+        .filter(|url| is_local_url(url))
+        .collect()
+}
+
+// URLs with a scheme ("https:", "data:") or a host ("//cdn") are not part of the site.
+pub fn is_local_url(url: &str) -> bool {
+    !url.is_empty() && !url.starts_with("//") && !url.starts_with('#')
+        && !url.split(['/', '?', '#']).next().is_some_and(|first| first.contains(':'))
+}
+
+// A file:// URL for an absolute path, with spaces escaped.
+pub fn file_url(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/").replace(' ', "%20");
+    if path.starts_with('/') { format!("file://{}", path) } else { format!("file:///{}", path) }
+}
+
+// The site-relative file a local URL on a page in page_dir points at, ignoring any query
+// or fragment.
+pub fn resolve_url(page_dir: &Path, url: &str) -> Option<PathBuf> {
+    let path = &url[..url.find(['?', '#']).unwrap_or(url.len())];
+    match path.strip_prefix('/') {
+        Some(rooted) => normalize(Path::new(rooted)),
+        None => normalize(&page_dir.join(path)),
+    }
+}
+
+// Inlines local stylesheets, scripts and images so the page stands alone. load receives each
+// local URL and returns its bytes, or None to leave the reference as it is.
+pub fn bundle_assets(page: &str, mut load: impl FnMut(&str) -> Option<Vec<u8>>) -> String {
+    let page = link_regex().replace_all(page, |captures: &regex::Captures| {
+        let tag = &captures[0];
+        let is_stylesheet = attribute(tag, "rel").is_some_and(|rel| rel.eq_ignore_ascii_case("stylesheet"));
+        match attribute(tag, "href").filter(|href| is_stylesheet && is_local_url(href)).and_then(&mut load) {
+            Some(css) => format!("<style>{}</style>", String::from_utf8_lossy(&css)),
+            None => tag.to_string(),
+        }
+    });
+    let page = script_src_regex().replace_all(&page, |captures: &regex::Captures| {
+        match attribute(&captures[0], "src").filter(|src| is_local_url(src)).and_then(&mut load) {
+            Some(js) => {
+                let attributes = src_attribute_regex().replace(&captures[1], "");
+                format!("<script{}>{}</script>", attributes, String::from_utf8_lossy(&js))
+            }
+            None => captures[0].to_string(),
+        }
+    });
+    img_regex().replace_all(&page, |captures: &regex::Captures| {
+        let tag = &captures[0];
+        let src = match attribute(tag, "src").filter(|src| is_local_url(src)) {
+            Some(src) => src,
+            None => return tag.to_string(),
+        };
+        let extension = Path::new(&src[..src.find(['?', '#']).unwrap_or(src.len())]).extension().and_then(|s| s.to_str()).unwrap_or_default();
+        match load(src) {
+            Some(bytes) => {
+                let data_uri = format!("data:{};base64,{}", encoding::mime_type(extension), encoding::base64(&bytes));
+                src_attribute_regex().replace(tag, |src: &regex::Captures| format!(r#"{}"{}""#, &src[1], data_uri)).to_string()
+            }
+            None => tag.to_string(),
+        }
+    }).to_string()
+}
+
+// Adds a nonce attribute to every <script> and <style> tag that doesn't carry one already.
+pub fn add_nonce(page: &str, nonce: &str) -> String {
+    script_or_style_open_regex().replace_all(page, |captures: &regex::Captures| {
+        let tag = &captures[0];
+        if attribute(tag, "nonce").is_some() {
+            return tag.to_string();
+        }
+        let insert_at = tag[..tag.len() - 1].trim_end().len();
+        format!(r#"{} nonce="{}"{}"#, &tag[..insert_at], nonce, &tag[insert_at..])
+    }).to_string()
+}
+
+// Adds integrity and crossorigin attributes to <script src> tags and to stylesheet and
+// preload links. hash receives each URL and returns its integrity value, or None to leave
+// the tag alone. Tags that already have an integrity attribute are kept as they are.
+pub fn add_integrity(page: &str, mut hash: impl FnMut(&str) -> Option<String>) -> String {
+    let mut rewrite = |tag: &str, url: Option<&str>| {
+        let integrity = match url.filter(|_| attribute(tag, "integrity").is_none()).and_then(&mut hash) {
+            Some(integrity) => integrity,
+            None => return tag.to_string(),
+        };
+        let mut attributes = format!(r#" integrity="{}""#, integrity);
+        if attribute(tag, "crossorigin").is_none() {
+            attributes.push_str(r#" crossorigin="anonymous""#);
+        }
+        let end = tag.len() - if tag.ends_with("/>") { 2 } else { 1 };
+        let insert_at = tag[..end].trim_end().len();
+        format!("{}{}{}", &tag[..insert_at], attributes, &tag[insert_at..])
+    };
+    let page = script_open_regex().replace_all(page, |captures: &regex::Captures| {
+        rewrite(&captures[0], attribute(&captures[0], "src"))
+    });
+    link_regex().replace_all(&page, |captures: &regex::Captures| {
+        let tag = &captures[0];
+        let subresource = attribute(tag, "rel").is_some_and(|rel| {
+            rel.split_whitespace().any(|rel| ["stylesheet", "preload", "modulepreload"].iter().any(|known| rel.eq_ignore_ascii_case(known)))
+        });
+        rewrite(tag, attribute(tag, "href").filter(|_| subresource))
+    }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn critical_css_is_inlined_and_its_link_becomes_a_preload() {
+        let page = r#"<html><head><link rel="stylesheet" href="/css/critical.css"><link rel="stylesheet" href="/css/site.css"></head><body></body></html>"#;
+        let inlined = inline_critical_css(page, "body { margin: 0 }\n", "css/critical.css").unwrap();
+        assert_eq!(inlined, concat!(
+            r#"<html><head><link rel="preload" href="/css/critical.css" as="style"><link rel="stylesheet" href="/css/site.css">"#,
+            "<style>body { margin: 0 }</style></head><body></body></html>",
+        ));
+        assert_eq!(inline_critical_css("<p>no head</p>", "body{}", "css/critical.css"), None);
+    }
+
+    #[test]
+    fn tag_balance_reports_mismatched_and_unclosed_tags_only() {
+        assert!(tag_balance_problems("<div><p>a<br><img src=x><li>b</div><!-- <span> --><script>if (a<b) {}</script>").is_empty());
+        assert_eq!(tag_balance_problems("<div>\n<span>a</div>"), ["<span> on line 2 is closed by </div> on line 2"]);
+        assert_eq!(tag_balance_problems("<section>\n</em>"), ["</em> on line 2 has no matching opening tag", "<section> on line 1 is never closed"]);
+    }
+
+    #[test]
+    fn relative_links_are_rebased_to_the_new_directory() {
+        let page = r#"<link href="./style.css"><img src='img/a.png'><a href="../up.html#top">u</a><a href="/abs.html">a</a><a href="https://x.org/">x</a>"#;
+        let rebased = rebase_relative_links(page, Path::new("docs"), Path::new("docs/about"));
+        assert_eq!(rebased, r#"<link href="../style.css"><img src='../img/a.png'><a href="../../up.html#top">u</a><a href="/abs.html">a</a><a href="https://x.org/">x</a>"#);
+        assert_eq!(rebase_relative_links(r#"<a href="guide/">g</a>"#, Path::new("a/b"), Path::new("a")), r#"<a href="b/guide/">g</a>"#);
+    }
+
+    #[test]
+    fn accessibility_problems_flag_missing_alt_lang_and_button_text() {
+        let page = "<html>\n<img src=\"a.png\">\n<img src=\"b.png\" alt=\"\">\n<button><i></i></button>\n<button aria-label=\"Close\"></button>\n</html>";
+        assert_eq!(accessibility_problems(page), [
+            "<html> on line 1 has no lang attribute",
+            "<img src=\"a.png\"> on line 2 has no alt attribute",
+            "<button> on line 4 has no accessible text",
+        ]);
+        assert!(accessibility_problems("<p><img alt src=\"a.png\"></p>").is_empty());
+    }
+
+    #[test]
+    fn word_count_skips_markup_comments_and_scripts() {
+        let page = "<h1>Two words</h1><!-- not these --><script>var x = 1;</script><p>and <b>three</b> more</p>";
+        assert_eq!(word_count(page), 5);
+    }
+}
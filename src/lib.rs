@@ -0,0 +1,17 @@
+pub mod builder;
+pub mod config;
+pub mod css;
+pub mod directives;
+pub mod encoding;
+pub mod error;
+pub mod frontmatter;
+pub mod html;
+pub mod log;
+pub mod remote;
+pub mod sourcefs;
+pub mod sourcemap;
+pub mod variables;
+
+pub use builder::site_builder::{BuildEvent, BuildReport, DirectiveContext, DirectiveHandler, DirectiveUsage, MissingReference, PathMapper, ProgressHandler, SharedCache, SiteBuilder, Timing, TodoMarker, Transform};
+pub use config::Config;
+pub use error::BuildError;
@@ -0,0 +1,179 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// Build events are printed as text by default, as one JSON object per line for log
+// aggregation, or with warnings and errors as GitHub Actions workflow commands. All of it
+// goes to stderr, leaving stdout to the documents that render and --stdin print.
+static FORMAT: AtomicU8 = AtomicU8::new(Format::Text as u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    Github,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "text" => Some(Format::Text),
+            "json" => Some(Format::Json),
+            "github" => Some(Format::Github),
+            _ => None,
+        }
+    }
+}
+
+pub fn set_format(format: Format) {
+    FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+fn format() -> Format {
+    match FORMAT.load(Ordering::Relaxed) {
+        format if format == Format::Json as u8 => Format::Json,
+        format if format == Format::Github as u8 => Format::Github,
+        _ => Format::Text,
+    }
+}
+
+pub fn file(path: &Path) {
+    match format() {
+        Format::Json => emit("file", &[("file", &path.display().to_string())]),
+        Format::Text | Format::Github => eprintln!("Processing file: {}", path.display()),
+    }
+}
+
+pub fn warning(message: &str) {
+    warning_at(message, None, None);
+}
+
+// A warning about a source file, and a line in it where known, which GitHub shows inline.
+pub fn warning_at(message: &str, file: Option<&Path>, line: Option<usize>) {
+    match format() {
+        Format::Json => emit_located("warning", message, file, line),
+        Format::Github => eprintln!("{}", github_line("warning", message, file, line)),
+        Format::Text => eprintln!("Warning: {}", message),
+    }
+}
+
+pub fn error(message: &str) {
+    error_at(message, None, None);
+}
+
+pub fn error_at(message: &str, file: Option<&Path>, line: Option<usize>) {
+    match format() {
+        Format::Json => emit_located("error", message, file, line),
+        Format::Github => eprintln!("{}", github_line("error", message, file, line)),
+        Format::Text => eprintln!("{}", message),
+    }
+}
+
+pub fn summary(message: &str) {
+    match format() {
+        Format::Json => emit("summary", &[("message", message)]),
+        Format::Text | Format::Github => eprintln!("{}", message),
+    }
+}
+
+fn emit(event: &str, fields: &[(&str, &str)]) {
+    eprintln!("{}", json_line(event, fields));
+}
+
+fn emit_located(event: &str, message: &str, file: Option<&Path>, line: Option<usize>) {
+    eprintln!("{}", located_json_line(event, message, file, line));
+}
+
+fn json_line(event: &str, fields: &[(&str, &str)]) -> String {
+    let mut line = format!(r#"{{"event":"{}""#, event);
+    for (key, value) in fields {
+        line.push_str(&format!(r#","{}":"{}""#, key, json_escape(value)));
+    }
+    line.push('}');
+    line
+}
+
+fn located_json_line(event: &str, message: &str, file: Option<&Path>, line: Option<usize>) -> String {
+    let file = file.map(|file| file.display().to_string());
+    let line = line.map(|line| line.to_string());
+    let mut fields = vec![("message", message)];
+    if let Some(file) = &file {
+        fields.push(("file", file));
+    }
+    if let Some(line) = &line {
+        fields.push(("line", line));
+    }
+    json_line(event, &fields)
+}
+
+fn github_line(command: &str, message: &str, file: Option<&Path>, line: Option<usize>) -> String {
+    format!("::{}{}::{}", command, github_location(file, line), github_escape(message))
+}
+
+// The file=,line= properties of a workflow command, with the path as GitHub expects it:
+// forward slashes and no leading ./.
+fn github_location(file: Option<&Path>, line: Option<usize>) -> String {
+    let file = match file {
+        Some(file) => file.to_string_lossy().replace('\\', "/"),
+        None => return String::new(),
+    };
+    let mut location = format!(" file={}", github_escape_property(file.trim_start_matches("./")));
+    if let Some(line) = line {
+        location.push_str(&format!(",line={}", line));
+    }
+    location
+}
+
+// Properties are separated by commas and end at the ::, so both are encoded too.
+fn github_escape_property(value: &str) -> String {
+    github_escape(value).replace(':', "%3A").replace(',', "%2C")
+}
+
+// Workflow commands end at a newline, so line breaks and the % that escapes them are encoded.
+fn github_escape(message: &str) -> String {
+    message.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+pub(crate) fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_events_are_one_object_per_line_with_escaped_values() {
+        assert_eq!(json_line("file", &[("file", "src/a.html")]), r#"{"event":"file","file":"src/a.html"}"#);
+        assert_eq!(json_line("summary", &[("message", "Built 2 files")]), r#"{"event":"summary","message":"Built 2 files"}"#);
+        let line = located_json_line("warning", "bad \"tag\"\nhere", Some(Path::new("src/a.html")), Some(3));
+        assert_eq!(line, r#"{"event":"warning","message":"bad \"tag\"\nhere","file":"src/a.html","line":"3"}"#);
+        assert_eq!(located_json_line("error", "failed", None, None), r#"{"event":"error","message":"failed"}"#);
+    }
+
+    #[test]
+    fn formats_parse_by_name() {
+        assert_eq!(Format::parse("json"), Some(Format::Json));
+        assert_eq!(Format::parse("text"), Some(Format::Text));
+        assert_eq!(Format::parse("xml"), None);
+    }
+
+    #[test]
+    fn github_annotations_carry_the_file_and_line() {
+        let line = github_line("warning", "[a.html] is empty\nreally", Some(Path::new("./src/docs,v1/a.html")), Some(4));
+        assert_eq!(line, "::warning file=src/docs%2Cv1/a.html,line=4::[a.html] is empty%0Areally");
+        assert_eq!(github_line("error", "100% broken", None, None), "::error::100%25 broken");
+        assert_eq!(github_line("warning", "no line", Some(Path::new("src\\b.html")), None), "::warning file=src/b.html::no line");
+    }
+}
@@ -1,11 +1,885 @@
-mod builder;
-use std::path::Path;
-use crate::builder::site_builder::SiteBuilder;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use elss::log::{self, Format};
+use elss::{BuildReport, Config, SharedCache, SiteBuilder, Timing};
+
+const TIMING_PAGES: usize = 10;
+
+const USAGE: &str = "Usage: elss [OPTIONS] [BASE_DIR]
+       elss render [OPTIONS] BASE_DIR FILE
+       elss explain [OPTIONS] BASE_DIR FILE
+       elss pdf [OPTIONS] BASE_DIR FILE OUT
+       elss check [OPTIONS] [BASE_DIR]
+       elss build-all [OPTIONS] SITES_DIR
+       elss directives [OPTIONS] [BASE_DIR]
+       elss catalog [OPTIONS] [BASE_DIR]
+       elss todos [OPTIONS] [BASE_DIR]
+       elss bundle-docs [OPTIONS] BASE_DIR --order TOC
+       elss --stdin [OPTIONS] [BASE_DIR]
+       elss --print-config [OPTIONS] [BASE_DIR]
+       elss --check-deterministic [OPTIONS] [BASE_DIR]
+
+Commands:
+  render              Expand a single file and print it to stdout without writing output
+  explain             Build one page in memory and describe each step: layouts, components
+                      and the files they resolved to, slots, variables and output size
+  pdf                 Flatten FILE and print it to the PDF file OUT with headless Chromium,
+                      found on PATH or named by ELSS_BROWSER
+  check               Build every page in memory and fail on any problem, writing nothing
+  directives          List every directive and component or layout name the site uses
+  catalog             Print a page showing every component rendered with its example
+                      variables from an adjacent <name>.toml
+  todos               List the <!-- TODO: --> and <!-- FIXME: --> comments in pages,
+                      components and layouts by file and line, writing nothing
+  build-all           Build every site under SITES_DIR (each with a src/ or elss.toml)
+  bundle-docs         Print the pages listed in TOC, one per line as \"- page.html\", as one
+                      document for printing, with headings and anchors adjusted
+
+Options:
+  --stdin             Expand a document read from stdin and write it to stdout
+  --print-config      Print the settings a build would use, after merging elss.toml, any
+                      overlay and these options, as TOML on stdout without building
+  --check-deterministic
+                      Build twice in memory and fail if any output differs, writing nothing
+  --usage-report      Write BASE_DIR/usage.json counting how often each component and layout
+                      is expanded and by which pages
+  --drafts            Also build pages marked draft: true in their front matter
+  --build-id <ID>     Stamp pages with build-id ID instead of the git revision, when
+                      version_meta is set in elss.toml
+  --pages <FILES>     Only build these pages (relative to src) and what they reference,
+                      given comma-separated (a.html,b.html) or by repeating --pages;
+                      BASE_DIR is never taken as a page, e.g. --pages a.html ./site
+  --only <DIR>        Only build the pages and assets under DIR (relative to src),
+                      leaving the rest of the output directory as it is
+  --out <DIR>         Write output to DIR instead of BASE_DIR/build
+  --mount <PATH>      Build into DIR/PATH with root-relative links rewritten under /PATH
+  --pages-mode        Build for a GitHub Pages project site: output to BASE_DIR/docs, add
+                      .nojekyll and rewrite root-relative links under /REPO
+  --repo <NAME>       With --pages-mode, the repository name instead of the one in the git
+                      origin remote
+  --relative-links    Rewrite root-relative links relative to each page, for browsing the
+                      output from file://; --offline is an alias
+  --legacy-layout     Apply only a page's first layout tag, in one pass, without nested
+                      layouts, front matter or directory defaults, as early releases did
+  --dedupe-components Emit components repeated within a page once and clone them in place
+  --log-format <F>    Print build events as text (default), as JSON lines on stderr, or
+                      with warnings and errors as GitHub Actions annotations (github)
+  --define <K=V>      Make {{ K }} render as V; may be repeated, later values win.
+                      Layout tag attributes and page front matter override defines,
+                      which override build.*
+  --max-file-size <N> Refuse to write outputs larger than N bytes (K, M and G suffixes allowed)
+  --source-map        Write a <page>.map file naming the source of each output byte range
+  --bundle            Inline local stylesheets, scripts and images into each page
+  --integrity         Add sha384 integrity attributes to local scripts and stylesheets
+  --csp-nonce         Give each page a random {{ nonce }} and add it to the page's script
+                      and style tags
+  --prune-unused-assets
+                      Only copy assets that some page links to
+  --allow-remote      Let <el-component src=\"https://...\" /> fetch content at build time
+  --remote-timeout <S>
+                      Give up on a remote component after S seconds (default 10)
+  --no-clean          Write into the existing output directory instead of replacing it,
+                      keeping files elss did not produce
+  --wait-for-lock     Wait for another build of the same output to finish instead of
+                      failing at once
+  --trim-directives   Remove the blank lines left by directive tags on lines of their own
+  --timing            Report the slowest pages and where the build spent its time
+  --env <NAME>        Merge elss.NAME.toml over elss.toml and keep <el-env name=\"NAME\">
+                      blocks, which other builds leave out
+  --copy-jobs <N>     Copy assets on N threads once the pages are built (default 1)
+  --incremental       Keep the previous output of pages whose sources, by content, did not
+                      change since the last incremental build
+  --skip-unchanged-assets
+                      Reuse the previous copy of assets whose size and mtime did not change
+  --fail-fast         With build-all, stop at the first site that fails
+  --strict            Abort the build on the first problem
+  --keep-going        Process every file, then fail if any problem was reported
+  --no-cache          Re-read every component instead of caching expansions
+  --strip-comments    Remove HTML comments from flattened pages
+  --enable-ssi        Inline <!--#include virtual=\"...\" --> directives
+  --warn-fragments    Warn about pages whose output has no <html> root element
+  --critical-css <F>  Inline the stylesheet F (relative to src) into every page head
+  --validate-refs     Report every missing component or layout reference before building
+  --lint              Warn about leftover, probably misspelled directives
+  --scoped-styles     Scope each component's <style> rules to its own elements
+  --validate-html     Report mismatched or unclosed tags in flattened pages
+  --a11y-lint         Warn about images without alt, a missing lang on <html> and buttons
+                      without accessible text in flattened pages
+  --encoding-lint     Warn about sources with control characters or invalid UTF-8
+  --check-links       Report <el-link> targets that the build did not produce
+  --warn-empty        Warn about empty component and layout files
+  --warn-duplicate-content
+                      Warn about layouts that use <el-content /> more than once";
+
+enum Command {
+    Build,
+    Render(PathBuf),
+    Explain(PathBuf),
+    Pdf(PathBuf, PathBuf),
+    Check,
+    BuildAll,
+    Directives,
+    Catalog,
+    Todos,
+    BundleDocs(PathBuf),
+    Stdin,
+    PrintConfig,
+    CheckDeterministic,
+}
+
+struct Options {
+    command: Command,
+    base_dir: PathBuf,
+    out: Option<PathBuf>,
+    strict: bool,
+    no_cache: bool,
+    strip_comments: bool,
+    enable_ssi: bool,
+    warn_fragments: bool,
+    critical_css: Option<PathBuf>,
+    order: Option<PathBuf>,
+    validate_refs: bool,
+    warn_duplicate_content: bool,
+    pages: Vec<PathBuf>,
+    fail_fast: bool,
+    incremental: bool,
+    skip_unchanged_assets: bool,
+    env: Option<String>,
+    mount: Option<String>,
+    pages_mode: bool,
+    repo: Option<String>,
+    relative_links: bool,
+    legacy_layout: bool,
+    dedupe_components: bool,
+    log_format: Format,
+    warn_empty: bool,
+    validate_html: bool,
+    a11y_lint: bool,
+    encoding_lint: bool,
+    check_links: bool,
+    drafts: bool,
+    usage_report: bool,
+    build_id: Option<String>,
+    scoped_styles: bool,
+    lint: bool,
+    defines: Vec<(String, String)>,
+    max_file_size: Option<u64>,
+    source_map: bool,
+    bundle: bool,
+    integrity: bool,
+    csp_nonce: bool,
+    timing: bool,
+    prune_unused_assets: bool,
+    allow_remote: bool,
+    remote_timeout: Option<Duration>,
+    no_clean: bool,
+    wait_for_lock: bool,
+    trim_directives: bool,
+    only: Option<PathBuf>,
+    keep_going: bool,
+    copy_jobs: usize,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        command: Command::Build,
+        base_dir: PathBuf::from("."),
+        out: None,
+        strict: false,
+        no_cache: false,
+        strip_comments: false,
+        enable_ssi: false,
+        warn_fragments: false,
+        critical_css: None,
+        order: None,
+        validate_refs: false,
+        warn_duplicate_content: false,
+        pages: Vec::new(),
+        fail_fast: false,
+        incremental: false,
+        skip_unchanged_assets: false,
+        env: None,
+        mount: None,
+        pages_mode: false,
+        repo: None,
+        relative_links: false,
+        legacy_layout: false,
+        dedupe_components: false,
+        log_format: Format::Text,
+        warn_empty: false,
+        validate_html: false,
+        a11y_lint: false,
+        encoding_lint: false,
+        check_links: false,
+        drafts: false,
+        usage_report: false,
+        build_id: None,
+        scoped_styles: false,
+        lint: false,
+        defines: Vec::new(),
+        max_file_size: None,
+        source_map: false,
+        bundle: false,
+        integrity: false,
+        csp_nonce: false,
+        timing: false,
+        prune_unused_assets: false,
+        allow_remote: false,
+        remote_timeout: None,
+        no_clean: false,
+        wait_for_lock: false,
+        trim_directives: false,
+        only: None,
+        keep_going: false,
+        copy_jobs: 1,
+    };
+    let mut positional = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--pages" => {
+                let pages = args.next().filter(|pages| !pages.starts_with("--")).ok_or("--pages requires a file")?;
+                for page in pages.split(',').map(str::trim).filter(|page| !page.is_empty()) {
+                    options.pages.push(PathBuf::from(page.strip_prefix("src/").unwrap_or(page)));
+                }
+                if options.pages.is_empty() {
+                    return Err("--pages requires at least one file".to_string());
+                }
+            }
+            "--stdin" => options.command = Command::Stdin,
+            "--print-config" => options.command = Command::PrintConfig,
+            "--check-deterministic" => options.command = Command::CheckDeterministic,
+            "--only" => {
+                let dir = args.next().ok_or("--only requires a directory")?;
+                options.only = Some(PathBuf::from(dir.strip_prefix("src/").unwrap_or(dir)));
+            }
+            "--out" => options.out = Some(PathBuf::from(args.next().ok_or("--out requires a directory")?)),
+            "--incremental" => options.incremental = true,
+            "--skip-unchanged-assets" => options.skip_unchanged_assets = true,
+            "--fail-fast" => options.fail_fast = true,
+            "--mount" => options.mount = Some(args.next().ok_or("--mount requires a path")?.clone()),
+            "--pages-mode" => options.pages_mode = true,
+            "--relative-links" | "--offline" => options.relative_links = true,
+            "--legacy-layout" => options.legacy_layout = true,
+            "--repo" => options.repo = Some(args.next().ok_or("--repo requires a name")?.clone()),
+            "--dedupe-components" => options.dedupe_components = true,
+            "--log-format" => {
+                let name = args.next().ok_or("--log-format requires text, json or github")?;
+                options.log_format = Format::parse(name).ok_or(format!("Unknown log format [{}]", name))?;
+            }
+            "--define" => {
+                let define = args.next().ok_or("--define requires key=value")?;
+                let (key, value) = define.split_once('=').ok_or(format!("--define expects key=value, got [{}]", define))?;
+                options.defines.push((key.trim().to_string(), value.to_string()));
+            }
+            "--max-file-size" => {
+                let size = args.next().ok_or("--max-file-size requires a size")?;
+                options.max_file_size = Some(parse_size(size).ok_or(format!("Invalid size [{}]", size))?);
+            }
+            "--source-map" => options.source_map = true,
+            "--bundle" => options.bundle = true,
+            "--integrity" => options.integrity = true,
+            "--csp-nonce" => options.csp_nonce = true,
+            "--timing" => options.timing = true,
+            "--prune-unused-assets" => options.prune_unused_assets = true,
+            "--allow-remote" => options.allow_remote = true,
+            "--no-clean" => options.no_clean = true,
+            "--wait-for-lock" => options.wait_for_lock = true,
+            "--trim-directives" => options.trim_directives = true,
+            "--remote-timeout" => {
+                let seconds = args.next().ok_or("--remote-timeout requires a number of seconds")?;
+                let timeout = seconds.parse().ok().and_then(|seconds| Duration::try_from_secs_f64(seconds).ok()).filter(|timeout| !timeout.is_zero());
+                options.remote_timeout = Some(timeout.ok_or(format!("Invalid number of seconds [{}]", seconds))?);
+            }
+            "--copy-jobs" => {
+                let jobs = args.next().ok_or("--copy-jobs requires a number")?;
+                options.copy_jobs = jobs.parse().ok().filter(|jobs| *jobs > 0).ok_or(format!("Invalid number of jobs [{}]", jobs))?;
+            }
+            "--env" => options.env = Some(args.next().ok_or("--env requires a name")?.clone()),
+            "--strict" => options.strict = true,
+            "--keep-going" => options.keep_going = true,
+            "--no-cache" => options.no_cache = true,
+            "--strip-comments" => options.strip_comments = true,
+            "--enable-ssi" => options.enable_ssi = true,
+            "--warn-fragments" => options.warn_fragments = true,
+            "--validate-refs" => options.validate_refs = true,
+            "--lint" => options.lint = true,
+            "--scoped-styles" => options.scoped_styles = true,
+            "--validate-html" => options.validate_html = true,
+            "--a11y-lint" => options.a11y_lint = true,
+            "--encoding-lint" => options.encoding_lint = true,
+            "--check-links" => options.check_links = true,
+            "--drafts" => options.drafts = true,
+            "--usage-report" => options.usage_report = true,
+            "--build-id" => options.build_id = Some(args.next().ok_or("--build-id requires an id")?.clone()),
+            "--warn-empty" => options.warn_empty = true,
+            "--warn-duplicate-content" => options.warn_duplicate_content = true,
+            "--critical-css" => options.critical_css = Some(PathBuf::from(args.next().ok_or("--critical-css requires a file")?)),
+            "--order" => options.order = Some(PathBuf::from(args.next().ok_or("--order requires a file")?)),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option [{}]", flag)),
+            value => positional.push(value),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    match positional.next() {
+        Some("render") => {
+            options.base_dir = PathBuf::from(positional.next().ok_or("render requires a base directory")?);
+            options.command = Command::Render(PathBuf::from(positional.next().ok_or("render requires a file")?));
+        }
+        Some("explain") => {
+            options.base_dir = PathBuf::from(positional.next().ok_or("explain requires a base directory")?);
+            options.command = Command::Explain(PathBuf::from(positional.next().ok_or("explain requires a file")?));
+        }
+        Some("pdf") => {
+            options.base_dir = PathBuf::from(positional.next().ok_or("pdf requires a base directory")?);
+            let file = PathBuf::from(positional.next().ok_or("pdf requires a file")?);
+            options.command = Command::Pdf(file, PathBuf::from(positional.next().ok_or("pdf requires an output file")?));
+        }
+        Some("check") => {
+            options.command = Command::Check;
+            if let Some(dir) = positional.next() {
+                options.base_dir = PathBuf::from(dir);
+            }
+        }
+        Some("directives") => {
+            options.command = Command::Directives;
+            if let Some(dir) = positional.next() {
+                options.base_dir = PathBuf::from(dir);
+            }
+        }
+        Some("catalog") => {
+            options.command = Command::Catalog;
+            if let Some(dir) = positional.next() {
+                options.base_dir = PathBuf::from(dir);
+            }
+        }
+        Some("todos") => {
+            options.command = Command::Todos;
+            if let Some(dir) = positional.next() {
+                options.base_dir = PathBuf::from(dir);
+            }
+        }
+        Some("bundle-docs") => {
+            options.base_dir = PathBuf::from(positional.next().ok_or("bundle-docs requires a base directory")?);
+            options.command = Command::BundleDocs(options.order.clone().ok_or("bundle-docs requires --order")?);
+        }
+        Some("build-all") => {
+            options.command = Command::BuildAll;
+            options.base_dir = PathBuf::from(positional.next().ok_or("build-all requires a sites directory")?);
+            if options.out.is_some() {
+                return Err("--out cannot be used with build-all".to_string());
+            }
+        }
+        Some(dir) => options.base_dir = PathBuf::from(dir),
+        None => {}
+    }
+    if let Some(extra) = positional.next() {
+        return Err(format!("Unexpected argument [{}]", extra));
+    }
+    Ok(options)
+}
+
+// Writes page to a temporary file and has the first headless browser that starts print it.
+const BROWSERS: &[&str] = &["chromium", "chromium-browser", "google-chrome", "google-chrome-stable", "chrome"];
+
+fn print_to_pdf(page: &str, out: &Path) -> Result<(), String> {
+    let out = std::path::absolute(out).map_err(|e| format!("Failed to resolve [{}]: {}", out.display(), e))?;
+    let html_path = std::env::temp_dir().join(format!("elss-print-{}.html", std::process::id()));
+    std::fs::write(&html_path, page).map_err(|e| format!("Failed to write [{}]: {}", html_path.display(), e))?;
+    let browsers: Vec<String> = match std::env::var("ELSS_BROWSER") {
+        Ok(browser) => vec![browser],
+        Err(_) => BROWSERS.iter().map(|browser| browser.to_string()).collect(),
+    };
+    let mut result = Err(format!("No headless browser found, tried {}; install Chromium or set ELSS_BROWSER", browsers.join(", ")));
+    for browser in &browsers {
+        let status = std::process::Command::new(browser)
+            .args(["--headless", "--disable-gpu", "--no-pdf-header-footer"])
+            .arg(format!("--print-to-pdf={}", out.display()))
+            .arg(elss::html::file_url(&html_path))
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status();
+        match status {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => result = Err(format!("Failed to run [{}]: {}", browser, e)),
+            Ok(status) if status.success() && out.is_file() => result = Ok(()),
+            Ok(status) => result = Err(format!("[{}] did not print the page ({})", browser, status)),
+        }
+        break;
+    }
+    let _ = std::fs::remove_file(&html_path);
+    result
+}
+
+// One page per line, relative to src, with or without a leading "- ". Blank lines and
+// # comments are skipped.
+fn read_toc(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read [{}]: {}", path.display(), e))?;
+    Ok(text.lines()
+        .map(|line| line.trim().trim_start_matches('-').trim().trim_matches(|c| c == '"' || c == '\''))
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| PathBuf::from(line.strip_prefix("src/").unwrap_or(line)))
+        .collect())
+}
+
+fn parse_size(size: &str) -> Option<u64> {
+    let (digits, multiplier) = match size.to_ascii_uppercase().chars().last()? {
+        'K' => (&size[..size.len() - 1], 1 << 10),
+        'M' => (&size[..size.len() - 1], 1 << 20),
+        'G' => (&size[..size.len() - 1], 1 << 30),
+        _ => (size, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+fn load_config(base_dir: &Path, env: Option<&str>) -> Config {
+    match Config::load_env(base_dir, env) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error(&e.to_string());
+            std::process::exit(1);
+        }
+    }
+}
+
+fn site_builder(options: &Options, base_dir: &Path, config: &Config) -> SiteBuilder {
+    let mut site_builder = SiteBuilder::from_config(base_dir.to_path_buf(), config)
+        .with_strip_comments(options.strip_comments)
+        .with_strict(options.strict)
+        .with_cache(!options.no_cache)
+        .with_ssi(options.enable_ssi)
+        .with_warn_fragments(options.warn_fragments)
+        .with_reference_validation(options.validate_refs)
+        .with_warn_duplicate_content(options.warn_duplicate_content)
+        .with_pages(options.pages.clone())
+        .with_incremental(options.incremental)
+        .with_skip_unchanged_assets(options.skip_unchanged_assets)
+        .with_copy_jobs(options.copy_jobs)
+        .with_dedupe_components(options.dedupe_components)
+        .with_warn_empty(options.warn_empty)
+        .with_html_validation(options.validate_html)
+        .with_a11y_lint(options.a11y_lint)
+        .with_encoding_lint(options.encoding_lint)
+        .with_relative_links(options.relative_links)
+        .with_legacy_layout(options.legacy_layout)
+        .with_link_checking(options.check_links)
+        .with_drafts(options.drafts)
+        .with_env(options.env.clone())
+        .with_usage_report(options.usage_report)
+        .with_build_id(options.build_id.clone())
+        .with_scoped_styles(options.scoped_styles)
+        .with_lint(options.lint)
+        .with_defines(options.defines.clone())
+        .with_max_file_size(options.max_file_size)
+        .with_source_map(options.source_map)
+        .with_bundle(options.bundle)
+        .with_integrity(options.integrity)
+        .with_csp_nonce(options.csp_nonce)
+        .with_prune_unused_assets(options.prune_unused_assets)
+        .with_allow_remote(options.allow_remote)
+        .with_no_clean(options.no_clean)
+        .with_wait_for_lock(options.wait_for_lock)
+        .with_trim_directives(options.trim_directives)
+        .with_only(options.only.clone())
+        .with_keep_going(options.keep_going);
+    if let Some(remote_timeout) = options.remote_timeout {
+        site_builder = site_builder.with_remote_timeout(remote_timeout);
+    }
+    if options.critical_css.is_some() {
+        site_builder = site_builder.with_critical_css(options.critical_css.clone());
+    }
+    if let Some(out) = &options.out {
+        site_builder = site_builder.with_dest_dir(out.clone());
+    }
+    if options.pages_mode {
+        // A <user>.github.io repository is served from the domain root.
+        let repo = options.repo.clone().or_else(|| git_repo_name(base_dir));
+        if repo.is_none() {
+            log::warning("--pages-mode found no git origin remote to name the repository, links are not rewritten; pass --repo");
+        }
+        site_builder = site_builder
+            .with_dest_dir(options.out.clone().unwrap_or_else(|| base_dir.join("docs")))
+            .with_base_path(repo.filter(|repo| !repo.ends_with(".github.io")))
+            .with_nojekyll(true);
+    }
+    if options.mount.is_some() {
+        site_builder = site_builder.with_mount(options.mount.clone());
+    }
+    site_builder
+}
+
+// The last path segment of the origin URL, e.g. "elss" for git@github.com:owner/elss.git.
+fn git_repo_name(dir: &Path) -> Option<String> {
+    let output = std::process::Command::new("git").arg("-C").arg(dir).args(["remote", "get-url", "origin"]).output().ok()?;
+    let url = String::from_utf8(output.stdout).ok()?;
+    let url = url.trim().trim_end_matches('/');
+    let name = url.trim_end_matches(".git").rsplit(['/', ':']).next()?;
+    (output.status.success() && !name.is_empty()).then(|| name.to_string())
+}
+
+// Sites are the immediate subdirectories that have a src/ directory or an elss.toml.
+fn find_sites(sites_dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut sites = Vec::new();
+    for entry in std::fs::read_dir(sites_dir)? {
+        let path = entry?.path();
+        if path.join("src").is_dir() || path.join(elss::config::CONFIG_FILE).is_file() {
+            sites.push(path);
+        }
+    }
+    sites.sort();
+    Ok(sites)
+}
+
+fn build_all(options: &Options) {
+    let sites = match find_sites(&options.base_dir) {
+        Ok(sites) => sites,
+        Err(e) => {
+            log::error(&format!("Failed to read directory [{}]: {}", options.base_dir.display(), e));
+            std::process::exit(1);
+        }
+    };
+    let cache = SharedCache::default();
+    let mut failed = 0;
+    for site in &sites {
+        let config = match Config::load_env(site, options.env.as_deref()) {
+            Ok(config) => config,
+            Err(e) => {
+                log::error(&format!("Failed [{}]: {}", site.display(), e));
+                failed += 1;
+                if options.fail_fast {
+                    break;
+                }
+                continue;
+            }
+        };
+        match site_builder(options, site, &config).with_shared_cache(cache.clone()).build() {
+            Ok(report) => log::summary(&format!("Built [{}]: {} files", site.display(), report.written.len())),
+            Err(e) => {
+                log::error(&format!("Failed [{}]: {}", site.display(), e));
+                failed += 1;
+                if options.fail_fast {
+                    break;
+                }
+            }
+        }
+    }
+    if failed > 0 {
+        log::error(&format!("{} of {} sites failed", failed, sites.len()));
+        std::process::exit(1);
+    }
+}
+
+fn build_summary(report: &BuildReport) -> String {
+    let mut summary = format!("Built {} files, {} written", report.written.len(), format_bytes(report.bytes_written));
+    if report.bytes_saved > 0 {
+        summary.push_str(&format!(", {} saved by optimizations", format_bytes(report.bytes_saved.unsigned_abs())));
+    } else if report.bytes_saved < 0 {
+        summary.push_str(&format!(", {} added by optimizations", format_bytes(report.bytes_saved.unsigned_abs())));
+    }
+    if !report.reused.is_empty() {
+        summary.push_str(&format!(", {} unchanged pages kept", report.reused.len()));
+    }
+    summary
+}
+
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+fn print_timing(timing: &Timing) {
+    for (page, elapsed) in timing.slowest(TIMING_PAGES) {
+        log::summary(&format!("{:>10.2?}  {}", elapsed, page.display()));
+    }
+    log::summary(&format!("Components {:.2?}, layouts {:.2?}, writing {:.2?}", timing.components, timing.layouts, timing.io));
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    let base_dir = if args.len() >= 2 { Path::new(&args[1]) } else { Path::new(".") };
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    log::set_format(options.log_format);
+    let config = load_config(&options.base_dir, options.env.as_deref());
+    let mut site_builder = site_builder(&options, &options.base_dir, &config);
+
+    match options.command {
+        Command::BuildAll => build_all(&options),
+        Command::Build => match site_builder.build() {
+            Ok(report) => {
+                if options.timing {
+                    print_timing(&report.timing);
+                }
+                log::summary(&build_summary(&report));
+            }
+            Err(e) => {
+                log::error(&format!("Build failed: {}", e));
+                std::process::exit(1);
+            }
+        },
+        Command::Check => {
+            let result = site_builder.with_dry_run(true)
+                .with_strict(true)
+                .with_unknown_directive_check(true)
+                .build();
+            match result {
+                Ok(report) => log::summary(&format!("Checked {} files, no problems found", report.written.len())),
+                Err(e) => {
+                    log::error(&format!("Check failed: {}", e));
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Directives => match site_builder.directive_usage() {
+            Ok(usage) => {
+                for (tag, count) in &usage.tags {
+                    println!("{:>6}  <{}>", count, tag);
+                }
+                println!("\nComponents:");
+                for name in &usage.components {
+                    println!("  {}", name);
+                }
+                println!("\nLayouts:");
+                for name in &usage.layouts {
+                    println!("  {}", name);
+                }
+            }
+            Err(e) => {
+                log::error(&format!("Failed to scan directives: {}", e));
+                std::process::exit(1);
+            }
+        },
+        Command::Stdin => {
+            let mut input = String::new();
+            if let Err(e) = std::io::stdin().read_to_string(&mut input) {
+                log::error(&format!("Failed to read stdin: {}", e));
+                std::process::exit(1);
+            }
+            match site_builder.with_progress(false).render_string(&input) {
+                Ok(content) => print!("{}", content),
+                Err(e) => {
+                    log::error(&format!("Render failed: {}", e));
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::PrintConfig => print!("{}", elss::config::to_toml(&site_builder.resolved_config())),
+        Command::CheckDeterministic => match site_builder.with_progress(false).check_deterministic() {
+            Ok(differing) if differing.is_empty() => log::summary("Two builds produced identical output"),
+            Ok(differing) => {
+                for output in &differing {
+                    log::error(&format!("[{}] differs between two builds", output.display()));
+                }
+                log::error(&format!("Output is not deterministic: {} files differ", differing.len()));
+                std::process::exit(1);
+            }
+            Err(e) => {
+                log::error(&format!("Build failed: {}", e));
+                std::process::exit(1);
+            }
+        },
+        Command::BundleDocs(toc) => {
+            let pages = match read_toc(&toc) {
+                Ok(pages) => pages,
+                Err(message) => {
+                    log::error(&message);
+                    std::process::exit(1);
+                }
+            };
+            match site_builder.with_progress(false).bundle_docs(&pages) {
+                Ok(document) => print!("{}", document),
+                Err(e) => {
+                    log::error(&format!("Bundle failed: {}", e));
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Catalog => match site_builder.with_progress(false).catalog() {
+            Ok(catalog) => print!("{}", catalog),
+            Err(e) => {
+                log::error(&format!("Catalog failed: {}", e));
+                std::process::exit(1);
+            }
+        },
+        Command::Todos => match site_builder.todo_markers() {
+            Ok(markers) => {
+                let mut last_file = None;
+                for marker in &markers {
+                    if last_file != Some(&marker.file) {
+                        println!("{}", marker.file.display());
+                        last_file = Some(&marker.file);
+                    }
+                    println!("  {:>5}  {}: {}", marker.line, marker.marker, marker.note);
+                }
+                log::summary(&format!("{} TODO and FIXME comments", markers.len()));
+            }
+            Err(e) => {
+                log::error(&format!("Failed to scan for TODO comments: {}", e));
+                std::process::exit(1);
+            }
+        },
+        Command::Pdf(file, out) => {
+            let file = file.strip_prefix("src").unwrap_or(&file).to_path_buf();
+            let page = match site_builder.with_progress(false).render_for_print(&file) {
+                Ok(page) => page,
+                Err(e) => {
+                    log::error(&format!("Render failed: {}", e));
+                    std::process::exit(1);
+                }
+            };
+            match print_to_pdf(&page, &out) {
+                Ok(()) => log::summary(&format!("Wrote {}", out.display())),
+                Err(message) => {
+                    log::error(&message);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Explain(file) => {
+            let file = file.strip_prefix("src").unwrap_or(&file).to_path_buf();
+            match site_builder.with_progress(false).explain(&file) {
+                Ok(trace) => {
+                    for step in trace {
+                        println!("{}", step);
+                    }
+                }
+                Err(e) => {
+                    log::error(&format!("Explain failed: {}", e));
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Render(file) => {
+            let file = file.strip_prefix("src").unwrap_or(&file).to_path_buf();
+            match site_builder.with_progress(false).render_component(&file) {
+                Ok(content) => print!("{}", content),
+                Err(e) => {
+                    log::error(&format!("Render failed: {}", e));
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_args(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn out_sets_the_output_directory() {
+        let options = parse(&["--out", "dist/site", "site"]).unwrap();
+        assert_eq!(options.out, Some(PathBuf::from("dist/site")));
+        assert_eq!(options.base_dir, PathBuf::from("site"));
+        assert!(parse(&["--out"]).is_err());
+    }
+
+    #[test]
+    fn render_takes_a_base_directory_and_a_file() {
+        let options = parse(&["render", "./site", "src/el-components/card.html"]).unwrap();
+        assert_eq!(options.base_dir, PathBuf::from("./site"));
+        assert!(matches!(options.command, Command::Render(file) if file == Path::new("src/el-components/card.html")));
+        assert!(parse(&["render", "./site"]).is_err());
+    }
+
+    #[test]
+    fn check_takes_an_optional_base_directory() {
+        let options = parse(&["check", "./site"]).unwrap();
+        assert!(matches!(options.command, Command::Check));
+        assert_eq!(options.base_dir, PathBuf::from("./site"));
+        assert_eq!(parse(&["check"]).unwrap().base_dir, PathBuf::from("."));
+    }
+
+    #[test]
+    fn stdin_selects_the_stdin_command() {
+        assert!(matches!(parse(&["--stdin", "site"]).unwrap().command, Command::Stdin));
+    }
+
+    #[test]
+    fn pages_takes_a_comma_separated_list_and_leaves_the_base_directory_alone() {
+        let options = parse(&["--pages", "a.html, src/b/c.html", "--pages", "d.html", "site"]).unwrap();
+        assert_eq!(options.pages, [PathBuf::from("a.html"), PathBuf::from("b/c.html"), PathBuf::from("d.html")]);
+        assert_eq!(options.base_dir, PathBuf::from("site"));
+        assert!(parse(&["--pages"]).is_err());
+        assert!(parse(&["--pages", "--strict"]).is_err());
+    }
+
+    #[test]
+    fn build_all_builds_every_site_under_the_directory() {
+        let sites_dir = std::env::temp_dir().join(format!("elss-test-build-all-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&sites_dir);
+        for (site, page) in [("a", "<p>a</p>"), ("b", "<p>b</p>")] {
+            std::fs::create_dir_all(sites_dir.join(site).join("src")).unwrap();
+            std::fs::write(sites_dir.join(site).join("src/index.html"), page).unwrap();
+        }
+        std::fs::create_dir_all(sites_dir.join("notes")).unwrap();
+        assert_eq!(find_sites(&sites_dir).unwrap(), [sites_dir.join("a"), sites_dir.join("b")]);
+
+        let options = parse(&["build-all", &sites_dir.to_string_lossy()]).unwrap();
+        build_all(&options);
+        assert_eq!(std::fs::read_to_string(sites_dir.join("a/build/index.html")).unwrap(), "<p>a</p>");
+        assert_eq!(std::fs::read_to_string(sites_dir.join("b/build/index.html")).unwrap(), "<p>b</p>");
+        std::fs::remove_dir_all(&sites_dir).unwrap();
+    }
+
+    #[test]
+    fn defines_repeat_and_split_on_the_first_equals_sign() {
+        let options = parse(&["--define", "a=1", "--define", "url=https://x.org/?q=1", "site"]).unwrap();
+        assert_eq!(options.defines, [("a".to_string(), "1".to_string()), ("url".to_string(), "https://x.org/?q=1".to_string())]);
+        assert!(parse(&["--define", "novalue"]).is_err());
+    }
+
+    #[test]
+    fn print_config_shows_a_cli_override_of_the_config_file() {
+        let config = Config::parse("dest_dir = \"public\"\n").unwrap();
+        let options = parse(&["--print-config", "--out", "dist", "site"]).unwrap();
+        assert!(matches!(options.command, Command::PrintConfig));
+        let printed = elss::config::to_toml(&site_builder(&options, &options.base_dir, &config).resolved_config());
+        assert!(printed.contains("dest_dir = \"dist\"\n"), "{}", printed);
+        assert!(!printed.contains("public"), "{}", printed);
+    }
+
+    #[test]
+    fn read_toc_lists_pages_in_order() {
+        let toc = std::env::temp_dir().join(format!("elss-test-toc-{}.yaml", std::process::id()));
+        std::fs::write(&toc, "# Docs\n- intro.html\n- \"src/guide/install.html\"\n\nfaq.html\n").unwrap();
+        let pages = read_toc(&toc);
+        std::fs::remove_file(&toc).unwrap();
+        assert_eq!(pages.unwrap(), [PathBuf::from("intro.html"), PathBuf::from("guide/install.html"), PathBuf::from("faq.html")]);
+        assert!(parse(&["bundle-docs", "site"]).is_err());
+    }
+
+    #[test]
+    fn pages_mode_builds_into_docs_with_nojekyll_and_the_repo_base_path() {
+        let base_dir = std::env::temp_dir().join(format!("elss-test-pages-mode-{}", std::process::id()));
+        std::fs::create_dir_all(base_dir.join("src")).unwrap();
+        std::fs::write(base_dir.join("src/index.html"), r#"<a href="/about.html">About</a>"#).unwrap();
+        let options = parse(&["--pages-mode", "--repo", "widgets", &base_dir.to_string_lossy()]).unwrap();
+        site_builder(&options, &base_dir, &Config::default()).with_progress(false).build().unwrap();
+        assert_eq!(std::fs::read_to_string(base_dir.join("docs/index.html")).unwrap(), r#"<a href="/widgets/about.html">About</a>"#);
+        assert!(base_dir.join("docs/.nojekyll").is_file());
+        assert!(!base_dir.join("build").exists());
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
 
-    let mut site_builder = SiteBuilder::new(base_dir.to_path_buf());
-    site_builder.build();
-}
\ No newline at end of file
+    #[test]
+    fn pdf_takes_a_base_directory_a_page_and_an_output_file() {
+        let options = parse(&["pdf", "./site", "src/report.html", "report.pdf"]).unwrap();
+        assert_eq!(options.base_dir, PathBuf::from("./site"));
+        assert!(matches!(options.command, Command::Pdf(file, out) if file == Path::new("src/report.html") && out == Path::new("report.pdf")));
+        assert!(parse(&["pdf", "./site", "src/report.html"]).is_err());
+    }
+}
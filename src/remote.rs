@@ -0,0 +1,69 @@
+// Build-time fetching of remote components. Transfers go through curl, so https works
+// without a TLS dependency of our own.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn is_remote(src: &str) -> bool {
+    src.starts_with("http://") || src.starts_with("https://")
+}
+
+// Returns the body at url, from cache_dir when an earlier build already fetched it unless
+// refresh is set. Fresh responses are written back to cache_dir.
+pub fn fetch(url: &str, timeout: Duration, cache_dir: &Path, refresh: bool) -> Result<String, String> {
+    let cache_path = cache_path(cache_dir, url);
+    if !refresh {
+        if let Ok(cached) = fs::read_to_string(&cache_path) {
+            return Ok(cached);
+        }
+    }
+    let output = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--location"])
+        .args(["--proto", "=http,https", "--proto-redir", "=http,https"])
+        .arg("--max-time").arg(timeout.as_secs_f64().to_string())
+        .arg(url)
+        .output()
+        .map_err(|e| format!("could not run curl: {}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    let body = String::from_utf8(output.stdout).map_err(|_| "response is not valid UTF-8".to_string())?;
+    if let Err(e) = fs::create_dir_all(cache_dir).and_then(|_| fs::write(&cache_path, &body)) {
+        crate::log::warning(&format!("could not cache [{}]: {}", url, e));
+    }
+    Ok(body)
+}
+
+// FNV-1a of the URL keeps cache file names short and filesystem-safe.
+fn cache_path(cache_dir: &Path, url: &str) -> PathBuf {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in url.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    cache_dir.join(format!("{:016x}.html", hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_http_and_https_urls_are_remote() {
+        assert!(is_remote("https://example.com/footer.html"));
+        assert!(is_remote("http://example.com/"));
+        assert!(!is_remote("ftp://example.com/footer.html"));
+        assert!(!is_remote("footer.html"));
+    }
+
+    #[test]
+    fn each_url_gets_its_own_cache_file() {
+        let dir = Path::new("cache");
+        assert_eq!(cache_path(dir, "https://a.org/x"), cache_path(dir, "https://a.org/x"));
+        assert_ne!(cache_path(dir, "https://a.org/x"), cache_path(dir, "https://a.org/y"));
+        assert_eq!(cache_path(dir, "https://a.org/x").parent(), Some(dir));
+    }
+}
@@ -0,0 +1,204 @@
+// Where the builder reads sources from. The real filesystem is the default; MemoryFs lets a
+// site be built from files embedded in the binary, e.g. with include_bytes! or include_dir.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+// Sources are shared with the asset copy workers, hence Sync.
+pub trait SourceFs: Sync {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    // Paths of the entries directly inside a directory, in no particular order.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    fn is_file(&self, path: &Path) -> bool;
+
+    fn is_dir(&self, path: &Path) -> bool;
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // Appends the file to buffer, so a caller reading many files can reuse one allocation.
+    fn read_into(&self, path: &Path, buffer: &mut String) -> io::Result<()> {
+        buffer.push_str(&self.read_to_string(path)?);
+        Ok(())
+    }
+
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        Ok(self.read(path)?.len() as u64)
+    }
+
+    // Sources without a modification time are always treated as changed.
+    fn modified(&self, _path: &Path) -> Option<SystemTime> {
+        None
+    }
+
+    fn copy(&self, path: &Path, to: &Path) -> io::Result<()> {
+        fs::write(to, self.read(path)?)
+    }
+
+    // The key a source is cached under, so two spellings of one file share an entry.
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        path.to_path_buf()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DiskFs;
+
+impl SourceFs for DiskFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?.map(|entry| entry.map(|entry| entry.path())).collect()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn read_into(&self, path: &Path, buffer: &mut String) -> io::Result<()> {
+        fs::File::open(path)?.read_to_string(buffer).map(|_| ())
+    }
+
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    fn copy(&self, path: &Path, to: &Path) -> io::Result<()> {
+        fs::copy(path, to).map(|_| ())
+    }
+
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+    }
+}
+
+// Files held in memory, keyed by the path the builder will ask for, i.e. under the base
+// directory's src/. Directories exist implicitly wherever a file does.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryFs {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        MemoryFs::default()
+    }
+
+    pub fn insert(&mut self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) {
+        self.files.insert(normalize(path.as_ref()), contents.into());
+    }
+
+    pub fn with_file(mut self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) -> Self {
+        self.insert(path, contents);
+        self
+    }
+}
+
+impl SourceFs for MemoryFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.files.get(&normalize(path)).cloned().ok_or_else(|| not_found(path))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let dir = normalize(path);
+        let entries: BTreeSet<PathBuf> = self.files.keys()
+            .filter_map(|file| file.strip_prefix(&dir).ok())
+            .filter_map(|relative| relative.components().next())
+            .map(|first| path.join(first))
+            .collect();
+        if entries.is_empty() {
+            return Err(not_found(path));
+        }
+        Ok(entries.into_iter().collect())
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.contains_key(&normalize(path))
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        let dir = normalize(path);
+        self.files.keys().any(|file| file != &dir && file.starts_with(&dir))
+    }
+}
+
+impl<P: AsRef<Path>, C: Into<Vec<u8>>> FromIterator<(P, C)> for MemoryFs {
+    fn from_iter<I: IntoIterator<Item = (P, C)>>(files: I) -> Self {
+        let mut fs = MemoryFs::new();
+        for (path, contents) in files {
+            fs.insert(path, contents);
+        }
+        fs
+    }
+}
+
+// "./site/src/a.html", "site/src/a.html" and "site/src/../src/a.html" name the same file.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{} is not in the source filesystem", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_fs_lists_files_and_implicit_directories() {
+        let fs: MemoryFs = [("site/src/index.html", "home"), ("site/src/blog/post.html", "post")].into_iter().collect();
+        assert_eq!(fs.read_to_string(Path::new("./site/src/blog/../index.html")).unwrap(), "home");
+        assert!(fs.is_dir(Path::new("site/src/blog")));
+        assert!(!fs.is_dir(Path::new("site/src/index.html")));
+        assert!(fs.is_file(Path::new("site/src/blog/post.html")));
+        let mut entries = fs.read_dir(Path::new("site/src")).unwrap();
+        entries.sort();
+        assert_eq!(entries, [PathBuf::from("site/src/blog"), PathBuf::from("site/src/index.html")]);
+        assert_eq!(fs.read(Path::new("site/src/missing.html")).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn read_into_appends_to_the_buffer() {
+        let base_dir = std::env::temp_dir().join(format!("elss-test-read-into-{}", std::process::id()));
+        fs::create_dir_all(&base_dir).unwrap();
+        fs::write(base_dir.join("a.html"), "<p>a</p>").unwrap();
+        let memory: MemoryFs = [(base_dir.join("a.html"), "<p>a</p>")].into_iter().collect();
+        for source_fs in [&DiskFs as &dyn SourceFs, &memory] {
+            let mut buffer = String::from("kept|");
+            source_fs.read_into(&base_dir.join("a.html"), &mut buffer).unwrap();
+            assert_eq!(buffer, "kept|<p>a</p>");
+            assert!(source_fs.read_into(&base_dir.join("missing.html"), &mut buffer).is_err());
+        }
+        fs::remove_dir_all(&base_dir).unwrap();
+    }
+}
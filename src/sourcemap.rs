@@ -0,0 +1,113 @@
+use std::borrow::Cow;
+use std::ops::Range;
+use std::path::Path;
+use crate::log::json_escape;
+
+// Expansions are bracketed with private-use markers while a page is assembled, so their
+// positions survive every later pass. The markers are stripped right before writing.
+const OPEN: char = '\u{E000}';
+const CLOSE: char = '\u{E001}';
+const END: char = '\u{E002}';
+
+pub fn wrap(id: usize, content: &str) -> String {
+    format!("{}{}{}{}{}{}{}", OPEN, id, END, content, CLOSE, id, END)
+}
+
+// Source text may already contain the private-use characters, e.g. icon font glyphs. Each
+// one is prefixed with END, which never starts a marker, and strip keeps the character.
+pub fn escape(text: &str) -> Cow<'_, str> {
+    if !text.contains([OPEN, CLOSE, END]) {
+        return Cow::Borrowed(text);
+    }
+    let mut escaped = String::with_capacity(text.len() + 3);
+    for c in text.chars() {
+        if c == OPEN || c == CLOSE || c == END {
+            escaped.push(END);
+        }
+        escaped.push(c);
+    }
+    Cow::Owned(escaped)
+}
+
+// Removes the markers and returns, for each byte range of the cleaned text, the id of the
+// innermost expansion it came from. Text outside any expansion is not mapped.
+pub fn strip(text: &str) -> (String, Vec<(Range<usize>, usize)>) {
+    let mut clean = String::with_capacity(text.len());
+    let mut ranges: Vec<(Range<usize>, usize)> = Vec::new();
+    let mut stack: Vec<usize> = Vec::new();
+    let mut segment_start = 0;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == END {
+            clean.extend(chars.next());
+            continue;
+        }
+        if c != OPEN && c != CLOSE {
+            clean.push(c);
+            continue;
+        }
+        let mut digits = String::new();
+        while let Some(&d) = chars.peek() {
+            chars.next();
+            if d == END {
+                break;
+            }
+            digits.push(d);
+        }
+        if let Some(&top) = stack.last() {
+            push_range(&mut ranges, segment_start..clean.len(), top);
+        }
+        segment_start = clean.len();
+        match (c, digits.parse::<usize>()) {
+            (OPEN, Ok(id)) => stack.push(id),
+            _ => {
+                stack.pop();
+            }
+        }
+    }
+    (clean, ranges)
+}
+
+fn push_range(ranges: &mut Vec<(Range<usize>, usize)>, range: Range<usize>, id: usize) {
+    if range.is_empty() {
+        return;
+    }
+    match ranges.last_mut() {
+        Some((last, last_id)) if *last_id == id && last.end == range.start => last.end = range.end,
+        _ => ranges.push((range, id)),
+    }
+}
+
+pub fn to_json(file: &Path, ranges: &[(Range<usize>, usize)], sources: &[String]) -> String {
+    let entries: Vec<String> = ranges.iter()
+        .map(|(range, id)| format!(r#"{{"start":{},"end":{},"source":"{}"}}"#, range.start, range.end, json_escape(&sources[*id])))
+        .collect();
+    format!("{{\"file\":\"{}\",\"ranges\":[\n{}\n]}}\n", json_escape(&file.to_string_lossy()), entries.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_maps_each_range_to_its_innermost_expansion() {
+        let text = format!("a{}b", wrap(0, &format!("xy{}z", wrap(1, "123"))));
+        let (clean, ranges) = strip(&text);
+        assert_eq!(clean, "axy123zb");
+        assert_eq!(ranges, [(1..3, 0), (3..6, 1), (6..7, 0)]);
+    }
+
+    #[test]
+    fn adjacent_ranges_of_one_source_merge() {
+        let text = format!("{}{}", wrap(2, "ab"), wrap(2, "cd"));
+        assert_eq!(strip(&text), ("abcd".to_string(), vec![(0..4, 2)]));
+    }
+
+    #[test]
+    fn escaped_private_use_characters_survive_strip() {
+        let source = "\u{E000}1\u{E002}icon\u{E001}";
+        let text = wrap(0, &format!("<i>{}</i>", escape(source)));
+        assert_eq!(strip(&text), (format!("<i>{}</i>", source), vec![(0..source.len() + 7, 0)]));
+        assert!(matches!(escape("plain"), Cow::Borrowed("plain")));
+    }
+}
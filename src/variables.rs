@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use regex::Regex;
+
+pub const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+fn placeholder_regex() -> &'static Regex {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| Regex::new(r#"\{\{\s*([A-Za-z0-9_.-]+)\s*\}\}"#).unwrap())
+}
+
+// Replaces `{{ name }}` with known variables. Unknown names are left for the author to spot.
+pub fn substitute(text: &str, variables: &BTreeMap<String, String>) -> String {
+    placeholder_regex().replace_all(text, |captures: &regex::Captures| {
+        match variables.get(&captures[1]) {
+            Some(value) => value.clone(),
+            None => captures[0].to_string(),
+        }
+    }).to_string()
+}
+
+// The names of the `{{ name }}` placeholders in text that variables would fill, in order.
+pub fn used<'t>(text: &'t str, variables: &BTreeMap<String, String>) -> Vec<&'t str> {
+    placeholder_regex().captures_iter(text)
+        .filter_map(|captures| captures.get(1))
+        .map(|name| name.as_str())
+        .filter(|name| variables.contains_key(*name))
+        .collect()
+}
+
+// Seconds since the epoch, taken from SOURCE_DATE_EPOCH when set so builds are reproducible.
+pub fn build_timestamp() -> u64 {
+    if let Some(epoch) = std::env::var("SOURCE_DATE_EPOCH").ok().and_then(|epoch| epoch.trim().parse().ok()) {
+        return epoch;
+    }
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0)
+}
+
+// The build.* variables available to every page.
+pub fn build_variables(timestamp: u64, time_format: &str) -> BTreeMap<String, String> {
+    let mut variables = BTreeMap::new();
+    variables.insert("build.time".to_string(), format_timestamp(timestamp, time_format));
+    variables.insert("build.year".to_string(), format_timestamp(timestamp, "%Y"));
+    variables
+}
+
+// A strftime subset in UTC: %Y, %m, %d, %H, %M, %S and %%.
+pub fn format_timestamp(timestamp: u64, format: &str) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let seconds = timestamp % 86_400;
+    let (year, month, day) = civil_from_days(days);
+    let mut result = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => result.push_str(&format!("{:04}", year)),
+            Some('m') => result.push_str(&format!("{:02}", month)),
+            Some('d') => result.push_str(&format!("{:02}", day)),
+            Some('H') => result.push_str(&format!("{:02}", seconds / 3600)),
+            Some('M') => result.push_str(&format!("{:02}", seconds / 60 % 60)),
+            Some('S') => result.push_str(&format!("{:02}", seconds % 60)),
+            Some('%') => result.push('%'),
+            Some(other) => {
+                result.push('%');
+                result.push(other);
+            }
+            None => result.push('%'),
+        }
+    }
+    result
+}
+
+// Howard Hinnant's days-to-civil conversion for the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_format_in_utc() {
+        assert_eq!(format_timestamp(1_700_000_000, DEFAULT_TIME_FORMAT), "2023-11-14T22:13:20Z");
+        assert_eq!(format_timestamp(951_782_400, "%d.%m.%Y 100%%"), "29.02.2000 100%");
+        assert_eq!(format_timestamp(0, "%Y %q"), "1970 %q");
+    }
+
+    #[test]
+    fn source_date_epoch_fixes_the_build_time() {
+        std::env::set_var("SOURCE_DATE_EPOCH", "1700000000");
+        let timestamp = build_timestamp();
+        std::env::remove_var("SOURCE_DATE_EPOCH");
+        assert_eq!(timestamp, 1_700_000_000);
+        let variables = build_variables(timestamp, "%Y-%m-%d");
+        assert_eq!(variables["build.time"], "2023-11-14");
+        assert_eq!(variables["build.year"], "2023");
+    }
+}